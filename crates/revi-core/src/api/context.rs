@@ -14,6 +14,9 @@ impl ContextRhaiApi {
         let mode = match str_mode.to_lowercase().as_str() {
             "insert" => Mode::Insert,
             "command" => Mode::Command,
+            "visual" => Mode::Visual,
+            "v-line" => Mode::VisualLine,
+            "v-block" => Mode::VisualBlock,
             _ => Mode::Normal,
         };
         // *self.0.panes[*self.0.focused_pane.borrow()].borrow_mut().mode = mode;
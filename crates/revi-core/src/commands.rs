@@ -198,6 +198,7 @@ build_command!(
             Mode::Command => (true, false),
             Mode::Normal => (false, true),
             Mode::Insert => (false, true),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => (false, true),
         };
         let mut bar = ctx.command_bar.borrow_mut();
         bar.set_focused(cmd_focused);
@@ -138,6 +138,7 @@ pub struct Mapper {
     nmaps: KeyMap,
     imaps: KeyMap,
     cmaps: KeyMap,
+    vmaps: KeyMap,
 }
 
 impl Default for Mapper {
@@ -152,6 +153,7 @@ impl Mapper {
             nmaps: KeyMap::new(),
             imaps: KeyMap::new(),
             cmaps: KeyMap::new(),
+            vmaps: KeyMap::new(),
         }
     }
 
@@ -160,6 +162,7 @@ impl Mapper {
             Mode::Normal => &self.nmaps,
             Mode::Insert => &self.imaps,
             Mode::Command => &self.cmaps,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => &self.vmaps,
         }
     }
 
@@ -168,6 +171,7 @@ impl Mapper {
             Mode::Normal => &mut self.nmaps,
             Mode::Insert => &mut self.imaps,
             Mode::Command => &mut self.cmaps,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => &mut self.vmaps,
         }
     }
 
@@ -5,6 +5,9 @@ pub enum Mode {
     Normal,
     Command,
     Insert,
+    Visual,
+    VisualLine,
+    VisualBlock,
 }
 
 impl fmt::Display for Mode {
@@ -13,6 +16,9 @@ impl fmt::Display for Mode {
             Self::Normal => "Normal",
             Self::Command => "Command",
             Self::Insert => "Insert",
+            Self::Visual => "Visual",
+            Self::VisualLine => "V-Line",
+            Self::VisualBlock => "V-Block",
         };
         write!(f, "{mode}")
     }
@@ -1,10 +1,10 @@
-use crate::{layout::Pos, subscription::Subscription, widget::BoxWidget};
+use crate::{layout::Pos, subscription::Subscription, task::Task, widget::BoxWidget};
 use crossterm::{cursor::SetCursorStyle, Result};
 pub trait App: Sized {
     type Settings: std::fmt::Debug;
-    type Message: std::fmt::Debug;
+    type Message: std::fmt::Debug + Send + 'static;
     fn new(settings: Self::Settings) -> Self;
-    fn update(&mut self, message: Self::Message) -> Option<Self::Message>;
+    fn update(&mut self, message: Self::Message) -> Task<Self::Message>;
     fn view(&self) -> BoxWidget;
     fn subscription(&self) -> Subscription<Self::Message> {
         Subscription::none()
@@ -1,6 +1,6 @@
+use crate::grid::Grid;
 use crate::layout::{Pos, Rect, Size, Stack};
 use crate::widget::{BoxWidget, Widget};
-use std::io::Stdout;
 #[derive(Debug, Default, Clone)]
 pub struct Container {
     pub bounds: Rect,
@@ -64,7 +64,7 @@ impl Widget for Container {
         self.bounds.height()
     }
 
-    fn draw(&self, stdout: &mut Stdout, bounds: Rect) {
+    fn draw(&self, grid: &mut Grid, bounds: Rect) {
         for (widget, mut wbounds) in self.children.iter().zip(generate_layout(
             bounds,
             self.bounds,
@@ -78,7 +78,7 @@ impl Widget for Container {
                 let y_offset = height.saturating_sub(content_height); // + (idx as u16);
                 wbounds.y += y_offset * self.vert_center as u16;
             }
-            widget.draw(stdout, wbounds);
+            widget.draw(grid, wbounds);
         }
     }
     fn debug_name(&self) -> String {
@@ -87,37 +87,36 @@ impl Widget for Container {
 }
 
 fn generate_layout(root: Rect, current: Rect, children: &[BoxWidget], stack: Stack) -> Vec<Rect> {
-    children.iter().fold(vec![], |mut acc, child| {
-        let last = acc.last().cloned().unwrap_or_default();
-        let x = match stack {
-            Stack::Vertically => current.x() + child.x() + root.x(),
-            Stack::Horizontally => current.x() + child.x() + last.width() + last.x() + root.x(),
-        };
-        let y = match stack {
-            Stack::Vertically => current.y() + child.y() + last.height() + last.y() + root.y(),
-            Stack::Horizontally => current.y() + child.y() + root.y(),
-        };
-        let width = match stack {
-            // NOTE: child should be priority if Shrinking is in effect
-            Stack::Vertically => child.width().max(current.width()).min(root.width()),
-            Stack::Horizontally => child
-                .width()
-                .min(current.width() - last.width())
-                .min(root.width()),
-        };
-        let height = match stack {
-            Stack::Vertically => child
-                .height()
-                .min(current.height() - last.height())
-                .min(root.height()),
-            Stack::Horizontally => child.height().min(current.height()).min(root.height()),
-        };
-        let size = Size::new(width, height);
-        let pos = Pos::new(x, y);
-        let rect = Rect::with_position(pos, size);
-        acc.push(rect);
-        acc
-    })
+    let x = current.x() + root.x();
+    let y = current.y() + root.y();
+    // How far along the stack the children so far reach.
+    let mut used = 0;
+    children
+        .iter()
+        .map(|child| match stack {
+            Stack::Vertically => {
+                let y = y + used + child.y();
+                // NOTE: child should be priority if Shrinking is in effect
+                let width = child.width().max(current.width()).min(root.width());
+                let height = child
+                    .height()
+                    .min(current.height().saturating_sub(used))
+                    .min(root.height());
+                used += child.y() + height;
+                Rect::with_position(Pos::new(x + child.x(), y), Size::new(width, height))
+            }
+            Stack::Horizontally => {
+                let x = x + used + child.x();
+                let width = child
+                    .width()
+                    .min(current.width().saturating_sub(used))
+                    .min(root.width());
+                let height = child.height().min(current.height()).min(root.height());
+                used += child.x() + width;
+                Rect::with_position(Pos::new(x, y + child.y()), Size::new(width, height))
+            }
+        })
+        .collect()
 }
 
 impl From<Container> for BoxWidget {
@@ -137,3 +136,41 @@ impl From<(Rect, Stack)> for Container {
         Self::new(rect, stack)
     }
 }
+
+#[test]
+fn nested_layout_offsets() {
+    use crate::text::Text;
+    let text = |width, height| BoxWidget::from(Text::new("a").max_width(width).max_height(height));
+    let root = Rect::with_position(Pos::new(2, 5), Size::new(20, 4));
+    let current = Rect::new(Size::new(20, 4));
+
+    let children = [text(4, 1), text(6, 1), text(30, 1)];
+    let rects = generate_layout(root, current, &children, Stack::Horizontally);
+    let xs = rects.iter().map(|r| (r.x, r.width)).collect::<Vec<_>>();
+    assert_eq!(xs, vec![(2, 4), (6, 6), (12, 10)]);
+
+    let children = [text(1, 1), text(1, 2), text(1, 3)];
+    let rects = generate_layout(root, current, &children, Stack::Vertically);
+    let ys = rects.iter().map(|r| (r.y, r.height)).collect::<Vec<_>>();
+    assert_eq!(ys, vec![(5, 1), (6, 2), (8, 1)]);
+}
+
+#[test]
+fn draws_into_grid() {
+    use crate::layout::Alignment;
+    use crate::text::Text;
+    let bar = Container::new(Rect::new(Size::new(12, 1)), Stack::Horizontally)
+        .push(Text::new("NORMAL").max_width(7))
+        .push(
+            Text::new("1/2")
+                .max_width(5)
+                .with_alignment(Alignment::Right),
+        );
+    let view = BoxWidget::from(
+        Container::new(Rect::new(Size::new(12, 2)), Stack::Vertically)
+            .push(Text::new("hello").max_width(12))
+            .push(bar),
+    );
+    let grid = view.to_grid();
+    assert_eq!(grid.lines(), vec!["hello       ", "NORMAL   1/2"]);
+}
@@ -0,0 +1,189 @@
+use crate::layout::Size;
+use crossterm::{
+    cursor::MoveTo,
+    style::{Attribute, ContentStyle, Print, ResetColor, SetAttribute, SetStyle},
+    QueueableCommand, Result,
+};
+use std::io::Write;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Screen columns `text` takes, a wide character takes two. Every grapheme takes
+/// at least one so controls and stray combining marks still get a cell.
+#[must_use]
+pub fn width(text: &str) -> usize {
+    text.graphemes(true)
+        .map(|symbol| symbol.width().max(1))
+        .sum()
+}
+
+/// One grapheme on the screen and how it looks, the cells a wide grapheme
+/// spills into are left with an empty symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    pub symbol: String,
+    pub style: ContentStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: " ".into(),
+            style: ContentStyle::new(),
+        }
+    }
+}
+
+/// A frame widgets draw into, the runtime only sends the cells that changed
+/// since the last one to the terminal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Grid {
+    size: Size,
+    cells: Vec<Cell>,
+}
+
+impl Grid {
+    pub fn new(size: Size) -> Self {
+        let len = size.width as usize * size.height as usize;
+        Self {
+            size,
+            cells: vec![Cell::default(); len],
+        }
+    }
+
+    #[must_use]
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    fn index(&self, x: u16, y: u16) -> Option<usize> {
+        (x < self.size.width && y < self.size.height)
+            .then_some(y as usize * self.size.width as usize + x as usize)
+    }
+
+    #[must_use]
+    pub fn get(&self, x: u16, y: u16) -> Option<&Cell> {
+        self.index(x, y).map(|idx| &self.cells[idx])
+    }
+
+    /// Cells off the grid are dropped.
+    pub fn set(&mut self, x: u16, y: u16, cell: Cell) {
+        if let Some(idx) = self.index(x, y) {
+            self.cells[idx] = cell;
+        }
+    }
+
+    /// Writes `text` from `x` rightwards, what runs off the edge is dropped and a
+    /// wide grapheme that would be cut in half is left blank.
+    pub fn put_str(&mut self, x: u16, y: u16, text: &str, style: ContentStyle) {
+        let mut x = x;
+        for symbol in text.graphemes(true) {
+            if x >= self.size.width {
+                break;
+            }
+            let span = width(symbol) as u16;
+            if x.saturating_add(span) > self.size.width {
+                self.set(x, y, Cell::default());
+                break;
+            }
+            // A control char would move the terminal's cursor, it's drawn blank.
+            let symbol = if symbol.chars().any(char::is_control) {
+                " ".to_string()
+            } else {
+                symbol.to_string()
+            };
+            self.set(x, y, Cell { symbol, style });
+            for x in x + 1..x + span {
+                let symbol = String::new();
+                self.set(x, y, Cell { symbol, style });
+            }
+            x += span;
+        }
+    }
+
+    /// The characters of row `y`, for tests to look at what was drawn.
+    #[must_use]
+    pub fn line(&self, y: u16) -> String {
+        (0..self.size.width)
+            .filter_map(|x| self.get(x, y))
+            .map(|cell| cell.symbol.as_str())
+            .collect()
+    }
+
+    #[must_use]
+    pub fn lines(&self) -> Vec<String> {
+        (0..self.size.height).map(|y| self.line(y)).collect()
+    }
+
+    /// Cells that differ from `previous`, every cell when the size changed.
+    pub fn diff<'a>(&'a self, previous: &'a Grid) -> impl Iterator<Item = (u16, u16, &'a Cell)> {
+        let resized = self.size != previous.size;
+        let width = self.size.width.max(1);
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(move |(idx, cell)| resized || previous.cells[*idx] != **cell)
+            .map(move |(idx, cell)| {
+                let (y, x) = (idx / width as usize, idx % width as usize);
+                (x as u16, y as u16, cell)
+            })
+    }
+
+    /// Queues what it takes to turn the screen showing `previous` into this grid.
+    pub fn draw_diff(&self, w: &mut impl Write, previous: &Grid) -> Result<()> {
+        let mut at = None;
+        let mut style = None;
+        for (x, y, cell) in self.diff(previous) {
+            // The wide grapheme before it already covers this cell.
+            if cell.symbol.is_empty() {
+                continue;
+            }
+            if at != Some((x, y)) {
+                w.queue(MoveTo(x, y))?;
+            }
+            if style != Some(cell.style) {
+                w.queue(SetAttribute(Attribute::Reset))?;
+                w.queue(SetStyle(cell.style))?;
+                style = Some(cell.style);
+            }
+            w.queue(Print(&cell.symbol))?;
+            at = Some((x + width(&cell.symbol) as u16, y));
+        }
+        if style.is_some() {
+            w.queue(ResetColor)?;
+            w.queue(SetAttribute(Attribute::Reset))?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn only_changes_are_drawn() {
+    let mut previous = Grid::new(Size::new(4, 2));
+    previous.put_str(0, 0, "abcdef", ContentStyle::new());
+    assert_eq!(previous.lines(), vec!["abcd", "    "]);
+
+    let mut grid = previous.clone();
+    grid.put_str(1, 1, "xy", ContentStyle::new());
+    grid.set(3, 0, Cell::default());
+    let changed = grid
+        .diff(&previous)
+        .map(|(x, y, cell)| (x, y, cell.symbol.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(changed, vec![(3, 0, " "), (1, 1, "x"), (2, 1, "y")]);
+
+    let mut out = Vec::new();
+    grid.draw_diff(&mut out, &grid).unwrap();
+    assert!(out.is_empty());
+    assert_eq!(grid.diff(&Grid::default()).count(), 8);
+}
+
+#[test]
+fn wide_graphemes() {
+    assert_eq!(width("a\u{301}中🎉"), 5);
+    let mut grid = Grid::new(Size::new(5, 2));
+    grid.put_str(0, 0, "e\u{301}中x", ContentStyle::new());
+    grid.put_str(2, 1, "ab中", ContentStyle::new());
+    assert_eq!(grid.lines(), vec!["e\u{301}中x ", "  ab "]);
+    assert_eq!(grid.get(2, 0).map(|cell| cell.symbol.as_str()), Some(""));
+}
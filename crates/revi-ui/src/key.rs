@@ -222,7 +222,7 @@ impl From<&str> for Key {
             "alt" => Key::Alt,
             "space" => Key::Space,
             "esc" => Key::Esc,
-            "enter" => Key::Enter,
+            "enter" | "cr" | "return" => Key::Enter,
             "backspace" => Key::Backspace,
             "left" => Key::Left,
             "right" => Key::Right,
@@ -236,6 +236,7 @@ impl From<&str> for Key {
             "backtab" => Key::BackTab,
             "delete" => Key::Delete,
             "insert" => Key::Insert,
+            "lt" => Key::Char('<'),
             "f1" => Self::from(1),
             "f2" => Self::from(2),
             "f3" => Self::from(3),
@@ -249,10 +250,11 @@ impl From<&str> for Key {
             "f11" => Self::from(11),
             "f12" => Self::from(12),
             _ => {
-                if c.len() == 1 {
-                    return Self::from(c.chars().collect::<Vec<_>>()[0]);
+                let mut chars = c.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Self::from(c),
+                    _ => Key::Null,
                 }
-                Key::Null
             }
         }
     }
@@ -485,6 +487,8 @@ fn test_from_crossterm_key_to_revi_key_upper_a() {
 fn string_to_keys_parse() {
     assert_ne!(string_to_keys("A"), string_to_keys("a"));
     assert_ne!(string_to_keys("B"), string_to_keys("b"));
+    assert_eq!(string_to_keys("<lt>"), vec![Keys::Key(Key::Char('<'))]);
+    assert_eq!(string_to_keys("中"), vec![Keys::Key(Key::Char('中'))]);
 }
 
 #[macro_export]
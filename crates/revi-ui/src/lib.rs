@@ -1,9 +1,11 @@
 pub mod application;
 pub mod container;
+pub mod grid;
 mod key;
 pub mod layout;
 pub mod runtime;
 pub mod subscription;
+pub mod task;
 pub mod text;
 pub mod widget;
 
@@ -12,7 +14,8 @@ pub use crossterm::{cursor::SetCursorStyle, event, style, style::Attribute, styl
 pub use key::string_to_keys;
 pub use key::Key;
 pub use key::Keys;
-pub use subscription::{Command, Subscription};
+pub use subscription::{Command, Subscription, Tick, Timer};
+pub use task::Task;
 
 use layout::Size;
 
@@ -1,50 +1,51 @@
-use crate::{
-    application::App,
-    layout::{Pos, Rect, Size},
-};
+use crate::{application::App, grid::Grid, layout::Pos, subscription::Clock};
 use crossterm::{
     cursor::{Hide, MoveTo, RestorePosition, SavePosition, Show},
     event,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
     QueueableCommand, Result,
 };
 use std::io::Stdout;
-use std::{io::Write, time::Duration};
+use std::sync::mpsc::{self, Sender};
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
 
-fn render_app<A>(w: &mut Stdout, app: &mut A) -> Result<()>
+const POLL: Duration = Duration::from_millis(50);
+
+fn render_app<A>(w: &mut Stdout, app: &mut A, previous: &mut Grid) -> Result<()>
 where
     A: App,
 {
     w.queue(Hide)?;
+    let grid = app.view().to_grid();
+    if grid.size() != previous.size() {
+        w.queue(Clear(ClearType::All))?;
+    }
+    grid.draw_diff(w, previous)?;
     if let Some(Pos { x, y }) = app.cursor_pos() {
         w.queue(MoveTo(x, y))?;
     }
     if let Some(cs) = app.cursor_shape() {
         w.queue(cs)?;
-    }
-    let widgets = app.view();
-    let width = widgets.width();
-    let height = widgets.height();
-    let app_size = Size { width, height };
-    let app_pos = Pos { x: 0, y: 0 };
-    w.queue(SavePosition)?;
-    widgets.draw(w, Rect::with_position(app_pos, app_size));
-    w.queue(RestorePosition)?;
-    if app.cursor_shape().is_some() {
         w.queue(Show)?;
     }
     w.flush()?;
+    *previous = grid;
     Ok(())
 }
 
-fn update<A>(app: &mut A, message: A::Message)
+fn update<A>(app: &mut A, message: A::Message, sender: &Sender<A::Message>)
 where
     A: App,
 {
-    let Some(message) = app.update(message) else {
-        return;
-    };
-    update(app, message);
+    for message in app.update(message).start(sender) {
+        update(app, message, sender);
+    }
 }
 
 pub fn run<A>(app: &mut A) -> Result<()>
@@ -57,19 +58,51 @@ where
     writer.queue(Hide)?;
     enable_raw_mode()?;
     writer.flush()?;
+    // What the terminal shows, each render only sends what changed.
+    let mut frame = Grid::default();
+    // Work from `Task::perform` sends its message back on this.
+    let (sender, receiver) = mpsc::channel();
     let mut subscriptions = app.subscription();
-    render_app(&mut writer, app)?;
+    let mut clock = Clock::default();
+    clock.sync(&subscriptions, Instant::now());
+    // `after` timers count from the last event or timer, ticks don't restart them.
+    let mut since = Instant::now();
+    render_app(&mut writer, app, &mut frame)?;
     while app.quit() {
-        if event::poll(Duration::from_millis(50)).unwrap_or(false) {
+        let wait = [
+            subscriptions
+                .next_timer()
+                .map(|delay| delay.saturating_sub(since.elapsed())),
+            clock.next_tick(Instant::now()),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(POLL, Duration::min);
+        let (restart, mut messages) = if event::poll(wait).unwrap_or(false) {
             let event = event::read()?;
-            for sub in subscriptions.0.iter() {
-                let Some(message) = sub(event.clone()) else {
-                    continue;
-                };
-                update(app, message);
-            }
-            render_app(&mut writer, app)?;
-            subscriptions = app.subscription();
+            let messages = subscriptions
+                .events
+                .iter()
+                .filter_map(|sub| sub(event.clone()))
+                .collect::<Vec<_>>();
+            (true, messages)
+        } else {
+            let messages = subscriptions.take_due(since.elapsed());
+            (!messages.is_empty(), messages)
+        };
+        messages.extend(clock.take_due(&subscriptions, Instant::now()));
+        messages.extend(receiver.try_iter());
+        if !restart && messages.is_empty() {
+            continue;
+        }
+        for message in messages {
+            update(app, message, &sender);
+        }
+        render_app(&mut writer, app, &mut frame)?;
+        subscriptions = app.subscription();
+        clock.sync(&subscriptions, Instant::now());
+        if restart {
+            since = Instant::now();
         }
     }
 
@@ -1,16 +1,149 @@
 use super::event::Event;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 pub type Command<T> = Box<dyn Fn(Event) -> Option<T>>;
 
-pub struct Subscription<T>(pub Vec<Command<T>>);
+/// A message the runtime sends once `delay` has gone by without an event.
+pub struct Timer<T> {
+    pub delay: Duration,
+    pub message: T,
+}
+
+/// A message the runtime sends every `interval`, events don't hold it back.
+pub struct Tick<T> {
+    pub interval: Duration,
+    message: Box<dyn Fn() -> T>,
+}
+
+pub struct Subscription<T> {
+    pub events: Vec<Command<T>>,
+    pub timers: Vec<Timer<T>>,
+    pub ticks: Vec<Tick<T>>,
+}
 
 impl<T> Subscription<T> {
     pub fn none() -> Self {
-        Self(vec![])
+        Self {
+            events: vec![],
+            timers: vec![],
+            ticks: vec![],
+        }
     }
 
     pub fn push(mut self, func: impl Fn(Event) -> Option<T> + 'static) -> Self {
-        self.0.push(Box::new(func));
+        self.events.push(Box::new(func));
+        self
+    }
+
+    /// Sends `message` after `delay`, the wait starts over on every event
+    /// since subscriptions are asked for again after each one.
+    pub fn after(mut self, delay: Duration, message: T) -> Self {
+        self.timers.push(Timer { delay, message });
         self
     }
+
+    /// Sends `message` every `interval` for as long as the app keeps asking for it.
+    pub fn every(mut self, interval: Duration, message: T) -> Self
+    where
+        T: Clone + 'static,
+    {
+        self.ticks.push(Tick {
+            interval,
+            message: Box::new(move || message.clone()),
+        });
+        self
+    }
+
+    /// How long from the start of the wait until the first timer is due.
+    #[must_use]
+    pub fn next_timer(&self) -> Option<Duration> {
+        self.timers.iter().map(|timer| timer.delay).min()
+    }
+
+    /// Takes the messages of every timer due once `elapsed` has gone by.
+    pub fn take_due(&mut self, elapsed: Duration) -> Vec<T> {
+        let (due, waiting) = std::mem::take(&mut self.timers)
+            .into_iter()
+            .partition::<Vec<_>, _>(|timer| timer.delay <= elapsed);
+        self.timers = waiting;
+        due.into_iter().map(|timer| timer.message).collect()
+    }
+}
+
+/// When each `every` interval last ticked, the runtime keeps this across
+/// subscriptions so a tick keeps its pace while events come in.
+#[derive(Debug, Default)]
+pub struct Clock {
+    last: HashMap<Duration, Instant>,
+}
+
+impl Clock {
+    /// Starts the intervals new to `sub` and forgets the ones it dropped.
+    pub fn sync<T>(&mut self, sub: &Subscription<T>, now: Instant) {
+        self.last
+            .retain(|interval, _| sub.ticks.iter().any(|tick| tick.interval == *interval));
+        for tick in &sub.ticks {
+            self.last.entry(tick.interval).or_insert(now);
+        }
+    }
+
+    /// How long from `now` until the next tick is due.
+    #[must_use]
+    pub fn next_tick(&self, now: Instant) -> Option<Duration> {
+        self.last
+            .iter()
+            .map(|(interval, last)| (*last + *interval).saturating_duration_since(now))
+            .min()
+    }
+
+    /// Takes the messages of every tick due at `now` and starts their intervals over.
+    pub fn take_due<T>(&mut self, sub: &Subscription<T>, now: Instant) -> Vec<T> {
+        let due = self
+            .last
+            .iter()
+            .filter(|(interval, last)| now.saturating_duration_since(**last) >= **interval)
+            .map(|(interval, _)| *interval)
+            .collect::<Vec<_>>();
+        for interval in &due {
+            self.last.insert(*interval, now);
+        }
+        sub.ticks
+            .iter()
+            .filter(|tick| due.contains(&tick.interval))
+            .map(|tick| (tick.message)())
+            .collect()
+    }
+}
+
+#[test]
+fn timers_come_due_once() {
+    let mut sub = Subscription::none()
+        .after(Duration::from_millis(20), 2)
+        .after(Duration::from_millis(10), 1);
+    assert_eq!(sub.next_timer(), Some(Duration::from_millis(10)));
+    assert!(sub.take_due(Duration::from_millis(5)).is_empty());
+    assert_eq!(sub.take_due(Duration::from_millis(10)), vec![1]);
+    assert_eq!(sub.take_due(Duration::from_millis(30)), vec![2]);
+    assert_eq!(sub.next_timer(), None);
+}
+
+#[test]
+fn ticks_repeat() {
+    let start = Instant::now();
+    let ms = Duration::from_millis;
+    let sub = Subscription::none().every(ms(10), 'a').every(ms(25), 'b');
+    let mut clock = Clock::default();
+    clock.sync(&sub, start);
+    assert_eq!(clock.next_tick(start), Some(ms(10)));
+    assert!(clock.take_due(&sub, start + ms(5)).is_empty());
+    assert_eq!(clock.take_due(&sub, start + ms(10)), vec!['a']);
+    assert_eq!(clock.take_due(&sub, start + ms(20)), vec!['a']);
+    assert_eq!(clock.take_due(&sub, start + ms(30)), vec!['a', 'b']);
+    assert_eq!(clock.next_tick(start + ms(30)), Some(ms(10)));
+
+    // Asking again keeps the pace, dropping a tick forgets it.
+    let sub = Subscription::none().every(ms(25), 'b');
+    clock.sync(&sub, start + ms(40));
+    assert_eq!(clock.next_tick(start + ms(40)), Some(ms(15)));
 }
@@ -0,0 +1,77 @@
+use std::sync::mpsc::Sender;
+use std::thread;
+
+/// What `App::update` asks the runtime to do after a message.
+pub enum Task<T> {
+    None,
+    /// Handled right away, before the next event.
+    Message(T),
+    /// Runs on a worker thread, the message it makes comes back through the event loop.
+    Perform(Box<dyn FnOnce() -> T + Send>),
+    Batch(Vec<Task<T>>),
+}
+
+impl<T> Task<T> {
+    pub fn none() -> Self {
+        Self::None
+    }
+
+    pub fn perform(work: impl FnOnce() -> T + Send + 'static) -> Self {
+        Self::Perform(Box::new(work))
+    }
+
+    pub fn batch(tasks: impl IntoIterator<Item = Task<T>>) -> Self {
+        Self::Batch(tasks.into_iter().collect())
+    }
+}
+
+impl<T: Send + 'static> Task<T> {
+    /// Starts the work on worker threads that send their message on `sender`,
+    /// hands back the messages that are ready now.
+    pub fn start(self, sender: &Sender<T>) -> Vec<T> {
+        match self {
+            Self::None => vec![],
+            Self::Message(message) => vec![message],
+            Self::Perform(work) => {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    // The runtime is gone once the app quits, nothing is left to tell.
+                    let _ = sender.send(work());
+                });
+                vec![]
+            }
+            Self::Batch(tasks) => tasks
+                .into_iter()
+                .flat_map(|task| task.start(sender))
+                .collect(),
+        }
+    }
+}
+
+impl<T> From<Option<T>> for Task<T> {
+    fn from(message: Option<T>) -> Self {
+        message.map_or(Self::None, Self::Message)
+    }
+}
+
+impl<T> std::fmt::Debug for Task<T>
+where
+    T: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            Self::Message(message) => f.debug_tuple("Message").field(message).finish(),
+            Self::Perform(_) => write!(f, "Perform(..)"),
+            Self::Batch(tasks) => f.debug_tuple("Batch").field(tasks).finish(),
+        }
+    }
+}
+
+#[test]
+fn tasks_start() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let task = Task::batch([Task::perform(|| 2), Task::from(Some(1)), Task::from(None)]);
+    assert_eq!(task.start(&sender), vec![1]);
+    assert_eq!(receiver.recv(), Ok(2));
+}
@@ -1,8 +1,8 @@
+use crate::grid::{self, Grid};
 use crate::layout::{Alignment, Rect};
 use crate::widget::Widget;
-use crossterm::style::{Attribute, Color, ContentStyle, ResetColor, SetAttribute, SetStyle};
-use crossterm::{cursor, queue, style};
-use std::io::Stdout;
+use crossterm::style::{self, Color, ContentStyle};
+use unicode_segmentation::UnicodeSegmentation;
 #[derive(Debug, Default, Clone)]
 pub struct Text {
     content: String,
@@ -16,7 +16,7 @@ pub struct Text {
 impl Text {
     pub fn new(content: &str) -> Self {
         let content = content.replace('\n', " ");
-        let width = content.chars().max().unwrap_or_default() as u16;
+        let width = grid::width(&content) as u16;
         let height = content.lines().count() as u16;
         Self {
             content,
@@ -28,8 +28,9 @@ impl Text {
         }
     }
 
+    /// Screen columns the text takes, at least as many as it was given.
     pub fn char_len(&self) -> usize {
-        self.content.len().max(self.width as usize)
+        grid::width(&self.content).max(self.width as usize)
     }
 
     pub fn with_alignment(mut self, align: Alignment) -> Self {
@@ -97,34 +98,40 @@ impl Widget for Text {
         self.height
     }
 
-    fn draw(&self, stdout: &mut Stdout, bounds: Rect) {
-        queue!(stdout, SetStyle(self.style)).expect("failed to set style");
+    fn draw(&self, grid: &mut Grid, bounds: Rect) {
         let width = bounds.width() as usize;
         let x = bounds.x() + self.x();
         let y = bounds.y() + self.y();
-        queue!(
-            stdout,
-            cursor::MoveTo(x, y),
-            style::Print(format_line(&self.content, width, &self.align)),
-        )
-        .expect("Failed to queue Text");
-        queue!(stdout, ResetColor, SetAttribute(Attribute::Reset))
-            .expect("failed to queue reset color and  attribute");
+        grid.put_str(
+            x,
+            y,
+            &format_line(&self.content, width, &self.align),
+            self.style,
+        );
     }
     fn debug_name(&self) -> String {
         self.comment.clone().unwrap_or_default()
     }
 }
 
+/// Pads or cuts `line` to `width` screen columns, a wide grapheme that doesn't
+/// fit is cut as a whole.
 fn format_line(line: &str, width: usize, align: &Alignment) -> String {
-    match align {
-        Alignment::Left => format!("{:<width$}", line),
-        Alignment::Right => format!("{:>width$}", line),
-        Alignment::Center => format!("{:^width$}", line),
-    }
-    .chars()
-    .take(width)
-    .collect()
+    let mut used = 0;
+    let line = line
+        .graphemes(true)
+        .take_while(|symbol| {
+            used += grid::width(symbol);
+            used <= width
+        })
+        .collect::<String>();
+    let pad = width.saturating_sub(grid::width(&line));
+    let (left, right) = match align {
+        Alignment::Left => (0, pad),
+        Alignment::Right => (pad, 0),
+        Alignment::Center => (pad / 2, pad - pad / 2),
+    };
+    format!("{}{line}{}", " ".repeat(left), " ".repeat(right))
 }
 
 #[test]
@@ -136,4 +143,8 @@ fn test_format_line() {
     assert_eq!(default, "               hello".to_string());
     let default = format_line("hello", 3, &Alignment::Left);
     assert_eq!(default, "hel".to_string());
+    let wide = format_line("中文字", 5, &Alignment::Left);
+    assert_eq!(wide, "中文 ".to_string());
+    let wide = format_line("中", 4, &Alignment::Right);
+    assert_eq!(wide, "  中".to_string());
 }
@@ -1,11 +1,14 @@
-use crate::{layout::Rect, text::Text};
-use std::io::Stdout;
+use crate::{
+    grid::Grid,
+    layout::{Rect, Size},
+    text::Text,
+};
 pub trait Widget: std::fmt::Debug + dyn_clone::DynClone {
     fn x(&self) -> u16;
     fn y(&self) -> u16;
     fn width(&self) -> u16;
     fn height(&self) -> u16;
-    fn draw(&self, stdout: &mut Stdout, bounds: Rect);
+    fn draw(&self, grid: &mut Grid, bounds: Rect);
     fn debug_name(&self) -> String {
         "DEFAULT".to_string()
     }
@@ -48,8 +51,17 @@ impl BoxWidget {
         self.widget.height()
     }
 
-    pub fn draw(&self, stdout: &mut Stdout, bounds: Rect) {
-        self.widget.draw(stdout, bounds);
+    pub fn draw(&self, grid: &mut Grid, bounds: Rect) {
+        self.widget.draw(grid, bounds);
+    }
+
+    /// Draws into a grid of the widget's own size, what the runtime would show.
+    #[must_use]
+    pub fn to_grid(&self) -> Grid {
+        let size = Size::new(self.width(), self.height());
+        let mut grid = Grid::new(size);
+        self.draw(&mut grid, Rect::new(size));
+        grid
     }
 
     #[must_use]
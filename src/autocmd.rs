@@ -0,0 +1,149 @@
+use std::str::FromStr;
+
+use rhai::FnPtr;
+
+/// Things scripts can hook into with `revi.autocmd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Editing a file that doesn't exist yet.
+    BufNewFile,
+    /// After a file is read into a new buffer.
+    BufReadPost,
+    BufWritePre,
+    BufWritePost,
+    InsertEnter,
+    InsertLeave,
+    /// Any mode change, its pattern is matched against `old:new`, `normal:insert`.
+    ModeChanged,
+    /// The cursor moved outside of insert mode.
+    CursorMoved,
+    VimResized,
+    /// Just before the editor quits.
+    VimLeavePre,
+}
+
+const EVENTS: &[(&str, Event)] = &[
+    ("BufNewFile", Event::BufNewFile),
+    ("BufReadPost", Event::BufReadPost),
+    ("BufRead", Event::BufReadPost),
+    ("BufWritePre", Event::BufWritePre),
+    ("BufWrite", Event::BufWritePre),
+    ("BufWritePost", Event::BufWritePost),
+    ("InsertEnter", Event::InsertEnter),
+    ("InsertLeave", Event::InsertLeave),
+    ("ModeChanged", Event::ModeChanged),
+    ("CursorMoved", Event::CursorMoved),
+    ("VimResized", Event::VimResized),
+    ("VimLeavePre", Event::VimLeavePre),
+];
+
+impl FromStr for Event {
+    type Err = String;
+
+    /// Event names ignore case like Vim's, `bufread` is `BufReadPost`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        EVENTS
+            .iter()
+            .find(|(event, _)| event.eq_ignore_ascii_case(name.trim()))
+            .map(|(_, event)| *event)
+            .ok_or(format!("No such event: {name}"))
+    }
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, _) = EVENTS
+            .iter()
+            .find(|(_, event)| event == self)
+            .expect("every event has a name");
+        write!(f, "{name}")
+    }
+}
+
+/// A script function to call when `event` fires on something `pattern` matches.
+#[derive(Debug, Clone)]
+pub struct AutoCmd {
+    pub event: Event,
+    pub pattern: String,
+    pub func: FnPtr,
+}
+
+impl AutoCmd {
+    /// Whether this runs for `event` on `target`, a file name or `old:new` modes.
+    pub fn matches(&self, event: Event, target: &str) -> bool {
+        self.event == event && pattern_match(&self.pattern, target)
+    }
+}
+
+/// Autocommand patterns, `*.rs,*.toml` matches either and a pattern without a
+/// `/` only looks at the last part of a path.
+pub fn pattern_match(pattern: &str, target: &str) -> bool {
+    split_top_level(pattern).into_iter().any(|pattern| {
+        let target = if pattern.contains('/') {
+            target
+        } else {
+            target.rsplit('/').next().unwrap_or(target)
+        };
+        glob(pattern, target)
+    })
+}
+
+/// Splits on the commas that aren't inside `{}`.
+fn split_top_level(pattern: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in pattern.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&pattern[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&pattern[start..]);
+    parts
+}
+
+/// `*` matches any run of chars, `?` any one and `{a,b}` either word.
+pub fn glob(pattern: &str, text: &str) -> bool {
+    if let Some(open) = pattern.find('{') {
+        if let Some(close) = pattern[open..].find('}').map(|i| open + i) {
+            let (head, tail) = (&pattern[..open], &pattern[close + 1..]);
+            return split_top_level(&pattern[open + 1..close])
+                .into_iter()
+                .any(|alt| glob(&format!("{head}{alt}{tail}"), text));
+        }
+    }
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    wildcard(&pattern, &text)
+}
+
+fn wildcard(pattern: &[char], text: &[char]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, _) => text.is_empty(),
+        (Some('*'), _) => (0..=text.len()).any(|skip| wildcard(&pattern[1..], &text[skip..])),
+        (Some('?'), Some(_)) => wildcard(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => wildcard(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+#[test]
+fn patterns() {
+    assert!(pattern_match("*", ""));
+    assert!(pattern_match("*.rs", "src/main.rs"));
+    assert!(!pattern_match("*.rs", "Cargo.toml"));
+    assert!(pattern_match("*.rs,*.toml", "Cargo.toml"));
+    assert!(pattern_match("*.{rs,toml}", "Cargo.toml"));
+    assert!(pattern_match("src/*.rs", "src/main.rs"));
+    assert!(!pattern_match("src/*.rs", "main.rs"));
+    assert!(pattern_match("?ain.rs", "main.rs"));
+    assert!(pattern_match("normal:*", "normal:insert"));
+    assert_eq!("bufread".parse(), Ok(Event::BufReadPost));
+    assert!("BufNope".parse::<Event>().is_err());
+}
@@ -1,56 +1,50 @@
-use std::{fs::OpenOptions, io::BufWriter};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufWriter, Write},
+};
 
+use regex::Regex;
 use revi_ui::layout::{Pos, Size};
 use ropey::Rope;
 
-#[derive(Debug, Clone, Copy, Default)]
+use crate::encoding::{self, Encoding, Format};
+use crate::ex::LineRange;
+use crate::grapheme;
+use crate::motion::{self, Motion, Span};
+use crate::options::{LocalOptions, Value};
+use crate::search;
+use crate::substitute::{self, Replace};
+use crate::undo::{Edit, History};
+use crate::wrap::{ScreenLine, Wrap};
+use crate::Mode;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
 pub struct Cursor {
     pub pos: Pos,
     pub scroll: Pos,
+    /// Rows of the top line a wrapped window scrolls past, for a line taller
+    /// than the window.
+    pub skip: usize,
+    /// Buffer row and column the visual selection started from.
+    pub anchor: Option<Pos>,
+    /// Screen column `j` and `k` keep to, where the cursor last moved sideways to.
     max: Pos,
 }
 
 impl Cursor {
-    pub fn up(&mut self, line_len: usize) {
-        let max = self.max.x as usize;
-        let col = self.pos.x as usize;
-        let col = col.max(max).min(line_len);
-        self.set_col(col);
-        self.sub_row(1);
-    }
-
-    pub fn down(&mut self, line_len: usize) {
-        let max = self.max.x as usize;
-        let col = self.pos.x as usize;
-        let col = col.max(max).min(line_len);
-        self.set_col(col);
-        self.add_row(1);
-    }
-
-    pub fn left(&mut self) {
-        self.sub_col_effect_max(1);
-    }
-
-    pub fn right(&mut self) {
-        self.add_col_effect_max(1);
-    }
-
     pub fn scroll_up(&mut self) {
         self.scroll.y = self.scroll.y.saturating_sub(1);
+        self.skip = 0;
     }
 
     pub fn scroll_down(&mut self, max: usize) {
         let max = max as u16;
         self.scroll.y = self.scroll.y.saturating_add(1).min(max);
-    }
-
-    pub fn scroll_left(&mut self) {
-        self.scroll.x = self.scroll.x.saturating_sub(1);
-    }
-
-    pub fn scroll_right(&mut self, max: usize) {
-        let max = max as u16;
-        self.scroll.x = self.scroll.x.saturating_add(1).min(max);
+        self.skip = 0;
     }
 
     pub fn pos(&self) -> Pos {
@@ -59,6 +53,18 @@ impl Cursor {
         Pos { x, y }
     }
 
+    /// Selected rows and columns, `(top left, bottom right)` both inclusive.
+    pub fn selection(&self) -> Option<(Pos, Pos)> {
+        let anchor = self.anchor?;
+        let pos = self.pos();
+        let (start, end) = if (anchor.y, anchor.x) <= (pos.y, pos.x) {
+            (anchor, pos)
+        } else {
+            (pos, anchor)
+        };
+        Some((start, end))
+    }
+
     pub fn new_line(&mut self) {
         self.set_col(0);
         self.add_row(1);
@@ -103,76 +109,281 @@ impl Cursor {
     //     self.pos.x as usize
     // }
 
-    pub fn add_col_effect_max(&mut self, col: usize) {
-        let col = col as u16;
-        self.pos.x = self.pos.x.saturating_add(col);
-        self.max.x = self.pos.x.max(self.max.x);
-    }
-
     pub fn add_col(&mut self, col: usize) {
         let col = col as u16;
         self.pos.x = self.pos.x.saturating_add(col);
     }
 
-    pub fn sub_col_effect_max(&mut self, col: usize) {
-        let col = col as u16;
-        self.pos.x = self.pos.x.saturating_sub(col);
-        self.max.x = self.pos.x;
-    }
-
     pub fn sub_col(&mut self, col: usize) {
         let col = col as u16;
         self.pos.x = self.pos.x.saturating_sub(col);
-        self.max.x = self.pos.x;
     }
 
-    pub fn set_col_effect(&mut self, col: usize) {
+    pub fn set_col(&mut self, col: usize) {
         let col = col as u16;
         self.pos.x = col;
-        self.max.x = self.pos.x.min(self.max.x);
     }
 
-    pub fn set_col(&mut self, col: usize) {
-        let col = col as u16;
-        self.pos.x = col;
+    /// Scrolls so `margin` rows stay on screen above and below the cursor, as far
+    /// as the buffer's `last` row allows.
+    pub fn keep_margin(&mut self, margin: usize, height: usize, last: usize) {
+        if height == 0 {
+            return;
+        }
+        let margin = margin.min(height.saturating_sub(1) / 2);
+        let row = self.row_scroll();
+        let top = self.scroll.y as usize;
+        let top = if row < top + margin {
+            row.saturating_sub(margin)
+        } else if row + margin >= top + height {
+            let bottom = (last + 1).saturating_sub(height).max(top);
+            (row + margin + 1 - height).min(bottom)
+        } else {
+            return;
+        };
+        self.scroll.y = top as u16;
+        self.pos.y = (row - top) as u16;
+    }
+
+    /// Moves to `row`/`col` of the buffer, scrolling just enough to keep it inside `size`.
+    /// `line` is the text of `row`, a wide grapheme or a tab is kept on screen as a whole.
+    pub fn jump(&mut self, row: usize, col: usize, line: &str, tabstop: usize, size: Size) {
+        let row = row as u16;
+        if row < self.scroll.y {
+            self.scroll.y = row;
+        } else if row >= self.scroll.y + size.height {
+            self.scroll.y = (row + 1).saturating_sub(size.height.max(1));
+        }
+        let left = grapheme::scroll_for(
+            line,
+            col,
+            self.scroll.x as usize,
+            size.width.max(1) as usize,
+            tabstop,
+        );
+        self.scroll.x = left as u16;
+        self.pos = Pos::new((col - left) as u16, row - self.scroll.y);
+        self.max.x = grapheme::display_col(line, col, tabstop) as u16;
     }
 }
 
+/// Options that change the bytes a write makes.
+const FILE_OPTIONS: [&str; 3] = ["fileformat", "fileencoding", "bomb"];
+
 #[derive(Debug, Clone)]
 pub struct Buffer {
     pub name: String,
     rope: Rope,
     cursor: Cursor,
+    history: History,
+    marks: BTreeMap<char, Pos>,
+    /// Buffer local options, `tabstop` and `shiftwidth`.
+    pub options: LocalOptions,
+    /// History position the file was last read or written at.
+    saved: usize,
+    /// Why the file couldn't be read as it is, shown once the buffer is loaded.
+    pub read_error: Option<String>,
+    /// [`FILE_OPTIONS`] as the file has them, setting one another way is a change.
+    written: Option<Vec<Value>>,
 }
 
 impl Buffer {
-    pub fn from_path(path: &str) -> Self {
-        let src = std::fs::read_to_string(path).unwrap_or_default();
+    /// Reads `path` as `encoding`, guessed from the file when `None`. A file that
+    /// can't be read or decoded opens `readonly` so writing can't lose what's in it.
+    pub fn from_path(path: &str, encoding: Option<Encoding>) -> Self {
+        let mut options = LocalOptions::default();
+        let mut read_error = None;
+        if let Some(encoding) = encoding {
+            options.set("fileencoding", Value::Str(Cow::Borrowed(encoding.name())));
+        }
+        let src = match std::fs::read(path) {
+            Ok(bytes) => match encoding::decode(&bytes, encoding) {
+                Ok(decoded) => {
+                    let name = decoded.encoding.name();
+                    options.set("fileencoding", Value::Str(Cow::Borrowed(name)));
+                    options.set("bomb", Value::Bool(decoded.bom));
+                    decoded.text
+                }
+                Err(err) => {
+                    read_error = Some(format!("\"{path}\" {err}, opened readonly"));
+                    String::from_utf8_lossy(&bytes).into_owned()
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                read_error = Some(format!("\"{path}\" {err}, opened readonly"));
+                String::new()
+            }
+        };
+        if read_error.is_some() {
+            options.set("readonly", Value::Bool(true));
+        }
+        let format = Format::detect(&src);
+        if !src.is_empty() {
+            options.set("fileformat", Value::Str(Cow::Borrowed(format.name())));
+        }
+        let src = format.to_buffer(src);
         Self {
             name: path.into(),
             rope: Rope::from_str(&src),
             cursor: Cursor::default(),
+            history: History::default(),
+            marks: BTreeMap::new(),
+            options,
+            saved: 0,
+            read_error,
+            written: None,
         }
     }
 
-    pub fn save(&self, filename: Option<String>) -> std::io::Result<()> {
+    /// Puts the options reading the file set over `defaults`, the buffer starts
+    /// out unmodified with them.
+    pub fn set_option_defaults(&mut self, defaults: LocalOptions) {
+        let read = std::mem::replace(&mut self.options, defaults);
+        self.options.extend(read);
+        self.written = Some(self.file_options());
+    }
+
+    fn file_options(&self) -> Vec<Value> {
+        FILE_OPTIONS
+            .iter()
+            .map(|name| self.options.get_or_default(name).clone())
+            .collect()
+    }
+
+    /// Reads the file again as `encoding`, its `fileencoding` when `None`. The
+    /// text and undo history are replaced and so are the options the file
+    /// decides, the rest stay as they were set.
+    pub fn reload(&mut self, encoding: Option<Encoding>) {
+        let encoding = encoding
+            .or_else(|| Encoding::from_name(self.options.get_or_default("fileencoding").as_str()));
+        let read = Self::from_path(&self.name, encoding);
+        let readonly = read.options.get_or_default("readonly").clone();
+        self.options.set("readonly", readonly);
+        for name in FILE_OPTIONS {
+            if let Some(value) = read.options.get(name) {
+                self.options.set(name, value.clone());
+            }
+        }
+        self.rope = read.rope;
+        self.history = History::default();
+        self.saved = 0;
+        self.read_error = read.read_error;
+        self.written = Some(self.file_options());
+    }
+
+    /// Writes the buffer in its `fileencoding`, `force` is `:w!` and writes a
+    /// `readonly` buffer over its file anyway.
+    pub fn save(&mut self, filename: Option<String>, force: bool) -> io::Result<()> {
         let name = match filename {
             Some(ref n) => n,
             None => &self.name,
         };
+        self.check_readonly(name, force)?;
+        let bytes = self.encode(0..self.rope.len_chars())?;
         let file = OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
             .open(name)?;
-        self.rope.write_to(BufWriter::new(file))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+        if self.is_own_file(name) {
+            self.saved = self.history.position();
+            self.written = Some(self.file_options());
+        }
+        Ok(())
+    }
+
+    /// Refuses to write over the buffer's own file while it's `readonly`, unless
+    /// `force`d.
+    fn check_readonly(&self, filename: &str, force: bool) -> io::Result<()> {
+        if !force && self.options.get_or_default("readonly").as_bool() && self.is_own_file(filename)
+        {
+            return Err(io::Error::other(
+                "'readonly' option is set (add ! to override)",
+            ));
+        }
         Ok(())
     }
 
+    /// Whether `filename` is the buffer's file however its path is spelled, a
+    /// path that doesn't exist yet is compared as it's written.
+    fn is_own_file(&self, filename: &str) -> bool {
+        let canonical = |path: &str| fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        canonical(filename) == canonical(&self.name)
+    }
+
+    /// Bytes of chars `range` as they're written, lines ending as `fileformat`
+    /// says. Fails before anything is written when a char has no bytes in the
+    /// `fileencoding`.
+    fn encode(&self, range: Range<usize>) -> io::Result<Vec<u8>> {
+        let fenc = self.options.get_or_default("fileencoding").as_str();
+        let encoding = Encoding::from_name(fenc).unwrap_or(Encoding::Utf8);
+        let ff = self.options.get_or_default("fileformat").as_str();
+        let format = Format::from_name(ff).unwrap_or(Format::Unix);
+        let bom = range.start == 0 && self.options.get_or_default("bomb").as_bool();
+        let first = self.rope.char_to_line(range.start);
+        let text = format.to_file(self.rope.slice(range).to_string());
+        encoding::encode(&text, encoding, bom).map_err(|idx| {
+            let before = format.to_buffer(text.chars().take(idx).collect());
+            let line = first + before.matches('\n').count() + 1;
+            io::Error::other(format!(
+                "Conversion error: line {line} can't be written as {}",
+                encoding.name()
+            ))
+        })
+    }
+
+    /// Where in the undo history the buffer is, it moves with every change.
+    pub fn undo_position(&self) -> usize {
+        self.history.position()
+    }
+
+    /// Whether the text or how it's written changed since the file was read or
+    /// last written.
+    pub fn is_modified(&self) -> bool {
+        self.history.has_pending()
+            || self.history.position() != self.saved
+            || self
+                .written
+                .as_ref()
+                .is_some_and(|written| *written != self.file_options())
+    }
+
+    /// Writes rows `rows` to `filename`, `force` as for [`Buffer::save`].
+    pub fn save_rows(&self, rows: LineRange, filename: &str, force: bool) -> io::Result<()> {
+        self.check_readonly(filename, force)?;
+        let start = self.line_to_char(rows.start);
+        let end = self.line_to_char(rows.end + 1);
+        let bytes = self.encode(start..end)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(filename)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&bytes)?;
+        writer.flush()
+    }
+
+    pub fn set_mark(&mut self, name: char, pos: Pos) {
+        self.marks.insert(name, pos);
+    }
+
+    /// Buffer row and column of mark `name`.
+    pub fn mark(&self, name: char) -> Option<Pos> {
+        self.marks.get(&name).copied()
+    }
+
+    /// Puts the cursor on the screen column it keeps to moving up and down.
     pub fn align_cursor(&mut self) {
-        let max = self.cursor.max.x as usize;
-        let line_max = self.current_line_len();
-        self.cursor.set_col(line_max.min(max));
+        let row = self.cursor.row_scroll();
+        let col = grapheme::col_at(&self.line(row), self.cursor.max.x as usize, self.tabstop())
+            .min(self.line_len(row));
+        self.cursor
+            .set_col(col.saturating_sub(self.cursor.scroll.x as usize));
     }
 
     pub fn _get_line_len(&self, row: usize) -> Option<usize> {
@@ -182,16 +393,28 @@ impl Buffer {
     }
 
     pub fn current_line_len(&self) -> usize {
-        let row = (self.cursor.pos.y + self.cursor.scroll.y) as usize;
-        self.rope.line(row).len_chars().saturating_sub(2)
+        self.line_len(self.cursor.row_scroll())
     }
 
+    /// Last column the cursor can take on `row`, where its last grapheme starts.
     pub fn line_len(&self, row: usize) -> usize {
-        self.rope.line(row).len_chars().saturating_sub(2)
+        let line = self.line(row);
+        grapheme::prev(&line, line.chars().count())
+    }
+
+    /// Columns a tab reaches to, the buffer's `tabstop`.
+    pub fn tabstop(&self) -> usize {
+        self.options.get_or_default("tabstop").as_int().max(1)
+    }
+
+    /// Screen column the cursor is drawn at, counted from the start of the line.
+    pub fn display_col(&self) -> usize {
+        let Pos { x, y } = self.cursor.pos();
+        grapheme::display_col(&self.line(y as usize), x as usize, self.tabstop())
     }
 
     pub fn len_lines(&self) -> usize {
-        self.rope.len_lines().saturating_sub(2)
+        motion::last_row(&self.rope)
     }
 
     pub fn get_cursor(&self) -> &Cursor {
@@ -202,13 +425,81 @@ impl Buffer {
         &mut self.cursor
     }
 
+    pub fn set_cursor(&mut self, cursor: Cursor) {
+        self.cursor = cursor;
+    }
+
+    /// `scrolloff`, see [`Cursor::keep_margin`].
+    pub fn keep_margin(&mut self, margin: usize, size: Size) {
+        let last = self.len_lines();
+        self.cursor.keep_margin(margin, size.height as usize, last);
+    }
+
+    /// Pulls the cursor back inside the text and `size`, another window may have
+    /// changed the buffer under it.
+    pub fn clamp_cursor(&mut self, size: Size) {
+        let Pos { x, y } = self.cursor.pos();
+        let row = (y as usize).min(self.len_lines());
+        let col = (x as usize).min(self.line_len(row));
+        let line = self.line(row);
+        let max = self.cursor.max;
+        self.cursor.jump(
+            row,
+            grapheme::start(&line, col),
+            &line,
+            self.tabstop(),
+            size,
+        );
+        self.cursor.max = max;
+    }
+
+    /// Puts `row` at the top of the window, the cursor moves along when it would
+    /// leave the screen.
+    pub fn scroll_to(&mut self, row: usize, size: Size) {
+        let top = row.min(self.len_lines());
+        let bottom = top + (size.height as usize).saturating_sub(1);
+        let row = self
+            .cursor
+            .row_scroll()
+            .clamp(top, bottom)
+            .min(self.len_lines());
+        let col = (self.cursor.pos().x as usize).min(self.line_len(row));
+        let line = self.line(row);
+        self.cursor.scroll.y = top as u16;
+        self.cursor.jump(
+            row,
+            grapheme::start(&line, col),
+            &line,
+            self.tabstop(),
+            size,
+        );
+    }
+
+    /// Text of `row` without its line break.
+    pub fn line(&self, row: usize) -> String {
+        let Some(line) = self.rope.get_line(row) else {
+            return String::new();
+        };
+        line.to_string().trim_end_matches(['\n', '\r']).to_string()
+    }
+
+    /// Next match of `regex` after the cursor, or before it when searching backwards.
+    pub fn search(&self, regex: &Regex, forward: bool) -> Option<Range<usize>> {
+        search::find(&self.rope, regex, self.cursor_idx(), forward)
+    }
+
     pub fn on_screen(&self, size: &Size) -> Vec<String> {
+        self.on_screen_from(self.cursor.scroll, size)
+    }
+
+    /// Lines a window scrolled to `scroll` shows, windows on this buffer have their own scroll.
+    pub fn on_screen_from(&self, scroll: Pos, size: &Size) -> Vec<String> {
         let Size { width, height } = size;
         let width = *width as usize;
         let height = *height as usize;
-        let top = self.cursor.scroll.y as usize;
+        let top = scroll.y as usize;
         let bottom = top + height;
-        let start = self.cursor.scroll.x as usize;
+        let start = scroll.x as usize;
         let end = start + width;
         self.rope
             .lines()
@@ -232,6 +523,135 @@ impl Buffer {
             .collect()
     }
 
+    /// Rows of a window scrolled to where `cursor` is, one for each line or with
+    /// `wrap` as many as each line takes.
+    pub fn screen_lines(
+        &self,
+        cursor: &Cursor,
+        size: Size,
+        wrap: Option<&Wrap>,
+    ) -> Vec<ScreenLine> {
+        let scroll = cursor.scroll;
+        let height = size.height as usize;
+        let mut lines = Vec::with_capacity(height);
+        let mut row = scroll.y as usize;
+        let mut skip = cursor.skip;
+        while lines.len() < height && row <= self.len_lines() {
+            let line = self.line(row);
+            match wrap {
+                Some(wrap) => {
+                    let segments = wrap.segments(&line);
+                    let skipped = skip.min(segments.len() - 1);
+                    lines.extend(segments.into_iter().enumerate().skip(skipped).map(
+                        |(idx, cols)| ScreenLine {
+                            row,
+                            cols,
+                            first: idx == 0,
+                        },
+                    ));
+                    skip = 0;
+                }
+                None => {
+                    let start = (scroll.x as usize).min(line.chars().count());
+                    let end = grapheme::fit(&line, start, size.width as usize, self.tabstop());
+                    lines.push(ScreenLine {
+                        row,
+                        cols: start..end,
+                        first: true,
+                    });
+                }
+            }
+            row += 1;
+        }
+        lines.truncate(height);
+        lines
+    }
+
+    /// Where the cursor is on a window that doesn't wrap, `x` in screen columns
+    /// from the window's left edge.
+    pub fn screen_cursor(&self) -> Pos {
+        let Pos { x: col, y: row } = self.cursor.pos();
+        let line = self.line(row as usize);
+        let tabstop = self.tabstop();
+        let x = grapheme::display_col(&line, col as usize, tabstop).saturating_sub(
+            grapheme::display_col(&line, self.cursor.scroll.x as usize, tabstop),
+        );
+        Pos::new(x as u16, self.cursor.pos.y)
+    }
+
+    /// Where the cursor is on a window wrapped by `wrap`.
+    pub fn wrapped_cursor(&self, wrap: &Wrap) -> Pos {
+        let Pos { x: col, y: row } = self.cursor.pos();
+        let top = self.cursor.scroll.y as usize;
+        let above = (top..row as usize)
+            .map(|row| wrap.rows(&self.line(row)))
+            .sum::<usize>();
+        let (idx, x) = wrap.locate(&self.line(row as usize), col as usize);
+        Pos::new(
+            x as u16,
+            (above + idx).saturating_sub(self.cursor.skip) as u16,
+        )
+    }
+
+    /// A wrapped window scrolls down until every row up to the cursor's fits in
+    /// `height`, past rows of the top line when that alone is taller.
+    pub fn keep_wrapped(&mut self, wrap: &Wrap, height: usize) {
+        let Pos { x: col, y: row } = self.cursor.pos();
+        let row = row as usize;
+        let height = height.max(1);
+        let mut top = (self.cursor.scroll.y as usize).min(row);
+        let (idx, _) = wrap.locate(&self.line(row), col as usize);
+        let mut skip = if top == row {
+            self.cursor.skip.min(idx)
+        } else {
+            self.cursor
+                .skip
+                .min(wrap.rows(&self.line(top)).saturating_sub(1))
+        };
+        let mut used = (top..row)
+            .map(|row| wrap.rows(&self.line(row)))
+            .sum::<usize>()
+            + idx
+            + 1
+            - skip;
+        while used > height && top < row {
+            used -= wrap.rows(&self.line(top)) - skip;
+            skip = 0;
+            top += 1;
+        }
+        skip += used.saturating_sub(height);
+        self.cursor.scroll = Pos::new(0, top as u16);
+        self.cursor.skip = skip;
+        self.cursor.pos = Pos::new(col, (row - top) as u16);
+    }
+
+    /// `gj` and `gk`, a row down or up the screen, inside the line while it goes on.
+    pub fn cursor_screen_row(&mut self, wrap: &Wrap, down: bool, size: Size) -> bool {
+        let Pos { x: col, y: row } = self.cursor.pos();
+        let row = row as usize;
+        let line = self.line(row);
+        let (idx, x) = wrap.locate(&line, col as usize);
+        let (row, idx) = if down {
+            if idx + 1 < wrap.rows(&line) {
+                (row, idx + 1)
+            } else if row < self.len_lines() {
+                (row + 1, 0)
+            } else {
+                return false;
+            }
+        } else if idx > 0 {
+            (row, idx - 1)
+        } else if row > 0 {
+            (row - 1, wrap.rows(&self.line(row - 1)) - 1)
+        } else {
+            return false;
+        };
+        let line = self.line(row);
+        let col = wrap.col_at(&line, idx, x, self.line_len(row));
+        self.cursor.jump(row, col, &line, self.tabstop(), size);
+        true
+    }
+
     pub fn get_all_text(&self) -> String {
         self.rope.to_string()
     }
@@ -239,90 +659,602 @@ impl Buffer {
     pub fn clear(&mut self) {
         self.rope = Rope::from_str("");
         self.cursor = Cursor::default();
+        self.history = History::default();
+        self.marks.clear();
+        self.saved = 0;
+    }
+
+    pub fn cursor_idx(&self) -> usize {
+        let Pos { x, y } = self.cursor.pos();
+        self.rope.line_to_char(y as usize) + x as usize
+    }
+
+    pub fn set_cursor_idx(&mut self, idx: usize, size: Size) {
+        let idx = idx.min(self.rope.len_chars());
+        let row = self
+            .rope
+            .char_to_line(idx)
+            .min(motion::last_row(&self.rope));
+        let start = self.rope.line_to_char(row);
+        let col = idx.min(motion::line_end(&self.rope, row)) - start;
+        let line = self.line(row);
+        self.cursor.jump(
+            row,
+            grapheme::start(&line, col),
+            &line,
+            self.tabstop(),
+            size,
+        );
+    }
+
+    pub fn span(&self, motion: Motion, count: usize, explicit: bool) -> Option<Span> {
+        motion::span(&self.rope, self.cursor_idx(), motion, count, explicit)
+    }
+
+    pub fn motion(&mut self, motion: Motion, count: usize, explicit: bool, size: Size) {
+        let idx = motion::target(&self.rope, self.cursor_idx(), motion, count, explicit);
+        self.set_cursor_idx(idx, size);
+        self.align_cursor();
+    }
+
+    pub fn first_non_blank(&self, idx: usize) -> usize {
+        let row = self.rope.char_to_line(idx.min(self.rope.len_chars()));
+        motion::first_non_blank(&self.rope, row)
+    }
+
+    pub fn set_anchor(&mut self, anchor: bool) {
+        self.cursor.anchor = anchor.then(|| self.cursor.pos());
+    }
+
+    /// Swaps the cursor with the other end of the visual selection.
+    pub fn swap_anchor(&mut self, size: Size) {
+        let Some(anchor) = self.cursor.anchor else {
+            return;
+        };
+        let pos = self.cursor.pos();
+        let idx = self.line_to_char(anchor.y as usize) + anchor.x as usize;
+        self.set_cursor_idx(idx, size);
+        self.cursor.anchor = Some(pos);
+    }
+
+    /// Columns of `row` the visual selection covers, end exclusive.
+    pub fn selected_cols(&self, row: usize, mode: Mode) -> Option<(usize, usize)> {
+        let (start, end) = self.cursor.selection()?;
+        let (top, bottom) = (start.y as usize, end.y as usize);
+        if row < top || row > bottom {
+            return None;
+        }
+        // One past the text so a selected line break shows up.
+        let full = self.line_len(row) + 2;
+        match mode {
+            Mode::Visual => {
+                let from = if row == top { start.x as usize } else { 0 };
+                let to = if row == bottom {
+                    end.x as usize + 1
+                } else {
+                    full
+                };
+                Some((from, to))
+            }
+            Mode::VisualLine => Some((0, full)),
+            Mode::VisualBlock => {
+                let (left, right) = (start.x.min(end.x), start.x.max(end.x));
+                Some((left as usize, right as usize + 1))
+            }
+            _ => None,
+        }
+    }
+
+    /// Char spans the visual selection covers, one per row for a block.
+    pub fn selection_spans(&self, mode: Mode) -> Vec<Span> {
+        let Some((start, end)) = self.cursor.selection() else {
+            return Vec::new();
+        };
+        let (top, bottom) = (start.y as usize, end.y as usize);
+        match mode {
+            Mode::VisualLine => vec![Span {
+                start: self.line_to_char(top),
+                end: self.line_to_char(bottom + 1),
+                linewise: true,
+            }],
+            Mode::VisualBlock => (top..=bottom)
+                .filter_map(|row| {
+                    let (left, right) = self.selected_cols(row, mode)?;
+                    let line = self.line_to_char(row);
+                    let end = motion::line_end(&self.rope, row);
+                    Some(Span {
+                        start: (line + left).min(end),
+                        end: (line + right).min(end),
+                        linewise: false,
+                    })
+                })
+                .collect(),
+            _ => {
+                let from = self.line_to_char(top) + start.x as usize;
+                let to = self.line_to_char(bottom) + end.x as usize + 1;
+                vec![Span {
+                    start: from,
+                    end: to.min(self.rope.len_chars()),
+                    linewise: false,
+                }]
+            }
+        }
+    }
+
+    pub fn char_at(&self, idx: usize) -> Option<char> {
+        self.rope.get_char(idx)
+    }
+
+    /// Text covered by `span`, linewise text always ends in a line break.
+    pub fn text(&self, span: Span) -> String {
+        let end = span.end.min(self.rope.len_chars());
+        let mut text = self.rope.slice(span.start.min(end)..end).to_string();
+        if span.linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Inserts `text` at char `idx` as one undo step, the cursor doesn't move.
+    pub fn insert_at(&mut self, idx: usize, text: impl Into<String>) {
+        let text = text.into();
+        if text.is_empty() {
+            return;
+        }
+        let at = idx.min(self.rope.len_chars());
+        self.apply_edit(Edit::Insert { at, text });
+        self.history.commit(self.cursor);
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    pub fn line_to_char(&self, row: usize) -> usize {
+        self.rope.line_to_char(row.min(self.rope.len_lines()))
+    }
+
+    /// Removes `span` as one undo step and leaves the cursor where it started.
+    pub fn remove(&mut self, span: Span, size: Size) {
+        self.remove_spans(&[span], size);
+    }
+
+    /// Removes every span as one undo step, `spans` are in buffer order and don't overlap.
+    pub fn remove_spans(&mut self, spans: &[Span], size: Size) {
+        let mut cursor = None;
+        for span in spans.iter().rev() {
+            let len = self.rope.len_chars();
+            let Span { mut start, end, .. } = *span;
+            let end = end.min(len);
+            // The last line has no line break of its own, take the one before it.
+            if span.linewise && end == len && start > 0 && self.rope.get_char(len - 1) != Some('\n')
+            {
+                start -= 1;
+            }
+            if start >= end {
+                continue;
+            }
+            let text = self.rope.slice(start..end).to_string();
+            self.apply_edit(Edit::Remove { at: start, text });
+            cursor = Some(span.start.min(start));
+        }
+        let Some(idx) = cursor else {
+            return;
+        };
+        self.set_cursor_idx(idx, size);
+        self.history.commit(self.cursor);
+    }
+
+    /// Swaps the case of every char in `spans` as one undo step.
+    pub fn toggle_case(&mut self, spans: &[Span]) {
+        for span in spans.iter().rev() {
+            let end = span.end.min(self.rope.len_chars());
+            let start = span.start.min(end);
+            let text = self.rope.slice(start..end).to_string();
+            let toggled = text
+                .chars()
+                .flat_map(|c| {
+                    if c.is_uppercase() {
+                        c.to_lowercase().collect::<Vec<_>>()
+                    } else {
+                        c.to_uppercase().collect::<Vec<_>>()
+                    }
+                })
+                .collect::<String>();
+            if toggled == text {
+                continue;
+            }
+            self.apply_edit(Edit::Remove { at: start, text });
+            self.apply_edit(Edit::Insert {
+                at: start,
+                text: toggled,
+            });
+        }
+        self.history.commit(self.cursor);
+    }
+
+    /// Every replacement `:s` would make in `rows`.
+    pub fn substitutions(
+        &self,
+        regex: &Regex,
+        rows: LineRange,
+        replacement: &str,
+        global: bool,
+    ) -> Vec<Replace> {
+        substitute::replacements(&self.rope, regex, rows, replacement, global)
+    }
+
+    /// Every replacement `:retab` would make in `rows`, see [`grapheme::retab`].
+    pub fn retabs(
+        &self,
+        rows: LineRange,
+        tabstop: usize,
+        expand: bool,
+        spaces: bool,
+    ) -> Vec<Replace> {
+        let old = self.tabstop();
+        (rows.start..=rows.end.min(self.len_lines()))
+            .flat_map(|row| {
+                let start = self.line_to_char(row);
+                grapheme::retab(&self.line(row), old, tabstop, expand, spaces)
+                    .into_iter()
+                    .map(move |(cols, text)| Replace {
+                        range: start + cols.start..start + cols.end,
+                        text,
+                    })
+            })
+            .collect()
+    }
+
+    /// Makes every replacement as one undo step, `edits` are in buffer order.
+    pub fn replace_ranges(&mut self, edits: &[Replace]) {
+        for Replace { range, text } in edits.iter().rev() {
+            let end = range.end.min(self.rope.len_chars());
+            let start = range.start.min(end);
+            if start < end {
+                let removed = self.rope.slice(start..end).to_string();
+                self.apply_edit(Edit::Remove {
+                    at: start,
+                    text: removed,
+                });
+            }
+            if !text.is_empty() {
+                self.apply_edit(Edit::Insert {
+                    at: start,
+                    text: text.clone(),
+                });
+            }
+        }
+        self.history.commit(self.cursor);
+    }
+
+    /// Text of `rows` ending in a line break.
+    fn rows_text(&self, rows: LineRange) -> String {
+        let start = self.line_to_char(rows.start);
+        let end = self.line_to_char(rows.end + 1);
+        let mut text = self.rope.slice(start..end).to_string();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Gives the last line a line break for the duration of an edit,
+    /// returns whether one was added so [`Buffer::restore_last_line`] can take it back.
+    fn pad_last_line(&mut self) -> bool {
+        let len = self.rope.len_chars();
+        if len == 0 || self.rope.get_char(len - 1) == Some('\n') {
+            return false;
+        }
+        self.apply_edit(Edit::Insert {
+            at: len,
+            text: "\n".into(),
+        });
+        true
+    }
+
+    fn restore_last_line(&mut self, padded: bool) {
+        let len = self.rope.len_chars();
+        if padded && self.rope.get_char(len.wrapping_sub(1)) == Some('\n') {
+            self.apply_edit(Edit::Remove {
+                at: len - 1,
+                text: "\n".into(),
+            });
+        }
+    }
+
+    /// `:t`, copies `rows` below line number `after`, `0` puts them above the first line.
+    pub fn copy_lines(&mut self, rows: LineRange, after: usize) {
+        let padded = self.pad_last_line();
+        let text = self.rows_text(rows);
+        let at = self.line_to_char(after);
+        self.apply_edit(Edit::Insert { at, text });
+        self.restore_last_line(padded);
+        self.history.commit(self.cursor);
+    }
+
+    /// `:m`, moves `rows` below line number `after`, which can't be inside them.
+    pub fn move_lines(&mut self, rows: LineRange, after: usize) {
+        if (rows.start..=rows.end + 1).contains(&after) {
+            return;
+        }
+        let padded = self.pad_last_line();
+        let text = self.rows_text(rows);
+        let start = self.line_to_char(rows.start);
+        let end = self.line_to_char(rows.end + 1);
+        let at = self.line_to_char(after);
+        let removed = Edit::Remove {
+            at: start,
+            text: text.clone(),
+        };
+        // Edit whichever end of the buffer comes later first so the other index holds.
+        if at >= end {
+            self.apply_edit(Edit::Insert { at, text });
+            self.apply_edit(removed);
+        } else {
+            self.apply_edit(removed);
+            self.apply_edit(Edit::Insert { at, text });
+        }
+        self.restore_last_line(padded);
+        self.history.commit(self.cursor);
+    }
+
+    /// Indents or dedents rows `top..=bottom` by `width` columns as one undo step.
+    /// Indents rows `top..=bottom` by `indent`, or takes up to `width` columns of
+    /// indentation off them.
+    pub fn shift_lines(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        indent: &str,
+        width: usize,
+        right: bool,
+    ) {
+        for row in (top..=bottom.min(self.len_lines())).rev() {
+            let start = self.rope.line_to_char(row);
+            let end = motion::line_end(&self.rope, row);
+            if right {
+                if start < end {
+                    self.apply_edit(Edit::Insert {
+                        at: start,
+                        text: indent.to_string(),
+                    });
+                }
+                continue;
+            }
+            let line = self.rope.slice(start..end);
+            let remove = match line.get_char(0) {
+                Some('\t') => 1,
+                _ => line.chars().take(width).take_while(|c| *c == ' ').count(),
+            };
+            if remove > 0 {
+                let text = self.rope.slice(start..start + remove).to_string();
+                self.apply_edit(Edit::Remove { at: start, text });
+            }
+        }
+        self.history.commit(self.cursor);
+    }
+
+    pub fn char_to_line(&self, idx: usize) -> usize {
+        self.rope.char_to_line(idx.min(self.rope.len_chars()))
+    }
+
+    fn apply_edit(&mut self, edit: Edit) {
+        self.apply(&edit);
+        self.history.record(edit, self.cursor);
+    }
+
+    /// Makes `edit` to the text, marks after it move along with their text and a
+    /// mark whose whole line is removed goes away.
+    fn apply(&mut self, edit: &Edit) {
+        let old = self.rope.clone();
+        edit.apply(&mut self.rope);
+        if self.marks.is_empty() {
+            return;
+        }
+        let marks = std::mem::take(&mut self.marks);
+        for (name, pos) in marks {
+            let row = (pos.y as usize).min(motion::last_row(&old));
+            let line = old.line_to_char(row)..old.line_to_char(row + 1);
+            let idx = line.start + (pos.x as usize).min(line.len());
+            let idx = match edit {
+                Edit::Insert { at, text } if idx >= *at => idx + text.chars().count(),
+                Edit::Remove { at, text } => {
+                    let end = at + text.chars().count();
+                    if idx >= end {
+                        idx - (end - at)
+                    } else if idx < *at {
+                        idx
+                    } else if *at <= line.start && end >= line.end && !line.is_empty() {
+                        continue;
+                    } else {
+                        *at
+                    }
+                }
+                _ => idx,
+            };
+            let row = self.rope.char_to_line(idx);
+            let col = idx - self.rope.line_to_char(row);
+            self.marks.insert(name, Pos::new(col as u16, row as u16));
+        }
     }
 
     pub fn insert(&mut self, text: impl Into<String>) {
         let text = text.into();
-        let row = self.cursor.pos.y as usize;
-        let col = self.cursor.pos.x as usize;
-        let char_idx = self.rope.line_to_char(row);
-        self.rope.insert(char_idx + col, &text);
-        let col = text.len();
+        let at = self.cursor_idx();
+        self.apply_edit(Edit::Insert {
+            at,
+            text: text.clone(),
+        });
+        let col = text.chars().count();
         self.cursor.add_col(col);
         if text.contains('\n') {
             self.cursor.new_line();
         }
+        self.history.commit(self.cursor);
     }
 
+    /// Removes the grapheme before the cursor, or the line break at the start of a line.
     pub fn backspace(&mut self) {
-        let col = self.cursor.pos.x as usize;
-        let row = self.cursor.pos.y as usize;
-        let char_idx = self.rope.line_to_char(row);
-        let start = (char_idx + col).saturating_sub(1);
-        let end = char_idx + col;
-        self.rope.remove(start..end);
+        let col = self.cursor.pos().x as usize;
+        let end = self.cursor_idx();
+        let prev = grapheme::prev(&self.line(self.cursor.row_scroll()), col);
+        let start = end.saturating_sub((col - prev).max(1));
+        if start == end {
+            return;
+        }
+        let text = self.rope.slice(start..end).to_string();
+        self.apply_edit(Edit::Remove { at: start, text });
         if col == 0 {
             self.cursor_up();
             self.cursor_end();
-            return;
+        } else {
+            self.cursor.sub_col(col - prev);
+        }
+        self.history.commit(self.cursor);
+    }
+
+    /// Removes the spaces before the cursor back to the last multiple of `soft`
+    /// columns, as `softtabstop` does. False when there's only one to remove.
+    pub fn backspace_soft_tab(&mut self, soft: usize) -> bool {
+        let col = self.cursor.pos().x as usize;
+        let shown = self.display_col();
+        let before = self
+            .line(self.cursor.row_scroll())
+            .chars()
+            .take(col)
+            .collect::<String>();
+        let spaces = before.chars().rev().take_while(|c| *c == ' ').count();
+        let remove = match shown % soft {
+            0 => soft,
+            over => over,
+        };
+        if remove < 2 || spaces < remove {
+            return false;
         }
-        self.cursor.left();
+        let end = self.cursor_idx();
+        let text = " ".repeat(remove);
+        self.apply_edit(Edit::Remove {
+            at: end - remove,
+            text,
+        });
+        self.cursor.sub_col(remove);
+        self.history.commit(self.cursor);
+        true
     }
 
+    /// Removes the grapheme under the cursor, or the line break past the end of a line.
     pub fn delete_char(&mut self) {
-        let col = self.cursor.pos.x as usize;
-        let row = self.cursor.pos.y as usize;
-        let char_idx = self.rope.line_to_char(row);
-        let start = char_idx + col;
-        let end = (char_idx + col).saturating_add(1);
-        self.rope.remove(start..end);
+        let start = self.cursor_idx();
+        let col = self.cursor.pos().x as usize;
+        let next = grapheme::next(&self.line(self.cursor.row_scroll()), col);
+        let end = (start + (next - col).max(1)).min(self.rope.len_chars());
+        if start >= end {
+            return;
+        }
+        let text = self.rope.slice(start..end).to_string();
+        self.apply_edit(Edit::Remove { at: start, text });
+        self.history.commit(self.cursor);
+    }
+
+    /// Everything edited until [`Buffer::end_undo_group`] is undone in one go.
+    pub fn begin_undo_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_undo_group(&mut self) {
+        self.history.end_group(self.cursor);
+    }
+
+    pub fn undo(&mut self) -> bool {
+        self.history.commit(self.cursor);
+        let Some(change) = self.history.undo().cloned() else {
+            return false;
+        };
+        for edit in change.edits.iter().rev() {
+            self.apply(&edit.invert());
+        }
+        self.cursor = change.before;
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.history.commit(self.cursor);
+        let Some(change) = self.history.redo().cloned() else {
+            return false;
+        };
+        for edit in change.edits.iter() {
+            self.apply(edit);
+        }
+        self.cursor = change.after;
+        true
     }
 
     pub fn cursor_up(&mut self) -> bool {
-        let row = self.cursor.pos.y as usize;
-        if row == 0 {
+        if self.cursor.pos.y == 0 {
             return false;
         }
-        let offset = self.cursor.scroll.y as usize;
-        let row = row.saturating_sub(1);
-        let len = self.line_len(row + offset);
-        self.cursor.up(len);
+        self.cursor_to_row(self.cursor.row_scroll() - 1);
+        self.cursor.sub_row(1);
         true
     }
 
     pub fn cursor_down(&mut self, max: usize) -> bool {
-        let row = self.cursor.pos.y as usize;
-        if row >= max {
+        if self.cursor.pos.y as usize >= max {
             return false;
         }
-        let offset = self.cursor.scroll.y as usize;
-        let row = row.saturating_add(1);
-        let len = self.line_len(row + offset);
-        self.cursor.down(len);
+        self.cursor_to_row(self.cursor.row_scroll() + 1);
+        self.cursor.add_row(1);
         true
     }
 
+    /// Puts the cursor on the grapheme of `row` under the screen column it's on,
+    /// or the one it keeps to when that's further right.
+    fn cursor_to_row(&mut self, row: usize) {
+        let want = self.display_col().max(self.cursor.max.x as usize);
+        let col = grapheme::col_at(&self.line(row), want, self.tabstop()).min(self.line_len(row));
+        self.cursor
+            .set_col(col.saturating_sub(self.cursor.scroll.x as usize));
+    }
+
+    /// Steps back a grapheme, scrolling left when it starts off screen.
     pub fn cursor_left(&mut self) -> bool {
-        let col = self.cursor.pos.x;
+        let col = self.cursor.pos().x as usize;
         if col == 0 {
             return false;
         }
-        self.cursor.left();
+        let line = self.line(self.cursor.row_scroll());
+        let col = grapheme::prev(&line, col);
+        let left = (self.cursor.scroll.x as usize).min(col);
+        self.cursor.scroll.x = left as u16;
+        self.cursor.set_col(col - left);
+        self.cursor.max.x = grapheme::display_col(&line, col, self.tabstop()) as u16;
         true
     }
 
+    /// Steps on a grapheme, scrolling right so all of it fits in `width` columns.
     pub fn cursor_right(&mut self, width: usize) -> bool {
-        let len_col = self.current_line_len();
-        let col = self.cursor.pos.x as usize;
-        if col < len_col && col < width {
-            self.cursor.right();
-            return true;
+        let col = self.cursor.pos().x as usize;
+        if col >= self.current_line_len() {
+            return false;
         }
-        false
+        let line = self.line(self.cursor.row_scroll());
+        let col = grapheme::next(&line, col);
+        let left = grapheme::scroll_for(
+            &line,
+            col,
+            self.cursor.scroll.x as usize,
+            width,
+            self.tabstop(),
+        );
+        self.cursor.scroll.x = left as u16;
+        self.cursor.set_col(col - left);
+        self.cursor.max.x = grapheme::display_col(&line, col, self.tabstop()) as u16;
+        true
     }
 
     pub fn cursor_end(&mut self) {
-        let row = self.cursor.pos.y as usize;
+        let row = self.cursor.row_scroll();
         let len = self.line_len(row);
         self.cursor.set_col(len);
     }
@@ -342,13 +1274,32 @@ impl Buffer {
         self.align_cursor();
     }
 
+    /// Scrolls a grapheme of the cursor's line to the left, the cursor keeps its
+    /// place on screen.
     pub fn scroll_left(&mut self) {
-        self.cursor.scroll_left();
+        let line = self.line(self.cursor.row_scroll());
+        let left = grapheme::prev(&line, self.cursor.scroll.x as usize);
+        self.scroll_sideways(&line, left);
     }
 
+    /// Scrolls a grapheme to the right while the line runs past `width` columns.
     pub fn scroll_right(&mut self, width: usize) {
-        let max = self.current_line_len();
-        self.cursor.scroll_right(max.saturating_sub(width));
+        let line = self.line(self.cursor.row_scroll());
+        let left = self.cursor.scroll.x as usize;
+        let tabstop = self.tabstop();
+        let shown = grapheme::display_col(&line, usize::MAX, tabstop)
+            - grapheme::display_col(&line, left, tabstop);
+        if shown > width {
+            self.scroll_sideways(&line, grapheme::next(&line, left));
+        }
+    }
+
+    fn scroll_sideways(&mut self, line: &str, left: usize) {
+        let col = self.cursor.pos.x as usize + left;
+        let col = grapheme::start(line, col).min(grapheme::prev(line, line.chars().count()));
+        let left = left.min(col);
+        self.cursor.scroll.x = left as u16;
+        self.cursor.set_col(col - left);
     }
 }
 
@@ -358,6 +1309,31 @@ impl Default for Buffer {
             name: "N/A".into(),
             rope: Rope::default(),
             cursor: Cursor::default(),
+            history: History::default(),
+            marks: BTreeMap::new(),
+            options: LocalOptions::default(),
+            saved: 0,
+            read_error: None,
+            written: None,
         }
     }
 }
+
+#[test]
+fn visual_selection_spans() {
+    let size = Size::new(80, 24);
+    let mut buf = Buffer::default();
+    buf.insert("one two\nthree four\n");
+    buf.set_cursor_idx(1, size);
+    buf.set_anchor(true);
+    buf.set_cursor_idx(10, size);
+    let text = |buf: &Buffer, mode| {
+        buf.selection_spans(mode)
+            .into_iter()
+            .map(|span| buf.text(span))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(text(&buf, Mode::Visual), vec!["ne two\nthr"]);
+    assert_eq!(text(&buf, Mode::VisualLine), vec!["one two\nthree four\n"]);
+    assert_eq!(text(&buf, Mode::VisualBlock), vec!["ne", "hr"]);
+}
@@ -1,5 +1,6 @@
 use super::AUTHOR;
 use clap::{crate_description, crate_name, crate_version, Parser};
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -12,4 +13,7 @@ use clap::{crate_description, crate_name, crate_version, Parser};
 )]
 pub struct Cli {
     pub files: Vec<String>,
+    /// Config script to run instead of init.rhai, NONE runs none.
+    #[arg(short = 'u', long = "config")]
+    pub config: Option<PathBuf>,
 }
@@ -0,0 +1,218 @@
+/// How a file's bytes turn into text, the `fileencoding` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+/// Every name `fileencoding` takes, in the order of [`Encoding::name`].
+pub const NAMES: &[&str] = &["utf-8", "utf-16le", "utf-16be", "latin1"];
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Latin1 => "latin1",
+        }
+    }
+
+    /// `utf8` and `iso-8859-1` work too.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Self::Utf8),
+            "utf-16le" | "utf16le" => Some(Self::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Self::Utf16Be),
+            "latin1" | "iso-8859-1" => Some(Self::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Byte order mark a file in this encoding starts with when `bomb` is set.
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8 => b"\xEF\xBB\xBF",
+            Self::Utf16Le => b"\xFF\xFE",
+            Self::Utf16Be => b"\xFE\xFF",
+            Self::Latin1 => b"",
+        }
+    }
+}
+
+/// How lines end in a file, the `fileformat` option. Buffers always hold `\n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `\n`
+    Unix,
+    /// `\r\n`
+    Dos,
+    /// `\r`
+    Mac,
+}
+
+/// Every name `fileformat` takes, in the order of [`Format::name`].
+pub const FORMATS: &[&str] = &["unix", "dos", "mac"];
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unix => "unix",
+            Self::Dos => "dos",
+            Self::Mac => "mac",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(Self::Unix),
+            "dos" => Some(Self::Dos),
+            "mac" => Some(Self::Mac),
+            _ => None,
+        }
+    }
+
+    /// `dos` when most lines end in `\r\n`, `mac` when there's a `\r` and no
+    /// `\n`. A mixed file read as `dos` is written back with `\r\n` throughout,
+    /// one read as `unix` keeps its few stray `\r`s as text.
+    pub fn detect(text: &str) -> Self {
+        let lf = text.matches('\n').count();
+        if lf == 0 {
+            return if text.contains('\r') {
+                Self::Mac
+            } else {
+                Self::Unix
+            };
+        }
+        if text.matches("\r\n").count() * 2 > lf {
+            Self::Dos
+        } else {
+            Self::Unix
+        }
+    }
+
+    /// `text` from a file in this format with its lines ending in `\n`.
+    pub fn to_buffer(self, text: String) -> String {
+        match self {
+            Self::Unix => text,
+            Self::Dos => text.replace("\r\n", "\n"),
+            Self::Mac => text.replace('\r', "\n"),
+        }
+    }
+
+    /// `text` from a buffer with its lines ending the way this format does.
+    pub fn to_file(self, text: String) -> String {
+        match self {
+            Self::Unix => text,
+            Self::Dos => text.replace('\n', "\r\n"),
+            Self::Mac => text.replace('\n', "\r"),
+        }
+    }
+}
+
+/// Text read from a file and what it takes to write it back the same.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: Encoding,
+    pub bom: bool,
+}
+
+/// Reads `bytes` as `encoding`, or guesses from the byte order mark and falls
+/// back on UTF-8. Errors when the bytes aren't valid in it, nothing is replaced.
+pub fn decode(bytes: &[u8], encoding: Option<Encoding>) -> Result<Decoded, String> {
+    let found = [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be]
+        .into_iter()
+        .find(|enc| bytes.starts_with(enc.bom()));
+    let encoding = encoding.or(found).unwrap_or(Encoding::Utf8);
+    let bom = !encoding.bom().is_empty() && bytes.starts_with(encoding.bom());
+    let body = if bom {
+        &bytes[encoding.bom().len()..]
+    } else {
+        bytes
+    };
+    let invalid = || format!("Illegal byte sequence for {}", encoding.name());
+    let text = match encoding {
+        Encoding::Utf8 => String::from_utf8(body.to_vec()).map_err(|_| invalid())?,
+        Encoding::Latin1 => body.iter().map(|b| char::from(*b)).collect(),
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            if body.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            let units = body.chunks(2).map(|pair| match encoding {
+                Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+            char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .map_err(|_| invalid())?
+        }
+    };
+    Ok(Decoded {
+        text,
+        encoding,
+        bom,
+    })
+}
+
+/// Bytes of `text` in `encoding`, after a byte order mark with `bom`. Errors
+/// with the char index of the first char `encoding` has no bytes for.
+pub fn encode(text: &str, encoding: Encoding, bom: bool) -> Result<Vec<u8>, usize> {
+    let mut bytes = Vec::with_capacity(text.len());
+    if bom {
+        bytes.extend_from_slice(encoding.bom());
+    }
+    match encoding {
+        Encoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+        Encoding::Latin1 => {
+            for (idx, c) in text.chars().enumerate() {
+                bytes.push(u8::try_from(c).map_err(|_| idx)?);
+            }
+        }
+        Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+        Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+    }
+    Ok(bytes)
+}
+
+#[test]
+fn round_trips() {
+    let decoded = decode(b"\xEF\xBB\xBFhi\n", None).unwrap();
+    assert_eq!(decoded.text, "hi\n");
+    assert_eq!((decoded.encoding, decoded.bom), (Encoding::Utf8, true));
+    let utf16 = decode(b"\xFF\xFEh\0\xE9\0", None).unwrap();
+    assert_eq!(
+        (utf16.text.as_str(), utf16.encoding),
+        ("hé", Encoding::Utf16Le)
+    );
+    assert_eq!(
+        encode("hé", Encoding::Utf16Le, true).unwrap(),
+        b"\xFF\xFEh\0\xE9\0"
+    );
+    assert_eq!(
+        encode("hé", Encoding::Utf16Be, false).unwrap(),
+        b"\0h\0\xE9"
+    );
+    // Latin-1 isn't valid UTF-8, it has to be asked for.
+    assert!(decode(b"caf\xE9", None).is_err());
+    let latin1 = decode(b"caf\xE9", Some(Encoding::Latin1)).unwrap();
+    assert_eq!(latin1.text, "café");
+    assert_eq!(encode("café", Encoding::Latin1, false).unwrap(), b"caf\xE9");
+    assert_eq!(encode("a中", Encoding::Latin1, false), Err(1));
+    assert!(decode(b"\xFF\xFEh", None).is_err());
+}
+
+#[test]
+fn line_endings() {
+    assert_eq!(Format::detect("a\r\nb\r\n"), Format::Dos);
+    assert_eq!(Format::detect("a\r\nb\n"), Format::Unix);
+    assert_eq!(Format::detect("a\r\nb\r\nc\n"), Format::Dos);
+    assert_eq!(Format::Dos.to_buffer("a\r\nb\n".into()), "a\nb\n");
+    assert_eq!(Format::detect("a\rb\r"), Format::Mac);
+    assert_eq!(Format::detect("a"), Format::Unix);
+    assert_eq!(Format::Dos.to_buffer("a\r\nb\r\n".into()), "a\nb\n");
+    assert_eq!(Format::Mac.to_buffer("a\rb\r".into()), "a\nb\n");
+    assert_eq!(Format::Dos.to_file("a\nb\n".into()), "a\r\nb\r\n");
+}
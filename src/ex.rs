@@ -0,0 +1,199 @@
+/// Rows `start..=end` of the buffer an ex command works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    pub fn line(row: usize) -> Self {
+        Self {
+            start: row,
+            end: row,
+        }
+    }
+}
+
+/// What an address can look up in the buffer it's for, rows start at `0`.
+pub trait Lines {
+    fn current(&self) -> usize;
+    fn last(&self) -> usize;
+    fn mark(&self, name: char) -> Option<usize>;
+    /// First row after `from` matching `pattern`, or before it going backwards, wrapping around.
+    fn find(&self, pattern: &str, from: usize, forward: bool) -> Result<Option<usize>, String>;
+}
+
+fn take_number(src: &str) -> Option<(usize, &str)> {
+    let digits = src.chars().take_while(char::is_ascii_digit).count();
+    let number = src[..digits].parse::<usize>().ok()?;
+    Some((number, &src[digits..]))
+}
+
+/// Splits a `/pattern/` off `src`, `\/` is a `/` inside the pattern.
+fn take_pattern(src: &str, delim: char) -> (String, &str) {
+    let mut pattern = String::new();
+    let mut chars = src.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, next)) if next == delim => pattern.push(next),
+                Some((_, next)) => {
+                    pattern.push('\\');
+                    pattern.push(next);
+                }
+                None => pattern.push('\\'),
+            },
+            c if c == delim => return (pattern, &src[i + c.len_utf8()..]),
+            c => pattern.push(c),
+        }
+    }
+    (pattern, "")
+}
+
+/// Reads one address as a line number, `0` is the line before the first.
+///
+/// An address is `42`, `.`, `$`, `'a`, `/pattern/` or `?pattern?` followed by
+/// any number of `+n` and `-n` offsets, a lone offset counts from the current line.
+pub fn parse_address<'a>(
+    src: &'a str,
+    lines: &dyn Lines,
+) -> Result<Option<(usize, &'a str)>, String> {
+    let current = lines.current() + 1;
+    let mut chars = src.chars();
+    let (mut line, mut rest) = match chars.next() {
+        Some('.') => (current, chars.as_str()),
+        Some('$') => (lines.last() + 1, chars.as_str()),
+        Some('\'') => {
+            let name = chars.next().ok_or("Missing mark name")?;
+            let row = lines.mark(name).ok_or(format!("Mark not set: {name}"))?;
+            (row + 1, chars.as_str())
+        }
+        Some(delim @ ('/' | '?')) => {
+            let (pattern, rest) = take_pattern(chars.as_str(), delim);
+            let row = lines
+                .find(&pattern, lines.current(), delim == '/')?
+                .ok_or(format!("Pattern not found: {pattern}"))?;
+            (row + 1, rest)
+        }
+        Some('0'..='9') => take_number(src).ok_or("Invalid address")?,
+        Some('+' | '-') => (current, src),
+        _ => return Ok(None),
+    };
+    loop {
+        let mut chars = rest.chars();
+        let sign = match chars.next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => break,
+        };
+        let (offset, tail) = take_number(chars.as_str()).unwrap_or((1, chars.as_str()));
+        line = if sign > 0 {
+            line + offset
+        } else {
+            line.checked_sub(offset).ok_or("Invalid range")?
+        };
+        rest = tail;
+    }
+    Ok(Some((line, rest)))
+}
+
+/// Splits the range off the front of a command line, `%s/a/b/`, `3,$d` or `'<,'>y`.
+pub fn parse_range<'a>(
+    src: &'a str,
+    lines: &dyn Lines,
+) -> Result<(Option<LineRange>, &'a str), String> {
+    let src = src.trim_start();
+    if let Some(rest) = src.strip_prefix('%') {
+        let range = LineRange {
+            start: 0,
+            end: lines.last(),
+        };
+        return Ok((Some(range), rest));
+    }
+    let Some((start, rest)) = parse_address(src, lines)? else {
+        return Ok((None, src));
+    };
+    let (mut end, rest) = match rest.strip_prefix(',') {
+        Some(rest) => parse_address(rest, lines)?.ok_or(format!("Invalid range: {src}"))?,
+        None => (start, rest),
+    };
+    if start > end {
+        return Err("Backwards range given".into());
+    }
+    if end > lines.last() + 1 {
+        // `:20` on its own goes to the last line, `:20d` is an error.
+        if !rest.trim().is_empty() {
+            return Err("Invalid range".into());
+        }
+        end = lines.last() + 1;
+    }
+    let start = start.min(end);
+    let range = LineRange {
+        start: start.saturating_sub(1),
+        end: end.saturating_sub(1),
+    };
+    Ok((Some(range), rest))
+}
+
+/// Splits a command line into the command's name and what follows it, `s/a/b/` is `s` and `/a/b/`.
+pub fn split_command(src: &str) -> (&str, &str) {
+    let src = src.trim();
+    let len = src
+        .char_indices()
+        .find(|(_, c)| !c.is_ascii_alphabetic())
+        .map_or(src.len(), |(i, _)| i);
+    let (name, tail) = src.split_at(len);
+    (name, tail.trim_start())
+}
+
+#[cfg(test)]
+struct TestLines(Vec<&'static str>);
+
+#[cfg(test)]
+impl Lines for TestLines {
+    fn current(&self) -> usize {
+        4
+    }
+
+    fn last(&self) -> usize {
+        self.0.len() - 1
+    }
+
+    fn mark(&self, name: char) -> Option<usize> {
+        (name == 'a').then_some(2)
+    }
+
+    fn find(&self, pattern: &str, from: usize, forward: bool) -> Result<Option<usize>, String> {
+        let len = self.0.len();
+        let rows = (1..=len).map(|i| {
+            if forward {
+                (from + i) % len
+            } else {
+                (from + len * 2 - i) % len
+            }
+        });
+        Ok(rows.into_iter().find(|row| self.0[*row].contains(pattern)))
+    }
+}
+
+#[test]
+fn ranges() {
+    let lines = TestLines(vec!["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"]);
+    let parse = |src| parse_range(src, &lines);
+    let range = |start, end| Some(LineRange { start, end });
+    assert_eq!(parse("s/a/b/"), Ok((None, "s/a/b/")));
+    assert_eq!(parse("%s/a/b/"), Ok((range(0, 9), "s/a/b/")));
+    assert_eq!(parse("2,$s"), Ok((range(1, 9), "s")));
+    assert_eq!(parse(".s"), Ok((Some(LineRange::line(4)), "s")));
+    assert_eq!(parse(".+2,$-1d"), Ok((range(6, 8), "d")));
+    assert_eq!(parse("-,+d"), Ok((range(3, 5), "d")));
+    assert_eq!(parse("'a,/h/y"), Ok((range(2, 7), "y")));
+    assert_eq!(parse("?b?"), Ok((Some(LineRange::line(1)), "")));
+    assert!(parse("5,2s").is_err());
+    assert!(parse("20s").is_err());
+    assert_eq!(parse("20"), Ok((range(9, 9), "")));
+    assert!(parse("'bd").is_err());
+    assert_eq!(parse_address("0", &lines), Ok(Some((0, ""))));
+    assert_eq!(split_command("s/a/b/g"), ("s", "/a/b/g"));
+    assert_eq!(split_command("w foo.txt"), ("w", "foo.txt"));
+}
@@ -0,0 +1,257 @@
+use std::ops::Range;
+
+use revi_ui::grid::width;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Char column each grapheme of `line` starts at.
+fn starts(line: &str) -> impl Iterator<Item = usize> + '_ {
+    line.graphemes(true).scan(0, |col, symbol| {
+        let start = *col;
+        *col += symbol.chars().count();
+        Some(start)
+    })
+}
+
+/// Each grapheme of `line` with the char column it starts at and the screen
+/// columns it takes, buffer columns count chars but the cursor steps a grapheme
+/// at a time. A tab reaches to the next multiple of `tabstop`.
+fn graphemes(line: &str, tabstop: usize) -> impl Iterator<Item = (&str, usize, usize)> + '_ {
+    let tabstop = tabstop.max(1);
+    line.graphemes(true)
+        .scan((0, 0), move |(col, shown), symbol| {
+            let start = *col;
+            let span = if symbol == "\t" {
+                tabstop - *shown % tabstop
+            } else {
+                width(symbol)
+            };
+            *col += symbol.chars().count();
+            *shown += span;
+            Some((symbol, start, span))
+        })
+}
+
+/// Start of the grapheme char `col` is part of, a column past the end stays put.
+pub fn start(line: &str, col: usize) -> usize {
+    if col >= line.chars().count() {
+        return col;
+    }
+    starts(line)
+        .take_while(|start| *start <= col)
+        .last()
+        .unwrap_or(0)
+}
+
+/// Start of the grapheme after the one at `col`, the end of the line after the last.
+pub fn next(line: &str, col: usize) -> usize {
+    starts(line)
+        .find(|start| *start > col)
+        .unwrap_or_else(|| line.chars().count().max(col))
+}
+
+/// Start of the grapheme before the one at `col`.
+pub fn prev(line: &str, col: usize) -> usize {
+    starts(line)
+        .take_while(|start| *start < col)
+        .last()
+        .unwrap_or(0)
+}
+
+/// Screen column char `col` is drawn at.
+pub fn display_col(line: &str, col: usize, tabstop: usize) -> usize {
+    graphemes(line, tabstop)
+        .take_while(|(_, start, _)| *start < col)
+        .map(|(_, _, span)| span)
+        .sum()
+}
+
+/// Screen columns the grapheme at `col` takes, the cell past the end takes one.
+pub fn width_at(line: &str, col: usize, tabstop: usize) -> usize {
+    graphemes(line, tabstop)
+        .find(|(_, start, _)| *start == col)
+        .map_or(1, |(_, _, span)| span)
+}
+
+/// Char column of the grapheme covering screen column `x`, the end of the
+/// line when `x` is past it.
+pub fn col_at(line: &str, x: usize, tabstop: usize) -> usize {
+    let mut used = 0;
+    for (_, start, span) in graphemes(line, tabstop) {
+        used += span;
+        if used > x {
+            return start;
+        }
+    }
+    line.chars().count()
+}
+
+/// End of the graphemes from `col` on that fit in `width` screen columns.
+pub fn fit(line: &str, col: usize, width: usize, tabstop: usize) -> usize {
+    let mut used = 0;
+    for (_, start, span) in graphemes(line, tabstop).skip_while(|(_, start, _)| *start < col) {
+        used += span;
+        if used > width {
+            return start;
+        }
+    }
+    line.chars().count().max(col)
+}
+
+/// Leftmost column a window `width` wide can scroll to and still show all of
+/// the grapheme at `col`, as close to `left` as it can stay.
+pub fn scroll_for(line: &str, col: usize, left: usize, width: usize, tabstop: usize) -> usize {
+    if col < left {
+        return col;
+    }
+    // Screen column each grapheme from `left` up to `col` starts at.
+    let mut shown = 0;
+    let mut starts = Vec::new();
+    for (_, start, span) in graphemes(line, tabstop).take_while(|(_, start, _)| *start < col) {
+        if start >= left {
+            starts.push((start, shown));
+        }
+        shown += span;
+    }
+    let end = shown + width_at(line, col, tabstop);
+    match starts.iter().position(|(_, shown)| end - shown <= width) {
+        Some(0) => left,
+        Some(idx) => starts[idx].0,
+        None => col,
+    }
+}
+
+/// Columns `cols` of `line` as they're drawn, tabs turned into the spaces they
+/// reach over. Also hands back where in that text each column of `cols` starts,
+/// one more for its end.
+pub fn expand(line: &str, cols: Range<usize>, tabstop: usize) -> (String, Vec<usize>) {
+    let mut text = String::new();
+    let mut offsets = Vec::with_capacity(cols.len() + 1);
+    let mut len = 0;
+    for (symbol, start, span) in graphemes(line, tabstop) {
+        if start < cols.start {
+            continue;
+        }
+        if start >= cols.end {
+            break;
+        }
+        if symbol == "\t" {
+            offsets.push(len);
+            text.extend(std::iter::repeat_n(' ', span));
+            len += span;
+            continue;
+        }
+        for c in symbol.chars() {
+            offsets.push(len);
+            text.push(c);
+            len += 1;
+        }
+    }
+    offsets.push(len);
+    (text, offsets)
+}
+
+/// Whitespace reaching from screen column `from` to `to`, spaces when `expand`
+/// is set and as many tabs as fit otherwise.
+pub fn fill(from: usize, to: usize, tabstop: usize, expand: bool) -> String {
+    let tabstop = tabstop.max(1);
+    let mut col = from;
+    let mut text = String::new();
+    while !expand && (col / tabstop + 1) * tabstop <= to {
+        text.push('\t');
+        col = (col / tabstop + 1) * tabstop;
+    }
+    text.extend(std::iter::repeat_n(' ', to.saturating_sub(col)));
+    text
+}
+
+/// Runs of whitespace in `line` that hold a tab, or any run of two or more with
+/// `spaces`, redone with [`fill`] for `tabstop` so they end where they did
+/// under `old`. Ranges are char columns, only runs that change are given.
+pub fn retab(
+    line: &str,
+    old: usize,
+    tabstop: usize,
+    expand: bool,
+    spaces: bool,
+) -> Vec<(Range<usize>, String)> {
+    let mut found = Vec::new();
+    let mut run: Option<(usize, usize)> = None;
+    let mut shown = 0;
+    let graphemes = graphemes(line, old).chain(std::iter::once(("", line.chars().count(), 0)));
+    for (symbol, start, span) in graphemes {
+        if symbol == " " || symbol == "\t" {
+            run.get_or_insert((start, shown));
+        } else if let Some((from, from_shown)) = run.take() {
+            let old_text = line
+                .chars()
+                .skip(from)
+                .take(start - from)
+                .collect::<String>();
+            let text = fill(from_shown, shown, tabstop, expand);
+            let convert = old_text.contains('\t') || (spaces && old_text.len() > 1);
+            if convert && text != old_text {
+                found.push((from..start, text));
+            }
+        }
+        shown += span;
+    }
+    found
+}
+
+#[test]
+fn grapheme_columns() {
+    // `e` and a combining acute make one grapheme, `中` is two columns wide.
+    let line = "e\u{301}中x";
+    assert_eq!(next(line, 0), 2);
+    assert_eq!(next(line, 2), 3);
+    assert_eq!(next(line, 3), 4);
+    assert_eq!(prev(line, 3), 2);
+    assert_eq!(prev(line, 2), 0);
+    assert_eq!(start(line, 1), 0);
+    assert_eq!(display_col(line, 3, 8), 3);
+    assert_eq!(width_at(line, 2, 8), 2);
+    assert_eq!(col_at(line, 2, 8), 2);
+    assert_eq!(col_at(line, 3, 8), 3);
+    assert_eq!(col_at(line, 9, 8), 4);
+    assert_eq!(fit(line, 0, 2, 8), 2);
+    assert_eq!(fit(line, 2, 9, 8), 4);
+    assert_eq!(scroll_for(line, 3, 0, 2, 8), 3);
+    assert_eq!(scroll_for(line, 2, 0, 2, 8), 2);
+    assert_eq!(scroll_for(line, 2, 0, 3, 8), 0);
+    assert_eq!(scroll_for(&"a".repeat(100), 99, 5, 10, 8), 90);
+}
+
+#[test]
+fn tabs_reach_the_next_tabstop() {
+    let line = "a\tb\t\tc";
+    assert_eq!(display_col(line, 1, 4), 1);
+    assert_eq!(width_at(line, 1, 4), 3);
+    assert_eq!(display_col(line, 2, 4), 4);
+    assert_eq!(display_col(line, 5, 4), 12);
+    assert_eq!(col_at(line, 2, 4), 1);
+    assert_eq!(col_at(line, 9, 4), 4);
+    assert_eq!(fit(line, 0, 5, 4), 3);
+    let (text, offsets) = expand(line, 1..4, 4);
+    assert_eq!(text, "   b   ");
+    assert_eq!(offsets, vec![0, 3, 4, 7]);
+}
+
+#[test]
+fn retab_keeps_columns() {
+    assert_eq!(fill(1, 9, 4, false), "\t\t ");
+    assert_eq!(fill(1, 9, 4, true), " ".repeat(8));
+    let line = "\tx  y\t z";
+    assert_eq!(
+        retab(line, 8, 4, false, false),
+        vec![(0..1, "\t\t".to_string())]
+    );
+    assert_eq!(
+        retab(line, 8, 8, true, false),
+        vec![(0..1, " ".repeat(8)), (5..7, " ".repeat(5))]
+    );
+    assert_eq!(
+        retab("    x", 8, 4, false, true),
+        vec![(0..4, "\t".to_string())]
+    );
+    assert!(retab("    x", 8, 4, false, false).is_empty());
+}
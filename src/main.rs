@@ -6,36 +6,85 @@ const AUTHOR: &str = "
 Email: cowboy8625@protonmail.com
 ";
 
+mod autocmd;
 mod buffer;
 mod commandline;
+mod encoding;
+mod ex;
+mod grapheme;
 mod map_keys;
 mod message;
+mod motion;
+mod options;
 mod parse_keys;
+mod register;
+mod script;
+mod search;
 mod state;
+mod substitute;
 mod trie;
+mod undo;
+mod user_command;
+mod window;
+mod wrap;
 
 use buffer::Buffer;
 use clap::Parser;
 use message::Message;
-use revi_ui::{application::App, layout::Pos, SetCursorStyle, Subscription};
+use revi_ui::{application::App, layout::Pos, SetCursorStyle, Subscription, Task};
+use script::Script;
 use state::State;
 use std::cell::RefCell;
+use std::path::PathBuf;
 use std::rc::Rc;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     Insert,
     Command,
+    /// The `/` and `?` prompt.
+    Search,
     Normal,
+    Visual,
+    VisualLine,
+    VisualBlock,
+}
+
+impl Mode {
+    pub fn is_visual(&self) -> bool {
+        matches!(self, Self::Visual | Self::VisualLine | Self::VisualBlock)
+    }
+
+    /// Modes typing into the command bar instead of a buffer.
+    pub fn is_command_line(&self) -> bool {
+        matches!(self, Self::Command | Self::Search)
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = match self {
+            Self::Normal => "Normal",
+            Self::Command => "Command",
+            Self::Search => "Search",
+            Self::Insert => "Insert",
+            Self::Visual => "Visual",
+            Self::VisualLine => "V-Line",
+            Self::VisualBlock => "V-Block",
+        };
+        write!(f, "{mode}")
+    }
 }
 
 #[derive(Debug)]
 pub struct Settings {
     pub buffers: Vec<Buffer>,
+    /// Script run at startup, `None` skips it.
+    pub config: Option<PathBuf>,
 }
 
 enum Revi {
-    Editor(Rc<RefCell<State>>),
+    Editor(Rc<RefCell<State>>, Script),
 }
 
 impl App for Revi {
@@ -44,54 +93,74 @@ impl App for Revi {
     type Message = Message;
 
     fn new(settings: Self::Settings) -> Self {
+        let config = settings.config.clone();
+        let files = settings.buffers.len();
         let state = State::new(settings);
         let state = Rc::new(RefCell::new(state));
-        Self::Editor(state)
+        let mut script = Script::new(state.clone());
+        if let Some(path) = config {
+            script.load(&path);
+        }
+        for id in 0..files {
+            state.borrow_mut().buffer_loaded(id);
+        }
+        script.run_pending();
+        Self::Editor(state, script)
     }
 
-    fn update(&mut self, message: Self::Message) -> Option<Self::Message> {
+    fn update(&mut self, message: Self::Message) -> Task<Self::Message> {
         match self {
-            Self::Editor(state) => state.borrow_mut().update(message),
+            Self::Editor(state, script) => {
+                let next = state.borrow_mut().handle(message);
+                script.run_pending();
+                state.borrow_mut().task(next)
+            }
         }
     }
 
     fn view(&self) -> revi_ui::widget::BoxWidget {
         match self {
-            Self::Editor(ref state) => state.borrow().view(),
+            Self::Editor(ref state, _) => state.borrow().view(),
         }
     }
 
     fn cursor_pos(&self) -> Option<Pos> {
         match self {
-            Self::Editor(state) => state.borrow().cursor_pos(),
+            Self::Editor(state, _) => state.borrow().cursor_pos(),
         }
     }
     fn cursor_shape(&self) -> Option<SetCursorStyle> {
         match self {
-            Self::Editor(state) => state.borrow().cursor_shape(),
+            Self::Editor(state, _) => state.borrow().cursor_shape(),
         }
     }
 
     fn subscription(&self) -> Subscription<Self::Message> {
         match self {
-            Self::Editor(state) => state.borrow().subscription(),
+            Self::Editor(state, _) => state.borrow().subscription(),
         }
     }
 
     fn quit(&self) -> bool {
         match self {
-            Self::Editor(state) => state.borrow().quit(),
+            Self::Editor(state, _) => state.borrow().quit(),
         }
     }
 }
 
 fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let files = commandline::Cli::parse().files;
-    let buffers = files
+    let cli = commandline::Cli::parse();
+    let buffers = cli
+        .files
         .iter()
-        .map(|name| Buffer::from_path(name))
+        .map(|name| Buffer::from_path(name, None))
         .collect::<Vec<_>>();
-    let settings = Settings { buffers };
+    let config = match cli.config {
+        Some(path) if path.as_os_str() == "NONE" => None,
+        Some(path) => Some(path),
+        None => script::config_path().filter(|path| path.exists()),
+    };
+    let settings = Settings { buffers, config };
     Revi::new(settings).run()?;
     Ok(())
 }
@@ -1,5 +1,7 @@
 use super::{Message, Mode};
-use revi_ui::{string_to_keys, Keys};
+use crate::motion::{Motion, Operator};
+use crate::window::Direction;
+use revi_ui::{layout::Stack, string_to_keys, Keys};
 
 #[derive(Debug)]
 enum MapNode {
@@ -53,6 +55,19 @@ impl KeyMap {
         })
     }
 
+    /// Whether a mapping longer than `keys` starts with them.
+    fn has_longer(&self, keys: &[Keys]) -> bool {
+        let Some((first, rest)) = keys.split_first() else {
+            return !self.mappings.is_empty();
+        };
+        self.mappings.iter().any(|node| match node {
+            MapNode::Map(key, keymap) | MapNode::Middle(key, keymap, _) if key == first => {
+                keymap.has_longer(rest)
+            }
+            _ => false,
+        })
+    }
+
     // fn _is_last_branch_command(&self, _: &[Keys]) -> bool {
     //     todo!()
     // }
@@ -81,6 +96,15 @@ impl KeyMap {
         };
         for node in self.mappings.iter_mut() {
             match node {
+                MapNode::Map(k, map) if k == key && keys.len() == 1 => {
+                    let map = std::mem::replace(map, KeyMap::new());
+                    *node = MapNode::Middle(*k, map, command);
+                    return;
+                }
+                MapNode::Middle(k, _, cmd) if k == key && keys.len() == 1 => {
+                    *cmd = command;
+                    return;
+                }
                 MapNode::Map(k, map) if k == key => return map.insert(&keys[1..], command),
                 MapNode::Middle(k, map, _) if k == key => return map.insert(&keys[1..], command),
                 MapNode::End(k, cmd) if k == key && keys.len() == 1 => {
@@ -113,16 +137,80 @@ impl KeyMap {
     }
 }
 
+/// A mapping made by a script or `:map`, these are looked up before the built in ones.
+#[derive(Debug, Clone)]
+pub struct UserMap {
+    keys: Vec<Keys>,
+    pub lhs: String,
+    /// What it does, as `:map` shows it.
+    pub rhs: String,
+    pub noremap: bool,
+    pub message: Message,
+}
+
+/// Outcome of looking the pending keys up in the user mappings.
+#[derive(Debug)]
+pub enum UserLookup {
+    /// A longer mapping starts with the keys.
+    Pending,
+    /// The longest mapping the keys start with, and how many keys it takes.
+    Found(Message, usize),
+    NoMatch,
+}
+
+/// What a `:map` family command does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapKind {
+    Map,
+    NoRemap,
+    Unmap,
+}
+
+/// Modes and kind of `:nmap`, `:vnoremap`, `:iunmap` and the rest, the ones
+/// without a mode letter are for normal and visual mode.
+pub fn map_command(name: &str) -> Option<(&'static [Mode], MapKind)> {
+    let (prefix, kind) = if let Some(prefix) = name.strip_suffix("noremap") {
+        (prefix, MapKind::NoRemap)
+    } else if let Some(prefix) = name.strip_suffix("unmap") {
+        (prefix, MapKind::Unmap)
+    } else {
+        (name.strip_suffix("map")?, MapKind::Map)
+    };
+    let modes: &'static [Mode] = match prefix {
+        "" => &[Mode::Normal, Mode::Visual],
+        "n" => &[Mode::Normal],
+        "v" => &[Mode::Visual],
+        "i" => &[Mode::Insert],
+        "c" => &[Mode::Command],
+        _ => return None,
+    };
+    Some((modes, kind))
+}
+
 #[derive(Debug)]
 pub struct Mapper {
     nmaps: KeyMap,
     imaps: KeyMap,
     cmaps: KeyMap,
+    vmaps: KeyMap,
+    smaps: KeyMap,
+    user_nmaps: Vec<UserMap>,
+    user_imaps: Vec<UserMap>,
+    /// Also used at the `/` and `?` prompt.
+    user_cmaps: Vec<UserMap>,
+    user_vmaps: Vec<UserMap>,
 }
 
 impl Default for Mapper {
     fn default() -> Self {
-        Self::new().build_normal().build_insert().build_command()
+        Self::new()
+            .build_normal()
+            .build_marks()
+            .build_windows()
+            .build_insert()
+            .build_command()
+            .build_visual()
+            .build_search()
     }
 }
 
@@ -132,6 +220,12 @@ impl Mapper {
             nmaps: KeyMap::new(),
             imaps: KeyMap::new(),
             cmaps: KeyMap::new(),
+            vmaps: KeyMap::new(),
+            smaps: KeyMap::new(),
+            user_nmaps: Vec::new(),
+            user_imaps: Vec::new(),
+            user_cmaps: Vec::new(),
+            user_vmaps: Vec::new(),
         }
     }
 
@@ -140,6 +234,8 @@ impl Mapper {
             Mode::Normal => &self.nmaps,
             Mode::Insert => &self.imaps,
             Mode::Command => &self.cmaps,
+            Mode::Search => &self.smaps,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => &self.vmaps,
         }
     }
 
@@ -148,6 +244,8 @@ impl Mapper {
             Mode::Normal => &mut self.nmaps,
             Mode::Insert => &mut self.imaps,
             Mode::Command => &mut self.cmaps,
+            Mode::Search => &mut self.smaps,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => &mut self.vmaps,
         }
     }
 
@@ -161,6 +259,12 @@ impl Mapper {
         self.get_map(mode).is_possible_command(keys)
     }
 
+    /// Whether `keys` are a whole mapping and the start of a longer one, these
+    /// wait `timeoutlen` for the next key.
+    pub fn is_ambiguous(&self, mode: &Mode, keys: &[Keys]) -> bool {
+        !keys.is_empty() && self.get_map(mode).has_longer(keys)
+    }
+
     #[must_use]
     pub fn get_mapping(&self, mode: &Mode, keys: &[Keys]) -> Option<Message> {
         self.get_map(mode).get(keys)
@@ -173,8 +277,71 @@ impl Mapper {
         self
     }
 
-    pub fn _nmap(&mut self, keys: &str, message: Message) {
-        self.nmaps.insert(&string_to_keys(keys), message);
+    pub fn user_maps(&self, mode: &Mode) -> &[UserMap] {
+        match mode {
+            Mode::Normal => &self.user_nmaps,
+            Mode::Insert => &self.user_imaps,
+            Mode::Command | Mode::Search => &self.user_cmaps,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => &self.user_vmaps,
+        }
+    }
+
+    fn user_maps_mut(&mut self, mode: Mode) -> &mut Vec<UserMap> {
+        match mode {
+            Mode::Normal => &mut self.user_nmaps,
+            Mode::Insert => &mut self.user_imaps,
+            Mode::Command | Mode::Search => &mut self.user_cmaps,
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => &mut self.user_vmaps,
+        }
+    }
+
+    /// Maps `lhs` in `mode`, replacing any mapping it already had.
+    pub fn map(&mut self, mode: Mode, lhs: &str, rhs: &str, noremap: bool, message: Message) {
+        let map = UserMap {
+            keys: string_to_keys(lhs),
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+            noremap,
+            message,
+        };
+        let maps = self.user_maps_mut(mode);
+        maps.retain(|old| old.keys != map.keys);
+        maps.push(map);
+    }
+
+    /// Removes the mapping of `lhs`, returns whether there was one.
+    pub fn unmap(&mut self, mode: Mode, lhs: &str) -> bool {
+        let keys = string_to_keys(lhs);
+        let maps = self.user_maps_mut(mode);
+        let len = maps.len();
+        maps.retain(|map| map.keys != keys);
+        maps.len() != len
+    }
+
+    pub fn user_lookup(&self, mode: &Mode, keys: &[Keys]) -> UserLookup {
+        let maps = self.user_maps(mode);
+        if keys.is_empty() {
+            return UserLookup::NoMatch;
+        }
+        if maps
+            .iter()
+            .any(|map| map.keys.len() > keys.len() && map.keys.starts_with(keys))
+        {
+            return UserLookup::Pending;
+        }
+        self.user_prefix(mode, keys)
+            .map_or(UserLookup::NoMatch, |(message, len)| {
+                UserLookup::Found(message, len)
+            })
+    }
+
+    /// The longest user mapping `keys` start with and how many keys it takes.
+    pub fn user_prefix(&self, mode: &Mode, keys: &[Keys]) -> Option<(Message, usize)> {
+        self.user_maps(mode)
+            .iter()
+            .filter(|map| keys.starts_with(&map.keys))
+            .max_by_key(|map| map.keys.len())
+            .map(|map| (map.message.clone(), map.keys.len()))
     }
 
     // pub fn nmap_from_str(&mut self, keys: &str, command: &str) {
@@ -204,23 +371,80 @@ impl Mapper {
         self.with_mapping(Mode::Normal, "j", Message::CursorDown)
             .with_mapping(Mode::Normal, "<down>", Message::CursorDown)
             .with_mapping(Mode::Normal, "k", Message::CursorUp)
-            .with_mapping(Mode::Normal, "up", Message::CursorUp)
+            .with_mapping(Mode::Normal, "<up>", Message::CursorUp)
+            .with_mapping(Mode::Normal, "gj", Message::CursorScreenDown)
+            .with_mapping(Mode::Normal, "g<down>", Message::CursorScreenDown)
+            .with_mapping(Mode::Normal, "gk", Message::CursorScreenUp)
+            .with_mapping(Mode::Normal, "g<up>", Message::CursorScreenUp)
             .with_mapping(Mode::Normal, "h", Message::CursorLeft)
             .with_mapping(Mode::Normal, "<left>", Message::CursorLeft)
             .with_mapping(Mode::Normal, "l", Message::CursorRight)
             .with_mapping(Mode::Normal, "<right>", Message::CursorRight)
             .with_mapping(Mode::Normal, ":", Message::ChangeMode(Mode::Command))
             .with_mapping(Mode::Normal, "i", Message::ChangeMode(Mode::Insert))
-            .with_mapping(Mode::Normal, "x", Message::Delete)
-            .with_mapping(Mode::Normal, "<delete>", Message::Delete)
-            // .with_mapping(Mode::Normal, "dd", DeleteLine)
+            .with_mapping(Mode::Normal, "v", Message::ChangeMode(Mode::Visual))
+            .with_mapping(Mode::Normal, "V", Message::ChangeMode(Mode::VisualLine))
+            .with_mapping(
+                Mode::Normal,
+                "<c-v>",
+                Message::ChangeMode(Mode::VisualBlock),
+            )
+            .with_mapping(Mode::Normal, "x", Message::DeleteRange(Motion::Right, None))
+            .with_mapping(
+                Mode::Normal,
+                "<delete>",
+                Message::DeleteRange(Motion::Right, None),
+            )
             .with_mapping(Mode::Normal, "<home>", Message::CursorHome)
             .with_mapping(Mode::Normal, "0", Message::CursorHome)
-            // .with_mapping(Mode::Normal, "gg", CursorTopOfBuffer)
-            // .with_mapping(Mode::Normal, "G", CursorToBottomOfBuffer)
+            .with_mapping(Mode::Normal, "gg", Message::Motion(Motion::FileStart, None))
+            .with_mapping(Mode::Normal, "G", Message::Motion(Motion::FileEnd, None))
+            .with_mapping(
+                Mode::Normal,
+                "^",
+                Message::Motion(Motion::FirstNonBlank, None),
+            )
+            .with_mapping(
+                Mode::Normal,
+                "w",
+                Message::Motion(Motion::WordForward(false), None),
+            )
+            .with_mapping(
+                Mode::Normal,
+                "W",
+                Message::Motion(Motion::WordForward(true), None),
+            )
+            .with_mapping(
+                Mode::Normal,
+                "b",
+                Message::Motion(Motion::WordBackward(false), None),
+            )
+            .with_mapping(
+                Mode::Normal,
+                "B",
+                Message::Motion(Motion::WordBackward(true), None),
+            )
+            .with_mapping(
+                Mode::Normal,
+                "e",
+                Message::Motion(Motion::WordEnd(false), None),
+            )
+            .with_mapping(
+                Mode::Normal,
+                "E",
+                Message::Motion(Motion::WordEnd(true), None),
+            )
             .with_mapping(Mode::Normal, "<end>", Message::CursorEnd)
             .with_mapping(Mode::Normal, "$", Message::CursorEnd)
             .with_mapping(Mode::Normal, "A", Message::InsertAtEnd)
+            .with_mapping(Mode::Normal, "p", Message::PutAfter(None))
+            .with_mapping(Mode::Normal, "P", Message::PutBefore(None))
+            .with_mapping(Mode::Normal, "u", Message::Undo)
+            .with_mapping(Mode::Normal, "<c-r>", Message::Redo)
+            .with_mapping(Mode::Normal, "/", Message::SearchPrompt(true))
+            .with_mapping(Mode::Normal, "?", Message::SearchPrompt(false))
+            .with_mapping(Mode::Normal, "n", Message::SearchNext(false))
+            .with_mapping(Mode::Normal, "N", Message::SearchNext(true))
         // .with_mapping(Mode::Normal, "<C-y>", ScrollUp, CursorDown)
         // .with_mapping(Mode::Normal, "<C-e>", ScrollDown, CursorUp)
         // .with_mapping(Mode::Normal, "<C-u>", ScrollUp)
@@ -235,37 +459,183 @@ impl Mapper {
         //         "O",
         //         CursorHome, NewLine, ChangeMode(Mode::Insert), CursorUp,
         //     )
-        //     .with_mapping(Mode::Normal, "^", FirstCharInLine)
         //     .with_mapping(
         //         Mode::Normal,
         //         "I",
         //         FirstCharInLine, ChangeMode(Mode::Insert),
         //     )
-        //     .with_mapping(Mode::Normal, "w", MoveForwardByWord)
-        //     .with_mapping(Mode::Normal, "b", MoveBackwardByWord)
         //     .with_mapping(Mode::Normal, "<C-w><C-w>", NextWindow)
         //     .with_mapping(
         //         Mode::Normal,
         //         "<enter>",
         //         ExecuteCommandLine, ExitCommandMode,
         //     )
-        //     .with_mapping(Mode::Normal, "yy", YankLine)
-        //     .with_mapping(Mode::Normal, "p", Paste)
-        //     .with_mapping(Mode::Normal, "P", PasteBack)
-        //     .with_mapping(Mode::Normal, "u", Undo)
         //     .with_mapping(Mode::Normal, "<space>a", CursorRight)
     }
 
     fn build_insert(self) -> Self {
         self.with_mapping(Mode::Insert, "<esc>", Message::ChangeMode(Mode::Normal))
             .with_mapping(Mode::Insert, "<backspace>", Message::BackSpace)
+            .with_mapping(Mode::Insert, "<delete>", Message::Delete)
             .with_mapping(Mode::Insert, "<up>", Message::CursorUp)
             .with_mapping(Mode::Insert, "<down>", Message::CursorDown)
             .with_mapping(Mode::Insert, "<left>", Message::CursorLeft)
             .with_mapping(Mode::Insert, "<right>", Message::CursorRight)
             .with_mapping(Mode::Insert, "<home>", Message::CursorHome)
             .with_mapping(Mode::Insert, "<end>", Message::CursorEnd)
-        //     .with_mapping(Mode::Insert, "<tab>", InsertTab)
+            .with_mapping(Mode::Insert, "<tab>", Message::InsertTab)
+    }
+
+    fn build_marks(self) -> Self {
+        let mapper = ('a'..='z').fold(self, |mapper, name| {
+            mapper.with_mapping(Mode::Normal, &format!("m{name}"), Message::SetMark(name))
+        });
+        ('a'..='z').chain(['<', '>']).fold(mapper, |mapper, name| {
+            let key = match name {
+                '<' => "<lt>".to_string(),
+                name => name.to_string(),
+            };
+            mapper
+                .with_mapping(
+                    Mode::Normal,
+                    &format!("'{key}"),
+                    Message::JumpToMark(name, true),
+                )
+                .with_mapping(
+                    Mode::Normal,
+                    &format!("`{key}"),
+                    Message::JumpToMark(name, false),
+                )
+        })
+    }
+
+    fn build_windows(self) -> Self {
+        let focus = Message::FocusWindow;
+        self.with_mapping(
+            Mode::Normal,
+            "<c-w>s",
+            Message::SplitWindow(Stack::Vertically),
+        )
+        .with_mapping(
+            Mode::Normal,
+            "<c-w>v",
+            Message::SplitWindow(Stack::Horizontally),
+        )
+        .with_mapping(Mode::Normal, "<c-w>h", focus(Direction::Left))
+        .with_mapping(Mode::Normal, "<c-w>j", focus(Direction::Down))
+        .with_mapping(Mode::Normal, "<c-w>k", focus(Direction::Up))
+        .with_mapping(Mode::Normal, "<c-w>l", focus(Direction::Right))
+        .with_mapping(Mode::Normal, "<c-w><left>", focus(Direction::Left))
+        .with_mapping(Mode::Normal, "<c-w><down>", focus(Direction::Down))
+        .with_mapping(Mode::Normal, "<c-w><up>", focus(Direction::Up))
+        .with_mapping(Mode::Normal, "<c-w><right>", focus(Direction::Right))
+        .with_mapping(Mode::Normal, "<c-w>w", Message::NextWindow)
+        .with_mapping(Mode::Normal, "<c-w><c-w>", Message::NextWindow)
+        .with_mapping(Mode::Normal, "<c-w>q", Message::CloseWindow)
+        .with_mapping(Mode::Normal, "<c-w>c", Message::CloseWindow)
+        .with_mapping(Mode::Normal, "<c-w>=", Message::EqualizeWindows)
+        .with_mapping(
+            Mode::Normal,
+            "<c-w>+",
+            Message::ResizeWindow(Stack::Vertically, 1),
+        )
+        .with_mapping(
+            Mode::Normal,
+            "<c-w>-",
+            Message::ResizeWindow(Stack::Vertically, -1),
+        )
+        .with_mapping(
+            Mode::Normal,
+            "<c-w>>",
+            Message::ResizeWindow(Stack::Horizontally, 1),
+        )
+        .with_mapping(
+            Mode::Normal,
+            "<c-w><lt>",
+            Message::ResizeWindow(Stack::Horizontally, -1),
+        )
+    }
+
+    fn build_visual(self) -> Self {
+        let op = Message::VisualOperator;
+        self.with_mapping(Mode::Visual, "<esc>", Message::ChangeMode(Mode::Normal))
+            .with_mapping(Mode::Visual, ":", Message::ChangeMode(Mode::Command))
+            .with_mapping(Mode::Visual, "v", Message::ChangeMode(Mode::Visual))
+            .with_mapping(Mode::Visual, "V", Message::ChangeMode(Mode::VisualLine))
+            .with_mapping(
+                Mode::Visual,
+                "<c-v>",
+                Message::ChangeMode(Mode::VisualBlock),
+            )
+            .with_mapping(Mode::Visual, "j", Message::CursorDown)
+            .with_mapping(Mode::Visual, "<down>", Message::CursorDown)
+            .with_mapping(Mode::Visual, "k", Message::CursorUp)
+            .with_mapping(Mode::Visual, "<up>", Message::CursorUp)
+            .with_mapping(Mode::Visual, "gj", Message::CursorScreenDown)
+            .with_mapping(Mode::Visual, "g<down>", Message::CursorScreenDown)
+            .with_mapping(Mode::Visual, "gk", Message::CursorScreenUp)
+            .with_mapping(Mode::Visual, "g<up>", Message::CursorScreenUp)
+            .with_mapping(Mode::Visual, "h", Message::CursorLeft)
+            .with_mapping(Mode::Visual, "<left>", Message::CursorLeft)
+            .with_mapping(Mode::Visual, "l", Message::CursorRight)
+            .with_mapping(Mode::Visual, "<right>", Message::CursorRight)
+            .with_mapping(Mode::Visual, "0", Message::CursorHome)
+            .with_mapping(Mode::Visual, "<home>", Message::CursorHome)
+            .with_mapping(Mode::Visual, "$", Message::CursorEnd)
+            .with_mapping(Mode::Visual, "<end>", Message::CursorEnd)
+            .with_mapping(
+                Mode::Visual,
+                "^",
+                Message::Motion(Motion::FirstNonBlank, None),
+            )
+            .with_mapping(Mode::Visual, "gg", Message::Motion(Motion::FileStart, None))
+            .with_mapping(Mode::Visual, "G", Message::Motion(Motion::FileEnd, None))
+            .with_mapping(
+                Mode::Visual,
+                "w",
+                Message::Motion(Motion::WordForward(false), None),
+            )
+            .with_mapping(
+                Mode::Visual,
+                "W",
+                Message::Motion(Motion::WordForward(true), None),
+            )
+            .with_mapping(
+                Mode::Visual,
+                "b",
+                Message::Motion(Motion::WordBackward(false), None),
+            )
+            .with_mapping(
+                Mode::Visual,
+                "B",
+                Message::Motion(Motion::WordBackward(true), None),
+            )
+            .with_mapping(
+                Mode::Visual,
+                "e",
+                Message::Motion(Motion::WordEnd(false), None),
+            )
+            .with_mapping(
+                Mode::Visual,
+                "E",
+                Message::Motion(Motion::WordEnd(true), None),
+            )
+            .with_mapping(Mode::Visual, "o", Message::SwapAnchor)
+            .with_mapping(Mode::Visual, "d", op(Operator::Delete))
+            .with_mapping(Mode::Visual, "x", op(Operator::Delete))
+            .with_mapping(Mode::Visual, "<delete>", op(Operator::Delete))
+            .with_mapping(Mode::Visual, "y", op(Operator::Yank))
+            .with_mapping(Mode::Visual, "c", op(Operator::Change))
+            .with_mapping(Mode::Visual, "s", op(Operator::Change))
+            .with_mapping(Mode::Visual, ">", op(Operator::ShiftRight))
+            .with_mapping(Mode::Visual, "<lt>", op(Operator::ShiftLeft))
+            .with_mapping(Mode::Visual, "~", op(Operator::ToggleCase))
+    }
+
+    fn build_search(self) -> Self {
+        self.with_mapping(Mode::Search, "<esc>", Message::CancelSearch)
+            .with_mapping(Mode::Search, "<enter>", Message::ExecuteSearch)
+            .with_mapping(Mode::Search, "<backspace>", Message::BackSpace)
     }
 
     fn build_command(self) -> Self {
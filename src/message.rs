@@ -6,6 +6,8 @@ use revi_ui::{
     Color, Keys,
 };
 
+use crate::motion::{Motion, Operator};
+use crate::window::Direction;
 use crate::Mode;
 
 #[allow(clippy::enum_variant_names)]
@@ -13,6 +15,10 @@ use crate::Mode;
 pub enum Message {
     CursorDown,
     CursorUp,
+    /// `gj`, a row down the screen when lines wrap.
+    CursorScreenDown,
+    /// `gk`
+    CursorScreenUp,
     CursorLeft,
     CursorRight,
     CursorHome,
@@ -22,10 +28,56 @@ pub enum Message {
     ScrollLeft,
     ScrollRight,
     InsertAtEnd,
+    /// `<tab>` in insert mode.
+    InsertTab,
     BackSpace,
     Delete,
+    Undo,
+    Redo,
+    Motion(Motion, Option<usize>),
+    Repeat(usize, Box<Message>),
+    DeleteRange(Motion, Option<usize>),
+    ChangeRange(Motion, Option<usize>),
+    YankRange(Motion, Option<usize>),
+    /// `>{motion}`, `>>` indents the line.
+    ShiftRightRange(Motion, Option<usize>),
+    /// `<{motion}`
+    ShiftLeftRange(Motion, Option<usize>),
+    PutAfter(Option<usize>),
+    PutBefore(Option<usize>),
+    /// Runs an operator over the visual selection and returns to normal mode.
+    VisualOperator(Operator),
+    /// `o` in visual mode, jumps to the other end of the selection.
+    SwapAnchor,
+    RegisterList,
+    SetMark(char),
+    /// Jumps to a mark, to the first non blank of its line when the flag is set.
+    JumpToMark(char, bool),
+    /// Opens the search prompt, forwards for `/` and backwards for `?`.
+    SearchPrompt(bool),
+    ExecuteSearch,
+    CancelSearch,
+    /// `n` repeats the last search, `N` with the flag set repeats it the other way.
+    SearchNext(bool),
+    NoHighlight,
+    /// Splits the focused window, `Stack::Vertically` puts the new one on top.
+    SplitWindow(Stack),
+    FocusWindow(Direction),
+    NextWindow,
+    /// Closes the focused window, closing the last one quits.
+    CloseWindow,
+    EqualizeWindows,
+    /// Grows the focused window by that many rows, or columns for `Stack::Horizontally`.
+    ResizeWindow(Stack, i32),
     KeyPress(Keys),
+    /// Types the keys of a mapping's right hand side, with the flag set they may
+    /// be mapped again.
+    FeedKeys(String, bool),
+    /// Calls the script function a mapping was made with.
+    ScriptCall(usize),
     CheckForMapping,
+    /// `timeoutlen` went by without a key while keys were pending.
+    MappingTimeout,
     ModeCommandInsertStr(String),
     ModeInsertInsertStr(String),
     ChangeMode(Mode),
@@ -37,10 +89,48 @@ pub enum Message {
     CloseCurrentPaneOnKeyPress,
     NextAvailableCommand,
     Resize(Size),
-    Save(Option<String>),
+    /// `:w`, runs `BufWritePre` and then writes with `WriteBuffer`. `true` is `:w!`.
+    Save(Option<String>, bool),
+    WriteBuffer(Option<String>, bool),
     Quit,
 }
 
+impl Message {
+    /// Hands a count typed before a mapping to the message it resolved to.
+    #[must_use]
+    pub fn with_count(self, count: usize) -> Self {
+        let times = |c: Option<usize>| Some(c.unwrap_or(1).saturating_mul(count));
+        match self {
+            Self::Motion(motion, c) => Self::Motion(motion, times(c)),
+            Self::DeleteRange(motion, c) => Self::DeleteRange(motion, times(c)),
+            Self::ChangeRange(motion, c) => Self::ChangeRange(motion, times(c)),
+            Self::YankRange(motion, c) => Self::YankRange(motion, times(c)),
+            Self::ShiftRightRange(motion, c) => Self::ShiftRightRange(motion, times(c)),
+            Self::ShiftLeftRange(motion, c) => Self::ShiftLeftRange(motion, times(c)),
+            Self::PutAfter(c) => Self::PutAfter(times(c)),
+            Self::PutBefore(c) => Self::PutBefore(times(c)),
+            Self::CursorDown
+            | Self::CursorUp
+            | Self::CursorScreenDown
+            | Self::CursorScreenUp
+            | Self::CursorLeft
+            | Self::CursorRight
+            | Self::ScrollUp
+            | Self::ScrollDown
+            | Self::ScrollLeft
+            | Self::ScrollRight
+            | Self::BackSpace
+            | Self::Delete
+            | Self::Undo
+            | Self::Redo
+            | Self::SearchNext(_)
+            | Self::NextWindow
+            | Self::ResizeWindow(..) => Self::Repeat(count, Box::new(self)),
+            _ => self,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct UserMessageBuilder {
     pub message: String,
@@ -76,7 +166,11 @@ impl UserMessageBuilder {
     pub fn build_container(&self, width: u16) -> Container {
         use revi_ui::widget::Widget;
         let stack = Stack::Vertically;
-        let msg = Text::new(&self.message);
+        let lines = self.message.lines().count().max(1) as u16;
+        let msg = self.message.lines().fold(
+            Container::new(Rect::new(Size::new(width, lines)), stack),
+            |acc, line| acc.push(Text::new(line).max_width(width)),
+        );
         let ft = Text::new(&self.footer).with_bg(Color::Grey);
         let height = msg.height() + ft.height();
         let rect = Rect::new(Size { width, height });
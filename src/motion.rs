@@ -0,0 +1,447 @@
+use ropey::Rope;
+
+use crate::grapheme;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    /// `>`, indents every line the operator covers.
+    ShiftRight,
+    /// `<`.
+    ShiftLeft,
+    /// `~` on a visual selection.
+    ToggleCase,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    LineEnd,
+    FirstNonBlank,
+    /// `w` and `W`, the flag is set for WORDs.
+    WordForward(bool),
+    /// `b` and `B`.
+    WordBackward(bool),
+    /// `e` and `E`.
+    WordEnd(bool),
+    FileStart,
+    FileEnd,
+    /// The operator key typed twice, `dd`, `yy` and `cc`.
+    Line,
+    Object(TextObject),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObject {
+    Word {
+        around: bool,
+        big: bool,
+    },
+    Quote {
+        around: bool,
+        quote: char,
+    },
+    Pair {
+        around: bool,
+        open: char,
+        close: char,
+    },
+}
+
+/// Char range `start..end` an operator works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub linewise: bool,
+}
+
+impl Span {
+    fn chars(start: usize, end: usize) -> Self {
+        Self {
+            start: start.min(end),
+            end: start.max(end),
+            linewise: false,
+        }
+    }
+
+    fn lines(rope: &Rope, first: usize, last: usize) -> Self {
+        let (first, last) = (first.min(last), first.max(last));
+        let last = last.min(last_row(rope));
+        Self {
+            start: rope.line_to_char(first),
+            end: rope.line_to_char(last + 1),
+            linewise: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharKind {
+    Space,
+    Word,
+    Punct,
+}
+
+fn kind(c: char, big: bool) -> CharKind {
+    if c.is_whitespace() {
+        CharKind::Space
+    } else if big || c.is_alphanumeric() || c == '_' {
+        CharKind::Word
+    } else {
+        CharKind::Punct
+    }
+}
+
+fn char_at(rope: &Rope, idx: usize) -> Option<char> {
+    rope.get_char(idx)
+}
+
+/// Last row with text on it, a trailing line break doesn't start a new one.
+pub fn last_row(rope: &Rope) -> usize {
+    let len = rope.len_chars();
+    let trailing = len > 0 && rope.char(len - 1) == '\n';
+    rope.len_lines().saturating_sub(1 + trailing as usize)
+}
+
+/// Index just past the last char of `row`, not counting the line break.
+pub fn line_end(rope: &Rope, row: usize) -> usize {
+    let start = rope.line_to_char(row);
+    let line = rope.line(row);
+    let len = line.len_chars();
+    let newline = len > 0 && line.char(len - 1) == '\n';
+    start + len - newline as usize
+}
+
+pub fn first_non_blank(rope: &Rope, row: usize) -> usize {
+    let start = rope.line_to_char(row);
+    let end = line_end(rope, row);
+    (start..end)
+        .find(|i| !rope.char(*i).is_whitespace())
+        .unwrap_or(end)
+}
+
+fn word_forward(rope: &Rope, mut idx: usize, big: bool) -> usize {
+    let len = rope.len_chars();
+    let Some(c) = char_at(rope, idx) else {
+        return len;
+    };
+    let start_kind = kind(c, big);
+    if start_kind != CharKind::Space {
+        while char_at(rope, idx).is_some_and(|c| kind(c, big) == start_kind) {
+            idx += 1;
+        }
+    }
+    while let Some(c) = char_at(rope, idx) {
+        if !c.is_whitespace() {
+            break;
+        }
+        // An empty line counts as a word of its own.
+        if c == '\n' && char_at(rope, idx + 1) == Some('\n') {
+            return idx + 1;
+        }
+        idx += 1;
+    }
+    idx
+}
+
+fn word_backward(rope: &Rope, mut idx: usize, big: bool) -> usize {
+    while idx > 0 && char_at(rope, idx - 1).is_some_and(char::is_whitespace) {
+        idx -= 1;
+        if char_at(rope, idx) == Some('\n') && idx > 0 && char_at(rope, idx - 1) == Some('\n') {
+            return idx;
+        }
+    }
+    let Some(c) = idx.checked_sub(1).and_then(|i| char_at(rope, i)) else {
+        return idx;
+    };
+    let k = kind(c, big);
+    while idx > 0 && char_at(rope, idx - 1).is_some_and(|c| kind(c, big) == k) {
+        idx -= 1;
+    }
+    idx
+}
+
+fn word_end(rope: &Rope, mut idx: usize, big: bool) -> usize {
+    let len = rope.len_chars();
+    idx += 1;
+    while char_at(rope, idx).is_some_and(char::is_whitespace) {
+        idx += 1;
+    }
+    let Some(c) = char_at(rope, idx) else {
+        return len.saturating_sub(1);
+    };
+    let k = kind(c, big);
+    while char_at(rope, idx + 1).is_some_and(|c| kind(c, big) == k) {
+        idx += 1;
+    }
+    idx
+}
+
+/// `step` taken `count` times from `start`, it stops early once a step goes
+/// nowhere so a huge count costs no more than reaching the end.
+fn steps(start: usize, count: usize, step: impl Fn(usize) -> usize) -> usize {
+    let mut at = start;
+    for _ in 0..count {
+        let next = step(at);
+        if next == at {
+            break;
+        }
+        at = next;
+    }
+    at
+}
+
+fn word_object(rope: &Rope, idx: usize, count: usize, around: bool, big: bool) -> Option<Span> {
+    let c = char_at(rope, idx)?;
+    let row = rope.char_to_line(idx);
+    let line_start = rope.line_to_char(row);
+    let line_end = line_end(rope, row);
+    let k = kind(c, big);
+    let same = |i: usize, k: CharKind| char_at(rope, i).is_some_and(|c| kind(c, big) == k);
+    let mut start = idx;
+    while start > line_start && same(start - 1, k) {
+        start -= 1;
+    }
+    let mut end = idx;
+    for _ in 0..count {
+        if end == line_end {
+            break;
+        }
+        let k = char_at(rope, end).map_or(CharKind::Space, |c| kind(c, big));
+        while end < line_end && same(end, k) {
+            end += 1;
+        }
+        if around && k != CharKind::Space {
+            while end < line_end && same(end, CharKind::Space) {
+                end += 1;
+            }
+        }
+    }
+    if around && k != CharKind::Space && end == line_end {
+        // No trailing white space to take, take the leading instead.
+        while start > line_start && same(start - 1, CharKind::Space) {
+            start -= 1;
+        }
+    }
+    Some(Span::chars(start, end))
+}
+
+fn quote_object(rope: &Rope, idx: usize, around: bool, quote: char) -> Option<Span> {
+    let row = rope.char_to_line(idx);
+    let start = rope.line_to_char(row);
+    let end = line_end(rope, row);
+    let quotes = (start..end)
+        .filter(|i| rope.char(*i) == quote)
+        .collect::<Vec<_>>();
+    let pair = quotes
+        .chunks_exact(2)
+        .find(|pair| pair[0] <= idx && idx <= pair[1])
+        .or_else(|| quotes.chunks_exact(2).find(|pair| pair[0] > idx))?;
+    let (open, close) = (pair[0], pair[1]);
+    if around {
+        return Some(Span::chars(open, close + 1));
+    }
+    Some(Span::chars(open + 1, close))
+}
+
+fn pair_object(rope: &Rope, idx: usize, around: bool, open: char, close: char) -> Option<Span> {
+    let mut depth = 0usize;
+    let mut left = None;
+    let mut i = idx + 1;
+    while i > 0 {
+        i -= 1;
+        match rope.get_char(i)? {
+            c if c == close && i != idx => depth += 1,
+            c if c == open && depth == 0 => {
+                left = Some(i);
+                break;
+            }
+            c if c == open => depth -= 1,
+            _ => {}
+        }
+    }
+    let left = left?;
+    let mut depth = 0usize;
+    let right = (left + 1..rope.len_chars()).find(|i| match rope.char(*i) {
+        c if c == open => {
+            depth += 1;
+            false
+        }
+        c if c == close && depth == 0 => true,
+        c if c == close => {
+            depth -= 1;
+            false
+        }
+        _ => false,
+    })?;
+    if around {
+        return Some(Span::chars(left, right + 1));
+    }
+    Some(Span::chars(left + 1, right))
+}
+
+/// Works out the range `motion` covers starting from char `idx`.
+///
+/// `count` is how many times the motion repeats and `explicit` tells motions like
+/// `G` that the count was typed by the user and is a line number.
+pub fn span(rope: &Rope, idx: usize, motion: Motion, count: usize, explicit: bool) -> Option<Span> {
+    let count = count.max(1);
+    let row = rope.char_to_line(idx);
+    let last_row = last_row(rope);
+    let line_start = rope.line_to_char(row);
+    let span = match motion {
+        Motion::Left | Motion::Right => {
+            // `h` and `l` step over whole graphemes.
+            let line = rope.slice(line_start..line_end(rope, row)).to_string();
+            let col = idx - line_start;
+            if motion == Motion::Left {
+                let start = steps(col, count, |col| grapheme::prev(&line, col));
+                Span::chars(line_start + start, idx)
+            } else {
+                let end = steps(col, count, |col| grapheme::next(&line, col));
+                Span::chars(idx, line_start + end)
+            }
+        }
+        Motion::Up => {
+            if row == 0 {
+                return None;
+            }
+            Span::lines(rope, row.saturating_sub(count), row)
+        }
+        Motion::Down => {
+            if row.saturating_add(count) > last_row {
+                return None;
+            }
+            Span::lines(rope, row, row + count)
+        }
+        Motion::Line => {
+            if row.saturating_add(count - 1) > last_row {
+                return None;
+            }
+            Span::lines(rope, row, row + count - 1)
+        }
+        Motion::LineStart => Span::chars(line_start, idx),
+        Motion::FirstNonBlank => Span::chars(first_non_blank(rope, row), idx),
+        Motion::LineEnd => {
+            let row = row.saturating_add(count - 1).min(last_row);
+            Span::chars(idx, line_end(rope, row))
+        }
+        Motion::WordForward(big) => {
+            let end = steps(idx, count, |i| word_forward(rope, i, big));
+            let end_row = rope.char_to_line(end.min(rope.len_chars()));
+            // Like vim, `dw` on the last word of a line stops at the line break.
+            let end = if end_row > row {
+                line_end(rope, end_row - 1).max(idx)
+            } else {
+                end
+            };
+            Span::chars(idx, end)
+        }
+        Motion::WordBackward(big) => {
+            let start = steps(idx, count, |i| word_backward(rope, i, big));
+            Span::chars(start, idx)
+        }
+        Motion::WordEnd(big) => {
+            let end = steps(idx, count, |i| word_end(rope, i, big));
+            Span::chars(idx, (end + 1).min(rope.len_chars()))
+        }
+        Motion::FileStart => {
+            let target = if explicit { count - 1 } else { 0 };
+            Span::lines(rope, target.min(last_row), row)
+        }
+        Motion::FileEnd => {
+            let target = if explicit { count - 1 } else { last_row };
+            Span::lines(rope, row, target.min(last_row))
+        }
+        Motion::Object(TextObject::Word { around, big }) => {
+            word_object(rope, idx, count, around, big)?
+        }
+        Motion::Object(TextObject::Quote { around, quote }) => {
+            quote_object(rope, idx, around, quote)?
+        }
+        Motion::Object(TextObject::Pair {
+            around,
+            open,
+            close,
+        }) => pair_object(rope, idx, around, open, close)?,
+    };
+    Some(span)
+}
+
+/// Where the cursor lands when `motion` is used on its own in normal mode.
+pub fn target(rope: &Rope, idx: usize, motion: Motion, count: usize, explicit: bool) -> usize {
+    let count = count.max(1);
+    let row = rope.char_to_line(idx);
+    let last_row = last_row(rope);
+    match motion {
+        Motion::WordForward(big) => steps(idx, count, |i| word_forward(rope, i, big)),
+        Motion::WordBackward(big) => steps(idx, count, |i| word_backward(rope, i, big)),
+        Motion::WordEnd(big) => steps(idx, count, |i| word_end(rope, i, big)),
+        Motion::FirstNonBlank => first_non_blank(rope, row),
+        Motion::FileStart if explicit => first_non_blank(rope, (count - 1).min(last_row)),
+        Motion::FileStart => first_non_blank(rope, 0),
+        Motion::FileEnd if explicit => first_non_blank(rope, (count - 1).min(last_row)),
+        Motion::FileEnd => first_non_blank(rope, last_row),
+        _ => span(rope, idx, motion, count, explicit).map_or(idx, |s| {
+            if s.start == idx {
+                s.end
+            } else {
+                s.start
+            }
+        }),
+    }
+    .min(rope.len_chars())
+}
+
+#[test]
+fn word_motions() {
+    let rope = Rope::from_str("foo bar.baz\nqux\n");
+    assert_eq!(target(&rope, 0, Motion::WordForward(false), 1, false), 4);
+    assert_eq!(target(&rope, 4, Motion::WordForward(false), 1, false), 7);
+    assert_eq!(target(&rope, 4, Motion::WordForward(true), 1, false), 12);
+    assert_eq!(target(&rope, 12, Motion::WordBackward(false), 1, false), 8);
+    assert_eq!(target(&rope, 0, Motion::WordEnd(false), 1, false), 2);
+}
+
+#[test]
+fn dw_stops_at_line_break() {
+    let rope = Rope::from_str("foo bar\nqux\n");
+    let span = span(&rope, 4, Motion::WordForward(false), 1, false).unwrap();
+    assert_eq!(span, Span::chars(4, 7));
+}
+
+#[test]
+fn line_spans() {
+    let rope = Rope::from_str("one\ntwo\nthree\n");
+    let span = span(&rope, 5, Motion::Line, 2, false).unwrap();
+    assert_eq!((span.start, span.end, span.linewise), (4, 14, true));
+    assert!(self::span(&rope, 5, Motion::Line, 5, false).is_none());
+}
+
+#[test]
+fn text_objects() {
+    let rope = Rope::from_str("call(foo, \"bar\") end\n");
+    let inner = Motion::Object(TextObject::Pair {
+        around: false,
+        open: '(',
+        close: ')',
+    });
+    assert_eq!(span(&rope, 6, inner, 1, false), Some(Span::chars(5, 15)));
+    let quote = Motion::Object(TextObject::Quote {
+        around: true,
+        quote: '"',
+    });
+    assert_eq!(span(&rope, 11, quote, 1, false), Some(Span::chars(10, 15)));
+    let word = Motion::Object(TextObject::Word {
+        around: true,
+        big: false,
+    });
+    assert_eq!(span(&rope, 17, word, 1, false), Some(Span::chars(16, 20)));
+}
@@ -0,0 +1,385 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// Where an option's value lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    /// Each buffer has its own, `:set` also changes the value new buffers start with.
+    Buffer,
+    /// Each window has its own, new windows start with the one they're split from.
+    Window,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Int(usize),
+    Str(Cow<'static, str>),
+}
+
+impl Value {
+    pub fn as_bool(&self) -> bool {
+        matches!(self, Self::Bool(true))
+    }
+
+    pub fn as_int(&self) -> usize {
+        match self {
+            Self::Int(int) => *int,
+            _ => 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Str(text) => text,
+            _ => "",
+        }
+    }
+}
+
+/// An option `:set` knows about, its type is the type of its default.
+#[derive(Debug)]
+pub struct OptionDef {
+    pub name: &'static str,
+    pub short: &'static str,
+    pub scope: Scope,
+    pub default: Value,
+    /// Every value a string option can take, any string goes when empty.
+    pub values: &'static [&'static str],
+}
+
+pub const OPTIONS: &[OptionDef] = &[
+    OptionDef {
+        name: "bomb",
+        short: "bomb",
+        scope: Scope::Buffer,
+        default: Value::Bool(false),
+        values: &[],
+    },
+    OptionDef {
+        name: "expandtab",
+        short: "et",
+        scope: Scope::Buffer,
+        default: Value::Bool(true),
+        values: &[],
+    },
+    OptionDef {
+        name: "fileencoding",
+        short: "fenc",
+        scope: Scope::Buffer,
+        default: Value::Str(Cow::Borrowed("utf-8")),
+        values: crate::encoding::NAMES,
+    },
+    OptionDef {
+        name: "fileformat",
+        short: "ff",
+        scope: Scope::Buffer,
+        default: Value::Str(Cow::Borrowed("unix")),
+        values: crate::encoding::FORMATS,
+    },
+    OptionDef {
+        name: "linebreak",
+        short: "lbr",
+        scope: Scope::Window,
+        default: Value::Bool(false),
+        values: &[],
+    },
+    OptionDef {
+        name: "number",
+        short: "nu",
+        scope: Scope::Window,
+        default: Value::Bool(false),
+        values: &[],
+    },
+    OptionDef {
+        name: "readonly",
+        short: "ro",
+        scope: Scope::Buffer,
+        default: Value::Bool(false),
+        values: &[],
+    },
+    OptionDef {
+        name: "relativenumber",
+        short: "rnu",
+        scope: Scope::Window,
+        default: Value::Bool(false),
+        values: &[],
+    },
+    OptionDef {
+        name: "scrolloff",
+        short: "so",
+        scope: Scope::Global,
+        default: Value::Int(0),
+        values: &[],
+    },
+    OptionDef {
+        name: "shiftwidth",
+        short: "sw",
+        scope: Scope::Buffer,
+        default: Value::Int(4),
+        values: &[],
+    },
+    OptionDef {
+        name: "showbreak",
+        short: "sbr",
+        scope: Scope::Global,
+        default: Value::Str(Cow::Borrowed("")),
+        values: &[],
+    },
+    OptionDef {
+        name: "showcmd",
+        short: "sc",
+        scope: Scope::Global,
+        default: Value::Bool(true),
+        values: &[],
+    },
+    OptionDef {
+        name: "softtabstop",
+        short: "sts",
+        scope: Scope::Buffer,
+        default: Value::Int(0),
+        values: &[],
+    },
+    OptionDef {
+        name: "tabstop",
+        short: "ts",
+        scope: Scope::Buffer,
+        default: Value::Int(8),
+        values: &[],
+    },
+    OptionDef {
+        name: "timeoutlen",
+        short: "tm",
+        scope: Scope::Global,
+        default: Value::Int(1000),
+        values: &[],
+    },
+    OptionDef {
+        name: "wrap",
+        short: "wrap",
+        scope: Scope::Window,
+        default: Value::Bool(false),
+        values: &[],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static OptionDef> {
+    OPTIONS
+        .iter()
+        .find(|def| def.name == name || def.short == name)
+}
+
+/// Values a buffer or window set for itself.
+#[derive(Debug, Clone, Default)]
+pub struct LocalOptions {
+    values: BTreeMap<&'static str, Value>,
+}
+
+impl LocalOptions {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+
+    /// Value of `name` set here, its default when nothing was.
+    pub fn get_or_default(&self, name: &str) -> &Value {
+        self.get(name).unwrap_or_else(|| {
+            &find(name)
+                .unwrap_or_else(|| panic!("unknown option {name}"))
+                .default
+        })
+    }
+
+    pub fn set(&mut self, name: &'static str, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    /// Takes on every value `other` set, over the ones set here.
+    pub fn extend(&mut self, other: LocalOptions) {
+        self.values.extend(other.values);
+    }
+}
+
+/// One argument of `:set`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetArg<'a> {
+    /// `opt`, turns a flag on or shows any other option.
+    Name(&'a str),
+    /// `noopt`
+    Off(&'a str),
+    /// `invopt` and `opt!`
+    Toggle(&'a str),
+    /// `opt?`
+    Show(&'a str),
+    /// `opt&`, back to the default.
+    Reset(&'a str),
+    /// `opt=val` and `opt:val`
+    Assign(&'a str, &'a str),
+}
+
+impl<'a> SetArg<'a> {
+    pub fn parse(arg: &'a str) -> Self {
+        if let Some((name, value)) = arg.split_once(['=', ':']) {
+            return Self::Assign(name, value);
+        }
+        if let Some(name) = arg.strip_suffix('?') {
+            return Self::Show(name);
+        }
+        if let Some(name) = arg.strip_suffix('!') {
+            return Self::Toggle(name);
+        }
+        if let Some(name) = arg.strip_suffix('&') {
+            return Self::Reset(name);
+        }
+        match (arg.strip_prefix("no"), arg.strip_prefix("inv")) {
+            (Some(name), _) if find(arg).is_none() => Self::Off(name),
+            (_, Some(name)) if find(arg).is_none() => Self::Toggle(name),
+            _ => Self::Name(arg),
+        }
+    }
+}
+
+/// `nowrap` or `tabstop=8`, how `:set` shows an option.
+pub fn display(name: &str, value: &Value) -> String {
+    match value {
+        Value::Bool(true) => name.to_string(),
+        Value::Bool(false) => format!("no{name}"),
+        Value::Int(int) => format!("{name}={int}"),
+        Value::Str(text) => format!("{name}={text}"),
+    }
+}
+
+/// Every option's global value, buffers and windows fall back on these.
+#[derive(Debug, Clone)]
+pub struct Options {
+    global: BTreeMap<&'static str, Value>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let global = OPTIONS
+            .iter()
+            .map(|def| (def.name, def.default.clone()))
+            .collect();
+        Self { global }
+    }
+}
+
+impl Options {
+    /// Value of `name` as a buffer and a window showing it see it.
+    pub fn get<'a>(
+        &'a self,
+        name: &str,
+        buffer: &'a LocalOptions,
+        window: &'a LocalOptions,
+    ) -> &'a Value {
+        let def = find(name).unwrap_or_else(|| panic!("unknown option {name}"));
+        let local = match def.scope {
+            Scope::Global => None,
+            Scope::Buffer => buffer.get(def.name),
+            Scope::Window => window.get(def.name),
+        };
+        local.unwrap_or(&self.global[def.name])
+    }
+
+    /// Global values of every option in `scope`, what a new buffer or window starts with.
+    pub fn locals(&self, scope: Scope) -> LocalOptions {
+        let values = OPTIONS
+            .iter()
+            .filter(|def| def.scope == scope)
+            .map(|def| (def.name, self.global[def.name].clone()))
+            .collect();
+        LocalOptions { values }
+    }
+
+    /// Runs one `:set` argument, `local` is `:setlocal` and leaves the global value alone.
+    /// Returns the text to show for `opt?`.
+    pub fn set(
+        &mut self,
+        arg: &str,
+        local: bool,
+        buffer: &mut LocalOptions,
+        window: &mut LocalOptions,
+    ) -> Result<Option<String>, String> {
+        let arg = SetArg::parse(arg);
+        let name = match arg {
+            SetArg::Name(name)
+            | SetArg::Off(name)
+            | SetArg::Toggle(name)
+            | SetArg::Show(name)
+            | SetArg::Reset(name)
+            | SetArg::Assign(name, _) => name,
+        };
+        let def = find(name).ok_or(format!("Unknown option: {name}"))?;
+        let current = self.get(def.name, buffer, window).clone();
+        let invalid = || format!("Invalid argument: {name}");
+        let value = match (arg, &def.default) {
+            (SetArg::Show(_), _) | (SetArg::Name(_), Value::Int(_) | Value::Str(_)) => {
+                return Ok(Some(display(def.name, &current)));
+            }
+            (SetArg::Name(_), Value::Bool(_)) => Value::Bool(true),
+            (SetArg::Off(_), Value::Bool(_)) => Value::Bool(false),
+            (SetArg::Toggle(_), Value::Bool(_)) => Value::Bool(!current.as_bool()),
+            (SetArg::Reset(_), default) => default.clone(),
+            (SetArg::Assign(_, value), Value::Int(_)) => value
+                .parse()
+                .map(Value::Int)
+                .map_err(|_| format!("Number required after =: {name}={value}"))?,
+            (SetArg::Assign(_, value), Value::Str(_)) => {
+                if !def.values.is_empty() && !def.values.contains(&value) {
+                    return Err(format!("Invalid argument: {name}={value}"));
+                }
+                Value::Str(Cow::Owned(value.to_string()))
+            }
+            _ => return Err(invalid()),
+        };
+        let local_values = match def.scope {
+            Scope::Global => None,
+            Scope::Buffer => Some(buffer),
+            Scope::Window => Some(window),
+        };
+        match local_values {
+            Some(values) => {
+                values.set(def.name, value.clone());
+                if !local {
+                    self.global.insert(def.name, value);
+                }
+            }
+            None => {
+                self.global.insert(def.name, value);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[test]
+fn parse_set_args() {
+    assert_eq!(SetArg::parse("nowrap"), SetArg::Off("wrap"));
+    assert_eq!(SetArg::parse("number"), SetArg::Name("number"));
+    assert_eq!(SetArg::parse("nu!"), SetArg::Toggle("nu"));
+    assert_eq!(SetArg::parse("invnu"), SetArg::Toggle("nu"));
+    assert_eq!(SetArg::parse("ts?"), SetArg::Show("ts"));
+    assert_eq!(SetArg::parse("ts=4"), SetArg::Assign("ts", "4"));
+    assert_eq!(SetArg::parse("sw&"), SetArg::Reset("sw"));
+}
+
+#[test]
+fn set_scopes() {
+    let mut options = Options::default();
+    let mut buffer = options.locals(Scope::Buffer);
+    let mut window = options.locals(Scope::Window);
+    let mut set = |arg, local| options.set(arg, local, &mut buffer, &mut window);
+    assert_eq!(set("ts=4", false), Ok(None));
+    assert_eq!(set("sw=2", true), Ok(None));
+    assert_eq!(set("nu", false), Ok(None));
+    assert_eq!(set("ts", false), Ok(Some("tabstop=4".into())));
+    assert_eq!(set("nu?", false), Ok(Some("number".into())));
+    assert!(set("ts=x", false).is_err());
+    assert!(set("nots", false).is_err());
+    assert!(set("bogus", false).is_err());
+    assert_eq!(options.get("sw", &buffer, &window), &Value::Int(2));
+    let other = options.locals(Scope::Buffer);
+    assert_eq!(options.get("ts", &other, &window), &Value::Int(4));
+    assert_eq!(options.get("sw", &other, &window), &Value::Int(4));
+}
@@ -1,7 +1,22 @@
-use revi_ui::Keys;
+use revi_ui::{string_to_keys, Key, Keys};
+
+use crate::message::Message;
+use crate::motion::{Motion, Operator, TextObject};
+use crate::register::Registers;
+
+/// Outcome of reading the pending keys as `[count] operator [count] motion`.
+#[derive(Debug)]
+pub enum OperatorParse {
+    Pending,
+    NoMatch,
+    Done(Message),
+}
+
 #[derive(Debug)]
 pub struct KeyParser {
-    pub multiplier: usize,
+    pub count: Option<usize>,
+    pub register: Option<char>,
+    awaiting_register: bool,
     keys: Vec<Keys>,
     idx: usize,
 }
@@ -9,7 +24,9 @@ pub struct KeyParser {
 impl Default for KeyParser {
     fn default() -> Self {
         Self {
-            multiplier: 1,
+            count: None,
+            register: None,
+            awaiting_register: false,
             keys: Vec::with_capacity(20),
             idx: 0,
         }
@@ -17,6 +34,41 @@ impl Default for KeyParser {
 }
 
 impl KeyParser {
+    /// Takes a `"x` register prefix, only before any other key is pending.
+    pub fn push_register(&mut self, keys: Keys) -> bool {
+        if self.idx != 0 {
+            return false;
+        }
+        let c = keys.as_char();
+        if self.awaiting_register {
+            self.awaiting_register = false;
+            self.register = c.filter(|c| Registers::is_valid(*c));
+            return true;
+        }
+        if c == Some('"') {
+            self.awaiting_register = true;
+            return true;
+        }
+        false
+    }
+
+    /// Takes `keys` as part of a count prefix, only before any other key is pending.
+    /// A lone `0` is left for mappings to use.
+    pub fn push_count(&mut self, keys: Keys) -> bool {
+        if self.idx != 0 {
+            return false;
+        }
+        match digit(&keys) {
+            Some(0) if self.count.is_none() => false,
+            Some(d) => {
+                let count = self.count.unwrap_or(0);
+                self.count = Some(count.saturating_mul(10).saturating_add(d));
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn push(&mut self, keys: Keys) {
         if keys.is_null() {
             return;
@@ -32,10 +84,263 @@ impl KeyParser {
 
     pub fn clear(&mut self) {
         self.idx = 0;
-        self.multiplier = 1;
+        self.count = None;
+        self.register = None;
+        self.awaiting_register = false;
     }
 
     pub fn get_keys(&self) -> &[Keys] {
         &self.keys[..self.idx]
     }
+
+    /// What has been typed towards the next command, `"a3d`, for `showcmd`.
+    pub fn showcmd(&self) -> String {
+        let mut text = String::new();
+        if self.awaiting_register {
+            text.push('"');
+        }
+        if let Some(name) = self.register {
+            text.push('"');
+            text.push(name);
+        }
+        if let Some(count) = self.count {
+            text += &count.to_string();
+        }
+        for keys in self.get_keys() {
+            text += &show_key(*keys);
+        }
+        text
+    }
+}
+
+/// `^W` for control keys and `<Tab>` for ones that don't print.
+fn show_key(keys: Keys) -> String {
+    match keys {
+        Keys::KeyAndMod(key, Key::Ctrl) => format!("^{}", key.as_char().to_ascii_uppercase()),
+        Keys::KeyAndMod(key, modk) => format!("<{modk:?}-{}>", key.as_char()),
+        Keys::Key(Key::Caret) => "^".into(),
+        Keys::Key(key) if key.as_char() == '\0' || key.as_char().is_control() => {
+            format!("<{key:?}>")
+        }
+        Keys::Key(key) => key.as_char().to_string(),
+    }
+}
+
+fn digit(keys: &Keys) -> Option<usize> {
+    match keys {
+        Keys::Key(key) => key.try_digit(),
+        _ => None,
+    }
+}
+
+/// Splits a count off the front of `keys`, a leading `0` is a motion and not a count.
+fn take_count(keys: &[Keys]) -> (Option<usize>, &[Keys]) {
+    let mut count: Option<usize> = None;
+    let mut idx = 0;
+    while let Some(d) = keys.get(idx).and_then(digit) {
+        if d == 0 && count.is_none() {
+            break;
+        }
+        count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(d));
+        idx += 1;
+    }
+    (count, &keys[idx..])
+}
+
+fn operator(keys: &Keys) -> Option<Operator> {
+    match keys.as_char()? {
+        'd' => Some(Operator::Delete),
+        'c' => Some(Operator::Change),
+        'y' => Some(Operator::Yank),
+        '>' => Some(Operator::ShiftRight),
+        '<' => Some(Operator::ShiftLeft),
+        _ => None,
+    }
+}
+
+fn chars(src: &str) -> Vec<Keys> {
+    src.chars().map(|c| Keys::Key(Key::from(c))).collect()
+}
+
+fn motions() -> Vec<(Vec<Keys>, Motion)> {
+    let mut table = vec![
+        (chars("h"), Motion::Left),
+        (string_to_keys("<left>"), Motion::Left),
+        (chars("l"), Motion::Right),
+        (string_to_keys("<right>"), Motion::Right),
+        (chars("k"), Motion::Up),
+        (string_to_keys("<up>"), Motion::Up),
+        (chars("j"), Motion::Down),
+        (string_to_keys("<down>"), Motion::Down),
+        (chars("0"), Motion::LineStart),
+        (string_to_keys("<home>"), Motion::LineStart),
+        (chars("$"), Motion::LineEnd),
+        (string_to_keys("<end>"), Motion::LineEnd),
+        (chars("^"), Motion::FirstNonBlank),
+        (chars("w"), Motion::WordForward(false)),
+        (chars("W"), Motion::WordForward(true)),
+        (chars("b"), Motion::WordBackward(false)),
+        (chars("B"), Motion::WordBackward(true)),
+        (chars("e"), Motion::WordEnd(false)),
+        (chars("E"), Motion::WordEnd(true)),
+        (chars("gg"), Motion::FileStart),
+        (chars("G"), Motion::FileEnd),
+    ];
+    for (prefix, around) in [('i', false), ('a', true)] {
+        let object = |tail: &str, object: TextObject| {
+            (chars(&format!("{prefix}{tail}")), Motion::Object(object))
+        };
+        table.push(object("w", TextObject::Word { around, big: false }));
+        table.push(object("W", TextObject::Word { around, big: true }));
+        for quote in ['"', '\'', '`'] {
+            table.push(object(
+                &quote.to_string(),
+                TextObject::Quote { around, quote },
+            ));
+        }
+        for (keys, open, close) in [
+            ("(", '(', ')'),
+            (")", '(', ')'),
+            ("b", '(', ')'),
+            ("{", '{', '}'),
+            ("}", '{', '}'),
+            ("B", '{', '}'),
+            ("[", '[', ']'),
+            ("]", '[', ']'),
+            ("<", '<', '>'),
+            (">", '<', '>'),
+        ] {
+            table.push(object(
+                keys,
+                TextObject::Pair {
+                    around,
+                    open,
+                    close,
+                },
+            ));
+        }
+    }
+    table
+}
+
+impl KeyParser {
+    /// Reads the pending keys as an operator applied to a motion or text object,
+    /// `2dw`, `c3j`, `y$` or `dd`.
+    pub fn parse_operator(&self) -> OperatorParse {
+        let before = self.count;
+        let Some((op_key, keys)) = self.get_keys().split_first() else {
+            return OperatorParse::NoMatch;
+        };
+        let Some(op) = operator(op_key) else {
+            return OperatorParse::NoMatch;
+        };
+        let (after, keys) = take_count(keys);
+        if keys.is_empty() {
+            return OperatorParse::Pending;
+        }
+        let count = match (before, after) {
+            (None, None) => None,
+            (before, after) => Some(before.unwrap_or(1) * after.unwrap_or(1)),
+        };
+        let motion = if keys == [*op_key] {
+            Some(Motion::Line)
+        } else {
+            let table = motions();
+            if let Some((_, motion)) = table.iter().find(|(k, _)| k.as_slice() == keys) {
+                Some(*motion)
+            } else if table.iter().any(|(k, _)| k.starts_with(keys)) {
+                return OperatorParse::Pending;
+            } else {
+                None
+            }
+        };
+        let Some(motion) = motion else {
+            return OperatorParse::NoMatch;
+        };
+        OperatorParse::Done(match op {
+            Operator::Delete => Message::DeleteRange(motion, count),
+            Operator::Change => Message::ChangeRange(motion, count),
+            Operator::Yank => Message::YankRange(motion, count),
+            Operator::ShiftRight => Message::ShiftRightRange(motion, count),
+            Operator::ShiftLeft => Message::ShiftLeftRange(motion, count),
+            Operator::ToggleCase => return OperatorParse::NoMatch,
+        })
+    }
+}
+
+#[test]
+fn parse_operator_with_counts() {
+    let parse = |src: &str| {
+        let mut parser = KeyParser::default();
+        for k in string_to_keys(src) {
+            if !parser.push_count(k) {
+                parser.push(k);
+            }
+        }
+        parser.parse_operator()
+    };
+    assert!(matches!(
+        parse("2d3w"),
+        OperatorParse::Done(Message::DeleteRange(Motion::WordForward(false), Some(6)))
+    ));
+    assert!(matches!(
+        parse("dd"),
+        OperatorParse::Done(Message::DeleteRange(Motion::Line, None))
+    ));
+    assert!(matches!(
+        parse("y$"),
+        OperatorParse::Done(Message::YankRange(Motion::LineEnd, None))
+    ));
+    assert!(matches!(
+        parse("d0"),
+        OperatorParse::Done(Message::DeleteRange(Motion::LineStart, None))
+    ));
+    assert!(matches!(
+        parse("3>>"),
+        OperatorParse::Done(Message::ShiftRightRange(Motion::Line, Some(3)))
+    ));
+    assert!(matches!(parse("3"), OperatorParse::NoMatch));
+    assert!(matches!(parse("c2"), OperatorParse::Pending));
+    assert!(matches!(parse("dg"), OperatorParse::Pending));
+    assert!(matches!(parse("ci"), OperatorParse::Pending));
+    assert!(matches!(parse("dz"), OperatorParse::NoMatch));
+    assert!(matches!(parse("j"), OperatorParse::NoMatch));
+    assert!(matches!(parse("0"), OperatorParse::NoMatch));
+}
+
+#[test]
+fn count_prefix() {
+    let mut parser = KeyParser::default();
+    assert!(!parser.push_count(Keys::Key(Key::N0)));
+    assert!(parser.push_count(Keys::Key(Key::N1)));
+    assert!(parser.push_count(Keys::Key(Key::N0)));
+    assert_eq!(parser.count, Some(10));
+    parser.push(Keys::Key(Key::LJ));
+    assert!(!parser.push_count(Keys::Key(Key::N2)));
+    parser.clear();
+    assert_eq!(parser.count, None);
+}
+
+#[test]
+fn register_prefix() {
+    let mut parser = KeyParser::default();
+    for k in string_to_keys("\"a") {
+        assert!(parser.push_register(k));
+    }
+    assert_eq!(parser.register, Some('a'));
+    assert!(parser.push_count(Keys::Key(Key::N2)));
+    parser.push(Keys::Key(Key::LY));
+    assert!(!parser.push_register(Keys::Key(Key::Char('"'))));
+}
+
+#[test]
+fn showcmd_text() {
+    let mut parser = KeyParser::default();
+    for key in chars("\"a3") {
+        assert!(parser.push_register(key) || parser.push_count(key));
+    }
+    for key in string_to_keys("d<c-w><space>") {
+        parser.push(key);
+    }
+    assert_eq!(parser.showcmd(), "\"a3d^W<Space>");
 }
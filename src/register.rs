@@ -0,0 +1,148 @@
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+}
+
+impl Register {
+    pub fn new(text: impl Into<String>, linewise: bool) -> Self {
+        Self {
+            text: text.into(),
+            linewise,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    fn append(&mut self, other: Register) {
+        if other.linewise && !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.text.push_str(&other.text);
+        self.linewise |= other.linewise;
+    }
+}
+
+/// Vim style registers.
+///
+/// - `"` unnamed, whatever was last yanked, deleted or written into a register.
+/// - `0` last yank, `1` to `9` a ring of deletes that span lines.
+/// - `-` last delete inside a single line.
+/// - `a` to `z` named, writing to `A` to `Z` appends to them.
+/// - `_` black hole, writing to it changes nothing.
+#[derive(Debug, Default)]
+pub struct Registers {
+    unnamed: Register,
+    small_delete: Register,
+    numbered: [Register; 10],
+    named: [Register; 26],
+}
+
+impl Registers {
+    pub fn is_valid(name: char) -> bool {
+        matches!(name, '"' | '-' | '_' | '0'..='9' | 'a'..='z' | 'A'..='Z')
+    }
+
+    pub fn get(&self, name: Option<char>) -> Option<&Register> {
+        let reg = match name.unwrap_or('"') {
+            '"' => &self.unnamed,
+            '-' => &self.small_delete,
+            c @ '0'..='9' => &self.numbered[c as usize - '0' as usize],
+            c @ 'a'..='z' => &self.named[c as usize - 'a' as usize],
+            c @ 'A'..='Z' => &self.named[c as usize - 'A' as usize],
+            _ => return None,
+        };
+        Some(reg).filter(|r| !r.is_empty())
+    }
+
+    /// Writes into a named register, returns false when `name` isn't one.
+    fn write_named(&mut self, name: char, reg: Register) -> bool {
+        match name {
+            c @ 'a'..='z' => self.named[c as usize - 'a' as usize] = reg.clone(),
+            c @ 'A'..='Z' => {
+                let named = &mut self.named[c as usize - 'A' as usize];
+                named.append(reg);
+                self.unnamed = named.clone();
+                return true;
+            }
+            c @ '0'..='9' => self.numbered[c as usize - '0' as usize] = reg.clone(),
+            '-' => self.small_delete = reg.clone(),
+            _ => return false,
+        }
+        self.unnamed = reg;
+        true
+    }
+
+    pub fn yank(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            Some('_') => {}
+            Some(name) if self.write_named(name, reg.clone()) => {}
+            _ => {
+                self.numbered[0] = reg.clone();
+                self.unnamed = reg;
+            }
+        }
+    }
+
+    pub fn delete(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            Some('_') => {}
+            Some(name) if self.write_named(name, reg.clone()) => {}
+            _ if reg.linewise || reg.text.contains('\n') => {
+                self.numbered[1..].rotate_right(1);
+                self.numbered[1] = reg.clone();
+                self.unnamed = reg;
+            }
+            _ => {
+                self.small_delete = reg.clone();
+                self.unnamed = reg;
+            }
+        }
+    }
+
+    /// Every register holding something, in the order `:registers` shows them.
+    pub fn list(&self) -> Vec<(char, &Register)> {
+        std::iter::once(('"', &self.unnamed))
+            .chain(('0'..='9').zip(self.numbered.iter()))
+            .chain(std::iter::once(('-', &self.small_delete)))
+            .chain(('a'..='z').zip(self.named.iter()))
+            .filter(|(_, reg)| !reg.is_empty())
+            .collect()
+    }
+}
+
+#[test]
+fn yank_and_delete_registers() {
+    let mut regs = Registers::default();
+    regs.yank(None, Register::new("yanked", false));
+    regs.delete(None, Register::new("line\n", true));
+    regs.delete(None, Register::new("word", false));
+    assert_eq!(regs.get(Some('0')).unwrap().text, "yanked");
+    assert_eq!(regs.get(Some('1')).unwrap().text, "line\n");
+    assert_eq!(regs.get(Some('-')).unwrap().text, "word");
+    assert_eq!(regs.get(None).unwrap().text, "word");
+
+    regs.delete(None, Register::new("second\n", true));
+    assert_eq!(regs.get(Some('1')).unwrap().text, "second\n");
+    assert_eq!(regs.get(Some('2')).unwrap().text, "line\n");
+
+    regs.delete(Some('_'), Register::new("gone\n", true));
+    assert_eq!(regs.get(None).unwrap().text, "second\n");
+}
+
+#[test]
+fn named_registers_append() {
+    let mut regs = Registers::default();
+    regs.yank(Some('a'), Register::new("foo", false));
+    regs.yank(Some('A'), Register::new("bar", false));
+    assert_eq!(regs.get(Some('a')).unwrap().text, "foobar");
+    regs.yank(Some('A'), Register::new("baz\n", true));
+    assert_eq!(
+        regs.get(Some('a')),
+        Some(&Register::new("foobar\nbaz\n", true))
+    );
+    assert_eq!(regs.get(None), regs.get(Some('a')));
+    assert!(regs.get(Some('0')).is_none());
+}
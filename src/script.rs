@@ -0,0 +1,660 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use revi_ui::{layout::Size, Color};
+use rhai::{
+    Array, CustomType, Dynamic, Engine, EvalAltResult, FnPtr, ImmutableString, Map, Scope,
+    TypeBuilder, AST, INT,
+};
+
+use crate::autocmd::{AutoCmd, Event};
+use crate::buffer::Buffer;
+use crate::map_keys;
+use crate::message::{Message, UserMessageBuilder};
+use crate::motion::Span;
+use crate::state::State;
+use crate::substitute::Replace;
+use crate::user_command::{self, UserCommand};
+use crate::Mode;
+
+/// A script function waiting to be called.
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub func: FnPtr,
+    pub args: Vec<Dynamic>,
+}
+
+/// Script functions the editor holds on to.
+#[derive(Debug, Default)]
+pub struct Callbacks {
+    /// Functions `Message::ScriptCall` calls by index.
+    pub mapped: Vec<FnPtr>,
+    /// Commands scripts made, `:Name` calls them.
+    pub commands: BTreeMap<String, UserCommand>,
+    /// Calls made once `State::update` returns, the editor is borrowed until then.
+    pub pending: Vec<Call>,
+    pub autocmds: Vec<AutoCmd>,
+}
+
+impl Callbacks {
+    /// Queues every autocommand for `event` on `target`, each gets `data` with
+    /// the event's name added.
+    pub fn fire(&mut self, event: Event, target: &str, mut data: Map) {
+        if !self.autocmds.iter().any(|autocmd| autocmd.event == event) {
+            return;
+        }
+        data.insert("event".into(), event.to_string().into());
+        let calls = self
+            .autocmds
+            .iter()
+            .filter(|autocmd| autocmd.matches(event, target))
+            .map(|autocmd| Call {
+                func: autocmd.func.clone(),
+                args: vec![data.clone().into()],
+            })
+            .collect::<Vec<_>>();
+        self.pending.extend(calls);
+    }
+}
+
+/// `$XDG_CONFIG_HOME/revi/init.rhai`, or `~/.config/revi/init.rhai` when it's not set.
+pub fn config_path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("revi").join("init.rhai"))
+}
+
+fn script_error(msg: impl Into<String>) -> UserMessageBuilder {
+    UserMessageBuilder::default()
+        .message(msg)
+        .footer("script")
+        .fg(Color::Red)
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// A buffer as scripts see it, rows and columns start at `0`.
+#[derive(Debug, Clone)]
+pub struct ScriptBuffer {
+    state: Rc<RefCell<State>>,
+    id: usize,
+}
+
+impl ScriptBuffer {
+    fn read<T>(&mut self, read: impl FnOnce(&Buffer) -> T) -> T {
+        read(&self.state.borrow().buffers[self.id])
+    }
+
+    fn edit<T>(&mut self, edit: impl FnOnce(&mut Buffer) -> T) -> T {
+        self.state.borrow_mut().edit_buffer(self.id, edit)
+    }
+
+    /// `row` as an index into the buffer, `extra` allows rows past the last one.
+    fn row(&mut self, row: INT, extra: usize) -> ScriptResult<usize> {
+        let count = self.line_count() as usize + extra;
+        match usize::try_from(row) {
+            Ok(row) if row < count => Ok(row),
+            _ => Err(format!("Row {row} is out of range, the buffer has {count} lines").into()),
+        }
+    }
+
+    fn line(&mut self, row: INT) -> ScriptResult<ImmutableString> {
+        let row = self.row(row, 0)?;
+        Ok(self.read(|buf| buf.line(row)).into())
+    }
+
+    fn line_count(&mut self) -> INT {
+        self.read(|buf| buf.len_lines() + 1) as INT
+    }
+
+    /// `text` at `row` and `col`, a row past the end adds to the end of the buffer.
+    fn insert(&mut self, row: INT, col: INT, text: ImmutableString) -> ScriptResult<()> {
+        let row = self.row(row, 1)?;
+        let col = col.max(0) as usize;
+        self.edit(|buf| {
+            let start = buf.line_to_char(row);
+            let idx = start + col.min(buf.line(row).chars().count());
+            buf.insert_at(idx, text.as_str());
+        });
+        Ok(())
+    }
+
+    /// Removes the rows in `range`, `0..2` the first two.
+    fn delete(&mut self, start: INT, end: INT) -> ScriptResult<()> {
+        let start = self.row(start, 0)?;
+        let end = (end.max(0) as usize).min(self.line_count() as usize);
+        if start >= end {
+            return Ok(());
+        }
+        self.edit(|buf| {
+            let span = Span {
+                start: buf.line_to_char(start),
+                end: buf.line_to_char(end),
+                linewise: true,
+            };
+            buf.remove(span, Size::default());
+        });
+        Ok(())
+    }
+
+    fn delete_range(&mut self, range: std::ops::Range<INT>) -> ScriptResult<()> {
+        self.delete(range.start, range.end)
+    }
+
+    fn delete_inclusive(&mut self, range: std::ops::RangeInclusive<INT>) -> ScriptResult<()> {
+        self.delete(*range.start(), range.end().saturating_add(1))
+    }
+
+    fn replace_line(&mut self, row: INT, text: ImmutableString) -> ScriptResult<()> {
+        let row = self.row(row, 0)?;
+        self.edit(|buf| {
+            let start = buf.line_to_char(row);
+            let end = start + buf.line(row).chars().count();
+            buf.replace_ranges(&[Replace {
+                range: start..end,
+                text: text.to_string(),
+            }]);
+        });
+        Ok(())
+    }
+
+    fn get_text(&mut self) -> ImmutableString {
+        self.read(Buffer::get_all_text).into()
+    }
+
+    fn get_name(&mut self) -> ImmutableString {
+        self.read(|buf| buf.name.clone()).into()
+    }
+
+    fn get_is_modified(&mut self) -> bool {
+        self.read(Buffer::is_modified)
+    }
+}
+
+impl CustomType for ScriptBuffer {
+    fn build(mut builder: TypeBuilder<Self>) {
+        builder
+            .with_name("Buffer")
+            .with_fn("line", Self::line)
+            .with_fn("line_count", Self::line_count)
+            .with_fn("insert", Self::insert)
+            .with_fn("delete", Self::delete_range)
+            .with_fn("delete", Self::delete_inclusive)
+            .with_fn("replace_line", Self::replace_line)
+            .with_fn("get_text", Self::get_text)
+            .with_get("name", Self::get_name)
+            .with_get("is_modified", Self::get_is_modified);
+    }
+}
+
+/// The `revi` object scripts reach the editor through.
+#[derive(Debug, Clone)]
+pub struct ReviApi(Rc<RefCell<State>>);
+
+impl ReviApi {
+    fn get_mode(&mut self) -> ImmutableString {
+        self.0.borrow().mode.to_string().into()
+    }
+
+    fn set_mode(&mut self, mode: ImmutableString) {
+        let mode = match mode.to_lowercase().as_str() {
+            "insert" => Mode::Insert,
+            "command" => Mode::Command,
+            "visual" => Mode::Visual,
+            "v-line" => Mode::VisualLine,
+            "v-block" => Mode::VisualBlock,
+            _ => Mode::Normal,
+        };
+        self.0.borrow_mut().change_mode(mode);
+    }
+
+    /// `revi.nmap("Y", "y$")`, the keys are typed again and, unless it's a
+    /// `noremap`, may be mapped themselves.
+    fn map_keys(&mut self, modes: &[Mode], keys: &str, rhs: &str, remap: bool) {
+        let mut state = self.0.borrow_mut();
+        for mode in modes {
+            let message = Message::FeedKeys(rhs.to_string(), remap);
+            state.map_keys.map(*mode, keys, rhs, !remap, message);
+        }
+    }
+
+    fn map_function(&mut self, modes: &[Mode], keys: &str, func: FnPtr) {
+        let rhs = if func.is_anonymous() {
+            "<closure>".to_string()
+        } else {
+            format!("{}()", func.fn_name())
+        };
+        let mut state = self.0.borrow_mut();
+        let id = state.callbacks.mapped.len();
+        state.callbacks.mapped.push(func);
+        for mode in modes {
+            let message = Message::ScriptCall(id);
+            state.map_keys.map(*mode, keys, &rhs, false, message);
+        }
+    }
+
+    fn unmap(&mut self, modes: &[Mode], keys: &str) -> ScriptResult<()> {
+        let mut state = self.0.borrow_mut();
+        let mut found = false;
+        for mode in modes {
+            found |= state.map_keys.unmap(*mode, keys);
+        }
+        if !found {
+            return Err(format!("No such mapping: {keys}").into());
+        }
+        Ok(())
+    }
+
+    /// `revi.export_command(Fn("Name"))`, closures need a name given with them.
+    fn export_function(&mut self, func: FnPtr) -> ScriptResult<()> {
+        let name = func.fn_name().to_string();
+        self.export_command(name.into(), func)
+    }
+
+    fn export_command(&mut self, name: ImmutableString, func: FnPtr) -> ScriptResult<()> {
+        self.add_command(&name, UserCommand::raw(func))
+    }
+
+    /// `revi.command("Name", |cmd| ..., #{nargs: "*", complete: "file", bang: true, range: true})`,
+    /// the function gets a map with the `args`, whether there was a `bang` and the `rows`.
+    fn command(&mut self, name: ImmutableString, func: FnPtr, opts: Map) -> ScriptResult<()> {
+        let command = UserCommand::new(func, opts)?;
+        self.add_command(&name, command)
+    }
+
+    fn command_no_opts(&mut self, name: ImmutableString, func: FnPtr) -> ScriptResult<()> {
+        self.command(name, func, Map::new())
+    }
+
+    fn add_command(&mut self, name: &str, command: UserCommand) -> ScriptResult<()> {
+        user_command::valid_name(name)?;
+        let mut state = self.0.borrow_mut();
+        state.command_list.insert(name);
+        state.callbacks.commands.insert(name.to_string(), command);
+        Ok(())
+    }
+
+    /// `revi.autocmd("BufWritePre,BufNewFile", "*.rs", |ev| ...)`, the function gets a
+    /// map with the `event` and its `file`.
+    fn autocmd(
+        &mut self,
+        events: ImmutableString,
+        pattern: ImmutableString,
+        func: FnPtr,
+    ) -> ScriptResult<()> {
+        let events = events
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<Event>, _>>()?;
+        let autocmds = &mut self.0.borrow_mut().callbacks.autocmds;
+        autocmds.extend(events.into_iter().map(|event| AutoCmd {
+            event,
+            pattern: pattern.to_string(),
+            func: func.clone(),
+        }));
+        Ok(())
+    }
+
+    fn autocmd_any(&mut self, events: ImmutableString, func: FnPtr) -> ScriptResult<()> {
+        self.autocmd(events, "*".into(), func)
+    }
+
+    fn message(&mut self, msg: ImmutableString) {
+        let builder = UserMessageBuilder::default()
+            .message(msg.as_str())
+            .footer("message");
+        self.0.borrow_mut().messages.push(builder);
+    }
+
+    fn set_cursor_row(&mut self, row: INT) {
+        let mut state = self.0.borrow_mut();
+        let col = state.get_focused_buffer().get_cursor().pos().x as usize;
+        state.move_cursor_to(row.max(0) as usize, col);
+    }
+
+    fn set_cursor_col(&mut self, col: INT) {
+        let mut state = self.0.borrow_mut();
+        let row = state.get_focused_buffer().get_cursor().row_scroll();
+        state.move_cursor_to(row, col.max(0) as usize);
+    }
+
+    fn set_scroll_row(&mut self, row: INT) {
+        self.0.borrow_mut().scroll_to(row.max(0) as usize);
+    }
+
+    fn buffer(&self, id: usize) -> ScriptBuffer {
+        ScriptBuffer {
+            state: self.0.clone(),
+            id,
+        }
+    }
+
+    fn current_buffer(&mut self) -> ScriptBuffer {
+        let id = self.0.borrow().focused;
+        self.buffer(id)
+    }
+
+    fn buffers(&mut self) -> Array {
+        let count = self.0.borrow().buffers.len();
+        (0..count)
+            .map(|id| Dynamic::from(self.buffer(id)))
+            .collect()
+    }
+}
+
+impl CustomType for ReviApi {
+    fn build(mut builder: TypeBuilder<Self>) {
+        builder
+            .with_name("Revi")
+            .with_fn("export_command", Self::export_function)
+            .with_fn("export_command", Self::export_command)
+            .with_fn("command", Self::command)
+            .with_fn("command", Self::command_no_opts)
+            .with_fn("autocmd", Self::autocmd)
+            .with_fn("autocmd", Self::autocmd_any)
+            .with_fn("message", Self::message)
+            .with_fn("set_cursor_row", Self::set_cursor_row)
+            .with_fn("set_cursor_col", Self::set_cursor_col)
+            .with_fn("set_scroll_row", Self::set_scroll_row)
+            .with_fn("current_buffer", Self::current_buffer)
+            .with_fn("buffers", Self::buffers)
+            .with_get_set("mode", Self::get_mode, Self::set_mode);
+        // `nmap`, `inoremap`, `vunmap` and the rest, the ones without a mode letter
+        // are for normal and visual mode like `:map`.
+        for prefix in ["", "n", "v", "i", "c"] {
+            let (modes, _) = map_keys::map_command(&format!("{prefix}map")).expect("map command");
+            builder
+                .with_fn(
+                    format!("{prefix}map"),
+                    move |api: &mut Self, keys: ImmutableString, rhs: ImmutableString| {
+                        api.map_keys(modes, &keys, &rhs, true)
+                    },
+                )
+                .with_fn(
+                    format!("{prefix}map"),
+                    move |api: &mut Self, keys: ImmutableString, func: FnPtr| {
+                        api.map_function(modes, &keys, func)
+                    },
+                )
+                .with_fn(
+                    format!("{prefix}noremap"),
+                    move |api: &mut Self, keys: ImmutableString, rhs: ImmutableString| {
+                        api.map_keys(modes, &keys, &rhs, false)
+                    },
+                )
+                .with_fn(
+                    format!("{prefix}unmap"),
+                    move |api: &mut Self, keys: ImmutableString| api.unmap(modes, &keys),
+                );
+        }
+    }
+}
+
+/// The Rhai engine and the config script it ran, callbacks are called against it.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<State>>,
+}
+
+impl Script {
+    pub fn new(state: Rc<RefCell<State>>) -> Self {
+        let mut engine = Engine::new();
+        engine.build_type::<ReviApi>().build_type::<ScriptBuffer>();
+        Self {
+            engine,
+            ast: AST::empty(),
+            state,
+        }
+    }
+
+    fn report(&self, msg: impl Into<String>) {
+        self.state.borrow_mut().messages.push(script_error(msg));
+    }
+
+    /// Runs the config at `path`, errors show up as a message in the editor.
+    pub fn load(&mut self, path: &Path) {
+        match std::fs::read_to_string(path) {
+            Ok(src) => self.run(&src),
+            Err(err) => self.report(format!("{}: {err}", path.display())),
+        }
+    }
+
+    /// Runs `src` with `revi` in scope, functions it defines stay around for callbacks.
+    pub fn run(&mut self, src: &str) {
+        let mut scope = Scope::new();
+        scope.push_constant("revi", ReviApi(self.state.clone()));
+        let ast = match self.engine.compile_with_scope(&scope, src) {
+            Ok(ast) => ast,
+            Err(err) => return self.report(err.to_string()),
+        };
+        self.ast += ast.clone_functions_only();
+        if let Err(err) = self.engine.run_ast_with_scope(&mut scope, &ast) {
+            self.report(err.to_string());
+        }
+        self.run_pending();
+    }
+
+    /// Makes the calls the editor queued up while it was borrowed.
+    pub fn run_pending(&self) {
+        loop {
+            let calls = std::mem::take(&mut self.state.borrow_mut().callbacks.pending);
+            if calls.is_empty() {
+                return;
+            }
+            for Call { func, args } in calls {
+                if let Err(err) = func.call::<Dynamic>(&self.engine, &self.ast, args) {
+                    self.report(err.to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl Script {
+    /// Types `keys` the way the runtime does, the script calls a message queues
+    /// run before the message after it.
+    pub fn feed(&self, keys: &str) {
+        for key in revi_ui::string_to_keys(keys) {
+            self.update(Message::KeyPress(key));
+        }
+    }
+
+    fn update(&self, message: Message) {
+        let next = self.state.borrow_mut().handle(message);
+        self.run_pending();
+        let task = self.state.borrow_mut().task(next);
+        let (sender, _) = std::sync::mpsc::channel();
+        for message in task.start(&sender) {
+            self.update(message);
+        }
+    }
+}
+
+#[test]
+fn mappings_and_commands() {
+    let state = State::with_text("one\ntwo\nthree\n", Size::new(80, 24));
+    let state = Rc::new(RefCell::new(state));
+    let mut script = Script::new(state.clone());
+    script.run(
+        r#"
+        fn greet() { "hi" }
+        revi.nmap("Q", || revi.set_cursor_row(2));
+        revi.nmap("T", "Q");
+        revi.export_command("Greet", || revi.message(greet()));
+        "#,
+    );
+    let row = || {
+        state
+            .borrow()
+            .get_focused_buffer()
+            .get_cursor()
+            .row_scroll()
+    };
+    script.feed("Q");
+    assert_eq!(row(), 2);
+    script.feed("ggT");
+    assert_eq!(row(), 2);
+    script.feed(":Greet<enter>");
+    assert_eq!(state.borrow().messages.last().unwrap().message, "hi");
+    script.run("revi.nope();");
+    assert_eq!(state.borrow().messages.len(), 2);
+}
+
+#[test]
+fn buffer_api() {
+    let mut state = State::with_text("one\ntwo\nthree\nfour\n", Size::new(80, 24));
+    state.buffers[0].name = "notes.txt".into();
+    let state = Rc::new(RefCell::new(state));
+    let mut script = Script::new(state.clone());
+    script.run(
+        r#"
+        let buf = revi.current_buffer();
+        if buf.name != "notes.txt" || buf.line_count() != 4 { throw "wrong buffer"; }
+        buf.replace_line(0, buf.line(0).to_upper());
+        buf.insert(1, 3, "!");
+        buf.delete(2..=2);
+        if revi.buffers().len() != 1 || !buf.is_modified { throw "not modified"; }
+        revi.message(buf.get_text());
+        buf.line(10);
+        "#,
+    );
+    let messages = &state.borrow().messages;
+    assert_eq!(messages[0].message, "ONE\ntwo!\nfour\n");
+    assert!(messages[1].message.contains("out of range"));
+}
+
+#[test]
+fn autocommands() {
+    let mut state = State::with_text("one\ntwo\n", Size::new(80, 24));
+    state.buffers[0].name = "main.rs".into();
+    let state = Rc::new(RefCell::new(state));
+    let mut script = Script::new(state.clone());
+    script.run(
+        r#"
+        revi.autocmd("InsertEnter,InsertLeave", "*.rs", |ev| revi.message(ev.event));
+        revi.autocmd("InsertEnter", "*.toml", |ev| revi.message("toml"));
+        revi.autocmd("ModeChanged", "normal:v-line", |ev| revi.message(ev.new_mode));
+        revi.autocmd("CursorMoved", |ev| revi.message(ev.file));
+        "#,
+    );
+    script.feed("ia<esc>Vj");
+    let messages = state
+        .borrow()
+        .messages
+        .iter()
+        .map(|message| message.message.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        ["InsertEnter", "InsertLeave", "main.rs", "v-line", "main.rs"]
+    );
+}
+
+#[test]
+fn write_hooks_run_first() {
+    let dir = std::env::temp_dir().join(format!("revi-hooks-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("hook.txt").to_string_lossy().into_owned();
+    let mut state = State::with_text("one\n", Size::new(80, 24));
+    state.buffers[0].name = file.clone();
+    let state = Rc::new(RefCell::new(state));
+    let mut script = Script::new(state.clone());
+    script.run(
+        r#"
+        revi.nmap("<space>w", ":w<cr>");
+        revi.autocmd("BufWritePre", |ev| revi.current_buffer().insert(0, 0, "// "));
+        "#,
+    );
+    // The mapping types `:w` without going back to the runtime in between.
+    script.feed("<space>w");
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "// one\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn user_commands() {
+    let state = State::with_text("one\ntwo\nthree\n", Size::new(80, 24));
+    let state = Rc::new(RefCell::new(state));
+    let mut script = Script::new(state.clone());
+    script.run(
+        r#"
+        revi.command("Show", |cmd| {
+            revi.message(`${cmd.args} ${cmd.bang} ${cmd.rows.start}-${cmd.rows.end}`);
+        }, #{nargs: "*", bang: true, range: "%", complete: ["alpha", "beta"]});
+        revi.command("Plain", |cmd| revi.message("plain"));
+        revi.command("lower", |cmd| ());
+        revi.export_command("Raw", || revi.message("raw"));
+        "#,
+    );
+    script.feed(":Show! a b<enter>:2,3Show<enter>:Plain x<enter>:1Plain<enter>");
+    script.feed(":Sh<tab> b<tab><enter>:1,3Raw<enter>");
+    // `<tab>` again goes through the rest of the matches.
+    script.feed(":Show <tab><tab><enter>");
+    let messages = state
+        .borrow()
+        .messages
+        .iter()
+        .map(|message| message.message.clone())
+        .collect::<Vec<_>>();
+    assert!(messages[0].contains("capital"));
+    assert_eq!(
+        messages[1..],
+        [
+            "[\"a\", \"b\"] true 0-2",
+            "[] false 1-2",
+            "Trailing characters: x",
+            "No range allowed",
+            "[\"beta\"] false 0-2",
+            "No range allowed",
+            "[\"beta\"] false 0-2",
+        ]
+    );
+}
+
+#[test]
+fn mode_mappings() {
+    let state = State::with_text("one\ntwo\nthree\n", Size::new(80, 24));
+    let state = Rc::new(RefCell::new(state));
+    let mut script = Script::new(state.clone());
+    script.run(
+        r#"
+        revi.imap("jk", "<esc>");
+        revi.nmap("x", "dd");
+        revi.nnoremap("X", "x");
+        revi.nmap("Z", "X");
+        revi.vmap("q", || revi.message("visual"));
+        revi.nmap("gu", "u");
+        revi.nunmap("gu");
+        "#,
+    );
+    let text = || state.borrow().buffers[0].get_all_text();
+    // `X` types a plain `x`, `Z` maps to `X` so it does too.
+    script.feed("XZ");
+    assert_eq!(text(), "e\ntwo\nthree\n");
+    script.feed("x");
+    assert_eq!(text(), "two\nthree\n");
+    script.feed("ijxjk");
+    assert_eq!(text(), "jxtwo\nthree\n");
+    assert_eq!(state.borrow().mode, Mode::Normal);
+    script.feed(":nmap<enter>");
+    let listing = state.borrow().messages.last().unwrap().message.clone();
+    assert_eq!(
+        listing,
+        "n  x             dd\nn  X           * x\nn  Z             X"
+    );
+    script.feed(":iunmap jk<enter>:unmap gu<enter>");
+    assert_eq!(
+        state.borrow().messages.last().unwrap().message,
+        "No such mapping: gu"
+    );
+    // A `rhs` starting with its own `lhs` doesn't map it again.
+    script.run(r#"revi.nmap("j", "jA!<esc>");"#);
+    script.feed("ggj");
+    assert_eq!(text(), "jxtwo\nthree!\n");
+}
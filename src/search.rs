@@ -0,0 +1,116 @@
+use std::ops::Range;
+
+use regex::Regex;
+use ropey::Rope;
+
+use crate::buffer::Cursor;
+
+/// Last search typed at the `/` or `?` prompt.
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub pattern: String,
+    pub forward: bool,
+    /// Matches are highlighted until `:nohlsearch`.
+    pub highlight: bool,
+    /// Where the cursor was when the prompt opened, incremental search starts here.
+    pub origin: Option<Cursor>,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            pattern: String::new(),
+            forward: true,
+            highlight: false,
+            origin: None,
+        }
+    }
+}
+
+impl Search {
+    /// Compiles `pattern`, a leading `\V` matches the rest literally.
+    pub fn compile(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        match pattern.strip_prefix("\\V") {
+            Some(literal) => Regex::new(&regex::escape(literal)),
+            None => Regex::new(pattern),
+        }
+    }
+}
+
+/// Char range of the next match starting after `from`, or before it going backwards,
+/// wrapping around the end of the buffer. Lines are searched one at a time going
+/// out from `from`, so a match close by is found without reading the whole file.
+pub fn find(rope: &Rope, regex: &Regex, from: usize, forward: bool) -> Option<Range<usize>> {
+    let rows = rope.len_lines();
+    let row = rope.char_to_line(from.min(rope.len_chars()));
+    let in_row = |row: usize| {
+        let start = rope.line_to_char(row);
+        let line = rope.line(row).to_string();
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        char_ranges(regex, line)
+            .map(|m| start + m.start..start + m.end)
+            .collect::<Vec<_>>()
+    };
+    let here = in_row(row);
+    let after = if forward {
+        here.iter().find(|m| m.start > from)
+    } else {
+        here.iter().rev().find(|m| m.start < from)
+    };
+    if let Some(found) = after {
+        return Some(found.clone());
+    }
+    // The rest of the buffer, then round to the start of this line again.
+    (1..=rows).find_map(|step| {
+        let row = if forward {
+            (row + step) % rows
+        } else {
+            (row + rows - step) % rows
+        };
+        let found = in_row(row);
+        if forward {
+            found.first().cloned()
+        } else {
+            found.last().cloned()
+        }
+    })
+}
+
+/// Char ranges of the matches in `line`, empty ones too.
+fn char_ranges<'a>(regex: &'a Regex, line: &'a str) -> impl Iterator<Item = Range<usize>> + 'a {
+    regex
+        .find_iter(line)
+        .map(|m| line[..m.start()].chars().count()..line[..m.end()].chars().count())
+}
+
+/// Char ranges of every match in a single line.
+pub fn matches_in_line(regex: &Regex, line: &str) -> Vec<Range<usize>> {
+    char_ranges(regex, line).filter(|r| !r.is_empty()).collect()
+}
+
+#[test]
+fn find_wraps_around() {
+    let rope = Rope::from_str("foo bar\nbar foo\n");
+    let search = Search::default();
+    let regex = search.compile("foo").unwrap();
+    assert_eq!(find(&rope, &regex, 0, true), Some(12..15));
+    assert_eq!(find(&rope, &regex, 12, true), Some(0..3));
+    assert_eq!(find(&rope, &regex, 12, false), Some(0..3));
+    assert_eq!(find(&rope, &regex, 0, false), Some(12..15));
+    let regex = search.compile("b.r").unwrap();
+    assert_eq!(find(&rope, &regex, 4, true), Some(8..11));
+    // Each line is its own text, `$` is the end of a line.
+    let regex = search.compile("foo$").unwrap();
+    assert_eq!(find(&rope, &regex, 0, true), Some(12..15));
+    assert_eq!(find(&rope, &regex, 13, true), Some(12..15));
+}
+
+#[test]
+fn literal_patterns() {
+    let search = Search::default();
+    let line = "a.b axb";
+    let regex = search.compile("\\Va.b").unwrap();
+    assert_eq!(matches_in_line(&regex, line), vec![0..3]);
+    let regex = search.compile("a.b").unwrap();
+    assert_eq!(matches_in_line(&regex, line), vec![0..3, 4..7]);
+}
@@ -1,55 +1,222 @@
 use revi_ui::application::App;
 use revi_ui::container::Container;
 use revi_ui::event::Event;
+use revi_ui::grid;
 use revi_ui::layout::{Alignment, Pos, Rect, Stack};
 use revi_ui::text::Text;
-use revi_ui::{layout::Size, size, Keys};
-use revi_ui::{Attribute, Color, SetCursorStyle, Subscription};
+use revi_ui::widget::BoxWidget;
+use revi_ui::{layout::Size, size, string_to_keys, Keys};
+use revi_ui::{Attribute, Color, Key, SetCursorStyle, Subscription, Task};
+use std::time::Duration;
 
 use super::{Mode, Settings};
-use crate::buffer::Buffer;
-use crate::map_keys::Mapper;
+use crate::autocmd;
+use crate::buffer::{Buffer, Cursor};
+use crate::encoding::Encoding;
+use crate::ex::{self, LineRange, Lines};
+use crate::grapheme;
+use crate::map_keys::{self, MapKind, Mapper, UserLookup};
 use crate::message::Message;
 use crate::message::UserMessageBuilder;
-use crate::parse_keys::KeyParser;
+use crate::motion::{Motion, Operator, Span};
+use crate::options::{self, Options, Scope, Value};
+use crate::parse_keys::{KeyParser, OperatorParse};
+use crate::register::{Register, Registers};
+use crate::script::{Call, Callbacks};
+use crate::search::{self, Search};
+use crate::substitute::{Confirm, Replace, Substitute};
 use crate::trie;
+use crate::user_command::{Complete, RangeDefault};
+use crate::window::{self, Direction, Layout, LineNumbers, Window};
+use crate::wrap::{ScreenLine, Wrap};
+
+/// How many mappings deep `FeedKeys` follows before giving up.
+const MAX_MAP_DEPTH: usize = 100;
+
+/// Most bytes one put makes, `99999999999p` errors out instead of running out of memory.
+const MAX_PUT: usize = 64 << 20;
 
 #[derive(Debug)]
 pub struct State {
     pub focused: usize,
+    pub windows: Vec<Window>,
+    /// Index of the focused window in `windows`.
+    pub window: usize,
+    pub layout: Layout,
+    pub options: Options,
     pub buffers: Vec<Buffer>,
     pub messages: Vec<UserMessageBuilder>,
     pub command_list: trie::Trie,
     pub tab_index: usize,
+    /// The command line `<tab>` last made and the argument it completed from.
+    completion: Option<(String, String)>,
     pub command: Buffer,
+    pub registers: Registers,
+    pub register: Option<char>,
+    pub search: Search,
+    pub confirm: Option<Confirm>,
+    /// Pattern and replacement of the last `:s`, a bare `:s` does it again.
+    last_substitute: Option<(String, String)>,
+    /// Where a Visual-Block `c` starts typing, column and row, and the rows below
+    /// that get the same text once insert mode is left.
+    block_change: Option<(Pos, Vec<usize>)>,
     pub map_keys: Mapper,
     pub key_parse: KeyParser,
     pub mode: Mode,
     pub size: Size,
     pub is_running: bool,
+    pub callbacks: Callbacks,
+    /// How deep mappings are replaying each other's keys.
+    map_depth: usize,
+    /// Set while a `noremap` right hand side is typed, user mappings are skipped.
+    noremap: bool,
+    /// How many `handle` calls are running, only the outermost fires `CursorMoved`.
+    updating: usize,
+    /// Work for the runtime's worker threads, handed over after each message.
+    tasks: Vec<Task<Message>>,
 }
 
 impl State {
-    pub fn set_new_buffer_as_focused(&mut self, buf: Buffer) {
+    pub fn set_new_buffer_as_focused(&mut self, mut buf: Buffer) {
         let idx = self.buffers.len();
+        buf.set_option_defaults(self.options.locals(Scope::Buffer));
         self.buffers.push(buf);
+        self.show_buffer(idx);
+        self.buffer_loaded(idx);
+    }
+
+    /// Shows buffer `idx` in the focused window.
+    fn show_buffer(&mut self, idx: usize) {
         self.focused = idx;
+        self.windows[self.window].buffer = idx;
     }
 
     pub fn get_focused_buffer(&self) -> &Buffer {
         match self.mode {
-            Mode::Command => &self.command,
+            Mode::Command | Mode::Search => &self.command,
             _ => &self.buffers[self.focused],
         }
     }
 
     pub fn get_focused_buffer_mut(&mut self) -> &mut Buffer {
         match self.mode {
-            Mode::Command => &mut self.command,
+            Mode::Command | Mode::Search => &mut self.command,
             _ => &mut self.buffers[self.focused],
         }
     }
 
+    /// Where each window is drawn, the command bar keeps the last row.
+    fn window_rects(&self) -> Vec<(usize, Rect)> {
+        let area = Size::new(self.size.width, self.size.height.saturating_sub(1));
+        self.layout.rects(Rect::new(area))
+    }
+
+    fn window_rect(&self, window: usize) -> Rect {
+        self.window_rects()
+            .into_iter()
+            .find(|(id, _)| *id == window)
+            .map_or_else(Rect::default, |(_, rect)| rect)
+    }
+
+    /// Size of the focused window without its status bar.
+    fn text_area_size(&self) -> Size {
+        let rect = self.window_rect(self.window);
+        Size {
+            width: rect.width.saturating_sub(self.gutter_width(self.window)),
+            height: rect.height.saturating_sub(1),
+        }
+    }
+
+    /// Option `name` as the focused window and its buffer see it.
+    fn option(&self, name: &str) -> &Value {
+        self.window_option(self.window, name)
+    }
+
+    fn window_option(&self, window: usize, name: &str) -> &Value {
+        let window = &self.windows[window];
+        let buffer = &self.buffers[window.buffer].options;
+        self.options.get(name, buffer, &window.options)
+    }
+
+    fn line_numbers(&self, window: usize) -> LineNumbers {
+        LineNumbers::from_flags(
+            self.window_option(window, "number").as_bool(),
+            self.window_option(window, "relativenumber").as_bool(),
+        )
+    }
+
+    /// Columns the line numbers of `window` take, they grow with the buffer.
+    fn gutter_width(&self, window: usize) -> u16 {
+        let lines = self.buffers[self.windows[window].buffer].len_lines() + 1;
+        self.line_numbers(window).width(lines)
+    }
+
+    /// Columns `>` and `<` indent by, `shiftwidth` or `tabstop` when it's `0`.
+    fn shift_width(&self) -> usize {
+        match self.option("shiftwidth").as_int() {
+            0 => self.option("tabstop").as_int().max(1),
+            width => width,
+        }
+    }
+
+    /// Text `>` indents by, tabs are used where `expandtab` is off.
+    fn indent_unit(&self) -> String {
+        let tabstop = self.option("tabstop").as_int().max(1);
+        let width = self.shift_width();
+        if self.option("expandtab").as_bool() || !width.is_multiple_of(tabstop) {
+            return " ".repeat(width);
+        }
+        "\t".repeat(width / tabstop)
+    }
+
+    /// Columns `<tab>` and `<backspace>` work in while inserting, `softtabstop`
+    /// or `tabstop` when it's `0`.
+    fn soft_tabstop(&self) -> usize {
+        match self.option("softtabstop").as_int() {
+            0 => self.option("tabstop").as_int().max(1),
+            width => width,
+        }
+    }
+
+    /// How `window` lays out lines longer than it is wide, `None` with `nowrap`.
+    fn wrap(&self, window: usize) -> Option<Wrap> {
+        if !self.window_option(window, "wrap").as_bool() {
+            return None;
+        }
+        let width = self
+            .window_rect(window)
+            .width
+            .saturating_sub(self.gutter_width(window));
+        Some(Wrap {
+            width: width as usize,
+            linebreak: self.window_option(window, "linebreak").as_bool(),
+            showbreak: self.window_option(window, "showbreak").as_str().to_string(),
+            tabstop: self.window_option(window, "tabstop").as_int().max(1),
+        })
+    }
+
+    /// A wrapped window never scrolls sideways and keeps the cursor's row on screen.
+    fn keep_wrapped(&mut self) {
+        if self.mode.is_command_line() {
+            return;
+        }
+        let Some(wrap) = self.wrap(self.window) else {
+            return;
+        };
+        let height = self.text_area_size().height as usize;
+        self.buffers[self.focused].keep_wrapped(&wrap, height);
+    }
+
+    /// `scrolloff`, keeps that many lines around the cursor on screen.
+    fn keep_scrolloff(&mut self) {
+        let margin = self.option("scrolloff").as_int();
+        if margin == 0 || self.mode.is_command_line() {
+            return;
+        }
+        let size = self.text_area_size();
+        self.buffers[self.focused].keep_margin(margin, size);
+    }
+
     pub fn cursor_up(&mut self) -> Option<Message> {
         let buf = self.get_focused_buffer_mut();
         if buf.cursor_up() {
@@ -59,16 +226,30 @@ impl State {
     }
 
     pub fn cursor_down(&mut self) -> Option<Message> {
-        let Size { height, .. } = self.size;
-        let height = (height - 3) as usize;
+        let height = self.text_area_size().height.saturating_sub(1) as usize;
         let buf = self.get_focused_buffer_mut();
-        let h = height.min(buf.len_lines());
+        let scroll = buf.get_cursor().scroll.y as usize;
+        let h = height.min(buf.len_lines().saturating_sub(scroll));
         if buf.cursor_down(h) {
             return None;
         }
         Some(Message::ScrollDown)
     }
 
+    /// `gj` and `gk` move a row on screen, inside a wrapped line while it goes on.
+    pub fn cursor_screen_row(&mut self, down: bool) -> Option<Message> {
+        let Some(wrap) = self.wrap(self.window) else {
+            return Some(if down {
+                Message::CursorDown
+            } else {
+                Message::CursorUp
+            });
+        };
+        let size = self.text_area_size();
+        self.buffers[self.focused].cursor_screen_row(&wrap, down, size);
+        None
+    }
+
     pub fn cursor_left(&mut self) -> Option<Message> {
         let buf = self.get_focused_buffer_mut();
         if buf.cursor_left() {
@@ -78,7 +259,10 @@ impl State {
     }
 
     pub fn cursor_right(&mut self) -> Option<Message> {
-        let width = self.size.width.saturating_sub(1) as usize;
+        let width = match self.wrap(self.window) {
+            Some(_) if !self.mode.is_command_line() => usize::MAX,
+            _ => self.text_area_size().width as usize,
+        };
         let buf = self.get_focused_buffer_mut();
         if buf.cursor_right(width) {
             return None;
@@ -111,10 +295,9 @@ impl State {
     }
 
     pub fn scroll_down(&mut self) -> Option<Message> {
-        let height = self.size.height as usize;
+        let height = self.text_area_size().height as usize;
         let buf = self.get_focused_buffer_mut();
-        // HACK: the - 2 is for the command line and status bar
-        buf.scroll_down(height - 1);
+        buf.scroll_down(height + 1);
         None
     }
 
@@ -125,7 +308,7 @@ impl State {
     }
 
     pub fn scroll_right(&mut self) -> Option<Message> {
-        let width = self.size.width as usize;
+        let width = self.text_area_size().width as usize;
         let buf = self.get_focused_buffer_mut();
         buf.scroll_right(width);
         None
@@ -133,14 +316,28 @@ impl State {
 
     pub fn insert_at_end(&mut self) -> Option<Message> {
         self.change_mode(Mode::Insert);
-        let buf = self.get_focused_buffer_mut();
-        buf.cursor_end();
+        let size = self.text_area_size();
+        let buf = &mut self.buffers[self.focused];
+        // Past the last char, not on it.
+        let row = buf.get_cursor().row_scroll();
+        let idx = buf.line_to_char(row) + buf.line(row).chars().count();
+        buf.set_cursor_idx(idx, size);
         None
     }
 
     pub fn backspace(&mut self) -> Option<Message> {
+        if self.mode == Mode::Search && self.command.len_chars() == 0 {
+            return Some(Message::CancelSearch);
+        }
+        let soft = self.option("softtabstop").as_int();
+        let soft_tab = self.mode == Mode::Insert && soft > 0;
         let buf = self.get_focused_buffer_mut();
-        buf.backspace();
+        if !soft_tab || !buf.backspace_soft_tab(soft) {
+            buf.backspace();
+        }
+        if self.mode == Mode::Search {
+            return self.incremental_search();
+        }
         None
     }
 
@@ -150,27 +347,665 @@ impl State {
         None
     }
 
+    pub fn motion(&mut self, motion: Motion, count: Option<usize>) -> Option<Message> {
+        let size = self.text_area_size();
+        self.buffers[self.focused].motion(motion, count.unwrap_or(1), count.is_some(), size);
+        None
+    }
+
+    /// Runs `message` `count` times, edits it makes are undone together. Stops
+    /// once a run changes nothing and skips whole laps of one that goes round in
+    /// a circle, so a huge count can't hang the editor.
+    pub fn repeat(&mut self, count: usize, message: Message) -> Option<Message> {
+        self.buffers[self.focused].begin_undo_group();
+        let start = self.snapshot();
+        let mut count = count;
+        let mut done = 0;
+        while done < count {
+            let before = self.snapshot();
+            let mut next = Some(message.clone());
+            while let Some(message) = next {
+                next = self.handle(message);
+            }
+            done += 1;
+            let after = self.snapshot();
+            if after == before {
+                break;
+            }
+            if after == start {
+                count = done + (count - done) % done;
+            }
+        }
+        self.buffers[self.focused].end_undo_group();
+        None
+    }
+
+    /// What a repeated message can change, to tell when it stopped doing anything.
+    fn snapshot(&self) -> (usize, Cursor, usize, usize, Layout) {
+        let buf = &self.buffers[self.focused];
+        (
+            self.window,
+            *buf.get_cursor(),
+            buf.len_chars(),
+            buf.undo_position(),
+            self.layout.clone(),
+        )
+    }
+
+    pub fn operator(
+        &mut self,
+        op: Operator,
+        motion: Motion,
+        count: Option<usize>,
+    ) -> Option<Message> {
+        let buf = &self.buffers[self.focused];
+        // `cw` on a word changes to its end and leaves the white space after it alone.
+        let motion = match (op, motion) {
+            (Operator::Change, Motion::WordForward(big))
+                if buf
+                    .char_at(buf.cursor_idx())
+                    .is_some_and(|c| !c.is_whitespace()) =>
+            {
+                Motion::WordEnd(big)
+            }
+            _ => motion,
+        };
+        let span = buf.span(motion, count.unwrap_or(1), count.is_some())?;
+        self.apply_operator(op, &[span], false)
+    }
+
+    /// Runs `op` over the visual selection from its top left corner.
+    pub fn visual_operator(&mut self, op: Operator) -> Option<Message> {
+        let mode = self.mode;
+        let size = self.text_area_size();
+        let buf = &mut self.buffers[self.focused];
+        let spans = buf.selection_spans(mode);
+        let (start, end) = buf.get_cursor().selection()?;
+        let col = match mode {
+            Mode::VisualBlock => start.x.min(end.x),
+            _ => start.x,
+        };
+        let idx = buf.line_to_char(start.y as usize) + col as usize;
+        buf.set_cursor_idx(idx, size);
+        self.change_mode(Mode::Normal);
+        self.apply_operator(op, &spans, mode == Mode::VisualBlock)
+    }
+
+    /// `spans` are in buffer order, a block has one per row and is yanked one row per line.
+    fn apply_operator(&mut self, op: Operator, spans: &[Span], block: bool) -> Option<Message> {
+        let size = self.text_area_size();
+        let (indent, width) = (self.indent_unit(), self.shift_width());
+        let (first, last) = (*spans.first()?, *spans.last()?);
+        let buf = &mut self.buffers[self.focused];
+        match op {
+            Operator::ShiftRight | Operator::ShiftLeft => {
+                let top = buf.char_to_line(first.start);
+                let bottom = buf.char_to_line(last.end.saturating_sub(1)).max(top);
+                buf.shift_lines(top, bottom, &indent, width, op == Operator::ShiftRight);
+                let idx = buf.first_non_blank(buf.line_to_char(top));
+                buf.set_cursor_idx(idx, size);
+                return None;
+            }
+            Operator::ToggleCase => {
+                buf.toggle_case(spans);
+                buf.set_cursor_idx(first.start, size);
+                return None;
+            }
+            _ => {}
+        }
+        let text = if block {
+            spans
+                .iter()
+                .map(|span| buf.text(*span))
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            buf.text(first)
+        };
+        let reg = Register::new(text, first.linewise);
+        let name = self.register.take();
+        match op {
+            Operator::Yank => self.registers.yank(name, reg),
+            _ => self.registers.delete(name, reg),
+        }
+        match op {
+            Operator::Yank => {
+                let buf = &mut self.buffers[self.focused];
+                if first.start < buf.cursor_idx() && !first.linewise {
+                    buf.set_cursor_idx(first.start, size);
+                }
+            }
+            Operator::Delete => {
+                let buf = &mut self.buffers[self.focused];
+                buf.remove_spans(spans, size);
+                if first.linewise {
+                    let idx = buf.first_non_blank(buf.cursor_idx());
+                    buf.set_cursor_idx(idx, size);
+                }
+                buf.align_cursor();
+            }
+            Operator::Change => {
+                if block && spans.len() > 1 {
+                    let buf = &self.buffers[self.focused];
+                    let top = buf.char_to_line(first.start);
+                    let left = first.start - buf.line_to_char(top);
+                    // Rows too short to reach the block are left alone.
+                    let rows = spans[1..]
+                        .iter()
+                        .filter(|span| span.end > span.start)
+                        .map(|span| buf.char_to_line(span.start))
+                        .collect();
+                    self.block_change = Some((Pos::new(left as u16, top as u16), rows));
+                }
+                self.change_mode(Mode::Insert);
+                let buf = &mut self.buffers[self.focused];
+                let mut spans = spans.to_vec();
+                if let [span] = spans.as_mut_slice() {
+                    if span.linewise {
+                        // Keep the line itself, only its text goes.
+                        span.start = buf.first_non_blank(span.start);
+                        if buf.char_at(span.end.saturating_sub(1)) == Some('\n') {
+                            span.end -= 1;
+                        }
+                        span.end = span.end.max(span.start);
+                        span.linewise = false;
+                    }
+                }
+                buf.remove_spans(&spans, size);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Types what a Visual-Block `c` put on its first row into the other rows.
+    fn repeat_block_change(&mut self) {
+        let Some((start, rows)) = self.block_change.take() else {
+            return;
+        };
+        let buf = &mut self.buffers[self.focused];
+        let from = buf.line_to_char(start.y as usize) + start.x as usize;
+        let to = buf.cursor_idx();
+        if to <= from {
+            return;
+        }
+        let text = buf.text(Span {
+            start: from,
+            end: to,
+            linewise: false,
+        });
+        if text.contains('\n') {
+            return;
+        }
+        for row in rows {
+            let idx = buf.line_to_char(row) + start.x as usize;
+            buf.insert_at(idx, text.as_str());
+        }
+    }
+
+    pub fn put(&mut self, after: bool, count: Option<usize>) -> Option<Message> {
+        let size = self.text_area_size();
+        let Some(reg) = self.registers.get(self.register.take()) else {
+            return Some(
+                UserMessageBuilder::default()
+                    .message("Nothing in register")
+                    .footer("put")
+                    .fg(Color::Red)
+                    .build(),
+            );
+        };
+        let count = count.unwrap_or(1);
+        if reg.text.len().saturating_mul(count) > MAX_PUT {
+            return Some(
+                UserMessageBuilder::default()
+                    .message("Resulting text too long")
+                    .footer("put")
+                    .fg(Color::Red)
+                    .build(),
+            );
+        }
+        let text = reg.text.repeat(count);
+        let buf = &mut self.buffers[self.focused];
+        let idx = buf.cursor_idx();
+        if reg.linewise {
+            let row = buf.get_cursor().row_scroll() + after as usize;
+            let mut at = buf.line_to_char(row);
+            let mut text = text;
+            // Putting below a last line that has no line break of its own.
+            if at == buf.len_chars() && at > 0 && buf.char_at(at - 1) != Some('\n') {
+                text = format!("\n{}", text.trim_end_matches('\n'));
+                at = buf.len_chars();
+            }
+            buf.insert_at(at, text);
+            let first = buf.line_to_char(row);
+            let idx = buf.first_non_blank(first);
+            buf.set_cursor_idx(idx, size);
+        } else {
+            let on_char = buf.char_at(idx).is_some_and(|c| c != '\n');
+            let at = idx + (after && on_char) as usize;
+            let len = text.chars().count();
+            buf.insert_at(at, text);
+            buf.set_cursor_idx(at + len - 1, size);
+        }
+        buf.align_cursor();
+        None
+    }
+
+    pub fn register_list_command(&mut self) -> Option<Message> {
+        let width = self.size.width.saturating_sub(10) as usize;
+        let list = self
+            .registers
+            .list()
+            .iter()
+            .map(|(name, reg)| {
+                let kind = if reg.linewise { 'l' } else { 'c' };
+                let text = reg.text.replace('\n', "^J").replace('\t', "^I");
+                let text = text.chars().take(width).collect::<String>();
+                format!("  {kind}  \"{name}   {text}")
+            })
+            .collect::<Vec<String>>();
+        let msg = std::iter::once("Type Name Content".to_string())
+            .chain(list)
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(
+            UserMessageBuilder::default()
+                .message(msg)
+                .footer("registers")
+                .build(),
+        )
+    }
+
+    pub fn undo(&mut self) -> Option<Message> {
+        if self.buffers[self.focused].undo() {
+            return None;
+        }
+        Some(
+            UserMessageBuilder::default()
+                .message("Already at oldest change")
+                .footer("undo")
+                .fg(Color::Red)
+                .build(),
+        )
+    }
+
+    pub fn redo(&mut self) -> Option<Message> {
+        if self.buffers[self.focused].redo() {
+            return None;
+        }
+        Some(
+            UserMessageBuilder::default()
+                .message("Already at newest change")
+                .footer("redo")
+                .fg(Color::Red)
+                .build(),
+        )
+    }
+
+    pub fn swap_anchor(&mut self) -> Option<Message> {
+        let size = self.text_area_size();
+        self.buffers[self.focused].swap_anchor(size);
+        None
+    }
+
+    pub fn search_prompt(&mut self, forward: bool) -> Option<Message> {
+        self.search.forward = forward;
+        self.search.origin = Some(*self.buffers[self.focused].get_cursor());
+        self.change_mode(Mode::Search)
+    }
+
+    /// Moves the cursor to the first match of the prompt as it's typed.
+    fn incremental_search(&mut self) -> Option<Message> {
+        let origin = self.search.origin?;
+        let size = self.text_area_size();
+        let pattern = self.command.get_all_text();
+        let buf = &mut self.buffers[self.focused];
+        buf.set_cursor(origin);
+        if pattern.is_empty() {
+            return None;
+        }
+        let regex = self.search.compile(&pattern).ok()?;
+        if let Some(found) = buf.search(&regex, self.search.forward) {
+            buf.set_cursor_idx(found.start, size);
+        }
+        None
+    }
+
+    pub fn execute_search(&mut self) -> Option<Message> {
+        let pattern = self.command.get_all_text();
+        let origin = self.search.origin.take();
+        self.change_mode(Mode::Normal);
+        if let Some(origin) = origin {
+            self.buffers[self.focused].set_cursor(origin);
+        }
+        if !pattern.is_empty() {
+            self.search.pattern = pattern;
+        }
+        self.search_next(false)
+    }
+
+    pub fn cancel_search(&mut self) -> Option<Message> {
+        if let Some(origin) = self.search.origin.take() {
+            self.buffers[self.focused].set_cursor(origin);
+        }
+        self.change_mode(Mode::Normal)
+    }
+
+    pub fn search_next(&mut self, reverse: bool) -> Option<Message> {
+        let size = self.text_area_size();
+        let error = |msg: String| {
+            Some(
+                UserMessageBuilder::default()
+                    .message(msg)
+                    .footer("search")
+                    .fg(Color::Red)
+                    .build(),
+            )
+        };
+        if self.search.pattern.is_empty() {
+            return error("No previous regular expression".into());
+        }
+        let regex = match self.search.compile(&self.search.pattern) {
+            Ok(regex) => regex,
+            Err(err) => return error(err.to_string()),
+        };
+        self.search.highlight = true;
+        let forward = self.search.forward != reverse;
+        let buf = &mut self.buffers[self.focused];
+        let Some(found) = buf.search(&regex, forward) else {
+            return error(format!("Pattern not found: {}", self.search.pattern));
+        };
+        buf.set_cursor_idx(found.start, size);
+        None
+    }
+
+    pub fn no_highlight(&mut self) -> Option<Message> {
+        self.search.highlight = false;
+        None
+    }
+
     pub fn user_message(&mut self, builder: UserMessageBuilder) -> Option<Message> {
         self.messages.push(builder);
         None
     }
 
     pub fn key_press(&mut self, keys: Keys) -> Option<Message> {
+        if self.confirm.is_some() {
+            return self.confirm_key(keys);
+        }
+        let takes_count = self.mode == Mode::Normal || self.mode.is_visual();
+        if takes_count && (self.key_parse.push_register(keys) || self.key_parse.push_count(keys)) {
+            return None;
+        }
         self.key_parse.push(keys);
         Some(Message::CheckForMapping)
     }
 
+    /// Types `keys` as if the user did, mappings in them are followed up to
+    /// `MAX_MAP_DEPTH` deep so one mapping to itself can't hang the editor.
+    pub fn feed_keys(&mut self, keys: &str, remap: bool) -> Option<Message> {
+        self.feed_parsed(&string_to_keys(keys), remap)
+    }
+
+    fn feed_parsed(&mut self, keys: &[Keys], remap: bool) -> Option<Message> {
+        if self.map_depth >= MAX_MAP_DEPTH {
+            return Some(command_error("Recursive mapping"));
+        }
+        self.map_depth += 1;
+        let noremap = std::mem::replace(&mut self.noremap, !remap);
+        self.press_keys(keys);
+        self.noremap = noremap;
+        self.map_depth -= 1;
+        None
+    }
+
+    fn press_keys(&mut self, keys: &[Keys]) {
+        for key in keys {
+            let mut next = Some(Message::KeyPress(*key));
+            while let Some(message) = next {
+                next = self.handle(message);
+            }
+        }
+    }
+
+    /// Runs the user mapping that took the first `len` pending keys, the keys
+    /// after it are typed again.
+    fn user_mapping(&mut self, message: Message, len: usize) -> Option<Message> {
+        let (lhs, rest) = self.key_parse.get_keys().split_at(len);
+        let (lhs, rest) = (lhs.to_vec(), rest.to_vec());
+        // `"a3Y` with `Y` mapped to `y$` types `"a3y$`.
+        let mut prefix = String::new();
+        if let Some(name) = self.key_parse.register {
+            prefix = format!("\"{name}");
+        }
+        if let Some(count) = self.key_parse.count {
+            prefix += &count.to_string();
+        }
+        self.key_parse.clear();
+        let mut next = match message {
+            // `nmap j jzz` types its own `j` without looking it up again, like Vim.
+            Message::FeedKeys(keys, true) if string_to_keys(&keys).starts_with(&lhs) => {
+                let mut own = string_to_keys(&prefix);
+                own.extend_from_slice(&lhs);
+                let keys = string_to_keys(&keys);
+                self.feed_parsed(&own, false)
+                    .or_else(|| self.feed_parsed(&keys[lhs.len()..], true))
+            }
+            Message::FeedKeys(keys, remap) => {
+                Some(Message::FeedKeys(format!("{prefix}{keys}"), remap))
+            }
+            message => Some(message),
+        };
+        while let Some(message) = next {
+            next = self.handle(message);
+        }
+        self.press_keys(&rest);
+        None
+    }
+
+    /// `:map`, `:nnoremap`, `:iunmap` and the rest. `{lhs} {rhs}` maps, `{lhs}`
+    /// alone lists the mappings starting with it and nothing lists them all.
+    fn map_command(&mut self, modes: &[Mode], kind: MapKind, args: &str) -> Option<Message> {
+        let args = args.trim();
+        let (lhs, rhs) = args
+            .split_once(char::is_whitespace)
+            .map_or((args, ""), |(lhs, rhs)| (lhs, rhs.trim_start()));
+        match kind {
+            MapKind::Unmap if lhs.is_empty() => Some(command_error("Argument required")),
+            MapKind::Unmap => {
+                let mut found = false;
+                for mode in modes {
+                    found |= self.map_keys.unmap(*mode, lhs);
+                }
+                (!found).then(|| command_error(format!("No such mapping: {lhs}")))
+            }
+            _ if rhs.is_empty() => Some(self.map_list(modes, lhs)),
+            MapKind::Map | MapKind::NoRemap => {
+                let remap = kind == MapKind::Map;
+                for mode in modes {
+                    let message = Message::FeedKeys(rhs.to_string(), remap);
+                    self.map_keys.map(*mode, lhs, rhs, !remap, message);
+                }
+                None
+            }
+        }
+    }
+
+    /// The user mappings of `modes` starting with `lhs`, a `*` marks a `noremap`.
+    fn map_list(&self, modes: &[Mode], lhs: &str) -> Message {
+        let lines = modes
+            .iter()
+            .flat_map(|mode| {
+                let letter = match mode {
+                    Mode::Insert => 'i',
+                    Mode::Command => 'c',
+                    mode if mode.is_visual() => 'v',
+                    _ => 'n',
+                };
+                self.map_keys
+                    .user_maps(mode)
+                    .iter()
+                    .filter(|map| map.lhs.starts_with(lhs))
+                    .map(move |map| {
+                        let star = if map.noremap { '*' } else { ' ' };
+                        format!("{letter}  {:<12}{star} {}", map.lhs, map.rhs)
+                    })
+            })
+            .collect::<Vec<_>>();
+        let message = if lines.is_empty() {
+            "No mapping found".to_string()
+        } else {
+            lines.join("\n")
+        };
+        UserMessageBuilder::default()
+            .message(message)
+            .footer("map")
+            .build()
+    }
+
+    pub fn script_call(&mut self, id: usize) -> Option<Message> {
+        let func = self.callbacks.mapped.get(id)?.clone();
+        self.callbacks.pending.push(Call {
+            func,
+            args: Vec::new(),
+        });
+        None
+    }
+
+    /// A command a script made, `range` is what was typed before it.
+    fn user_command(
+        &mut self,
+        name: &str,
+        range: Option<LineRange>,
+        tail: &str,
+    ) -> Option<Message> {
+        let command = &self.callbacks.commands[name];
+        if range.is_some() && command.range == RangeDefault::NotAllowed {
+            return Some(command_error("No range allowed"));
+        }
+        let (bang, tail) = match tail.strip_prefix('!') {
+            Some(tail) => (true, tail),
+            None => (false, tail),
+        };
+        if command.raw {
+            let args = match tail.trim() {
+                "" => Vec::new(),
+                args => vec![args.into()],
+            };
+            let func = command.func.clone();
+            self.callbacks.pending.push(Call { func, args });
+            return None;
+        }
+        if bang && !command.bang {
+            return Some(command_error("No ! allowed"));
+        }
+        let buf = &self.buffers[self.focused];
+        let rows = match (range, command.range) {
+            (Some(rows), _) => rows,
+            (None, RangeDefault::All) => LineRange {
+                start: 0,
+                end: buf.len_lines(),
+            },
+            (None, _) => LineRange::line(buf.get_cursor().row_scroll()),
+        };
+        let args = match command.args(tail) {
+            Ok(args) => args,
+            Err(err) => return Some(command_error(err)),
+        };
+        let mut data = rhai::Map::new();
+        data.insert("args".into(), args.into());
+        data.insert("bang".into(), bang.into());
+        let rows = rows.start as rhai::INT..=rows.end as rhai::INT;
+        data.insert("rows".into(), rhai::Dynamic::from(rows));
+        let func = command.func.clone();
+        self.callbacks.pending.push(Call {
+            func,
+            args: vec![data.into()],
+        });
+        None
+    }
+
+    /// Runs `edit` on buffer `id`, its cursor stays put as far as the new text allows.
+    pub fn edit_buffer<T>(&mut self, id: usize, edit: impl FnOnce(&mut Buffer) -> T) -> T {
+        let size = self.text_area_size();
+        let buf = &mut self.buffers[id];
+        let cursor = *buf.get_cursor();
+        let out = edit(buf);
+        buf.set_cursor(cursor);
+        buf.clamp_cursor(size);
+        out
+    }
+
+    /// Moves the cursor to `row` and `col`, both clamped to the text.
+    pub fn move_cursor_to(&mut self, row: usize, col: usize) {
+        let size = self.text_area_size();
+        let buf = &mut self.buffers[self.focused];
+        let row = row.min(buf.len_lines());
+        let idx = buf.line_to_char(row) + col.min(buf.line_len(row));
+        buf.set_cursor_idx(idx, size);
+    }
+
+    pub fn scroll_to(&mut self, row: usize) {
+        let size = self.text_area_size();
+        self.buffers[self.focused].scroll_to(row, size);
+    }
+
     pub fn insert_mode_insert(&mut self, c: impl Into<String>) -> Option<Message> {
         self.buffers[self.focused].insert(c);
         None
     }
 
+    /// Fills to the next `softtabstop` column, with spaces under `expandtab` and
+    /// as many tabs as fit otherwise.
+    pub fn insert_tab(&mut self) -> Option<Message> {
+        let tabstop = self.option("tabstop").as_int().max(1);
+        let soft = self.soft_tabstop();
+        let expand = self.option("expandtab").as_bool();
+        let buf = &mut self.buffers[self.focused];
+        let col = buf.display_col();
+        let stop = (col / soft + 1) * soft;
+        buf.insert(grapheme::fill(col, stop, tabstop, expand));
+        None
+    }
+
     pub fn change_mode(&mut self, mode: Mode) -> Option<Message> {
-        if let Mode::Command = self.mode {
+        // Typing the key of the visual mode you're in leaves it.
+        let mode = match mode {
+            mode if mode == self.mode && mode.is_visual() => Mode::Normal,
+            mode => mode,
+        };
+        match (self.mode.is_visual(), mode.is_visual()) {
+            (false, true) => self.buffers[self.focused].set_anchor(true),
+            (true, false) => {
+                let buf = &mut self.buffers[self.focused];
+                if let Some((start, end)) = buf.get_cursor().selection() {
+                    buf.set_mark('<', start);
+                    buf.set_mark('>', end);
+                }
+                buf.set_anchor(false);
+            }
+            _ => {}
+        }
+        match (self.mode, mode) {
+            (Mode::Insert, Mode::Insert) => {}
+            (_, Mode::Insert) => self.buffers[self.focused].begin_undo_group(),
+            (Mode::Insert, _) => {
+                self.repeat_block_change();
+                self.buffers[self.focused].end_undo_group();
+            }
+            _ => {}
+        }
+        if self.mode.is_command_line() {
             self.get_focused_buffer_mut().clear();
         }
         self.get_focused_buffer_mut().align_cursor();
-        self.mode = mode;
+        let from_visual = self.mode.is_visual();
+        let old = std::mem::replace(&mut self.mode, mode);
+        self.mode_changed(old);
+        // `:` on a selection works on its lines.
+        if from_visual && mode == Mode::Command {
+            self.command.insert("'<,'>");
+        }
         None
     }
 
@@ -188,20 +1023,79 @@ impl State {
             .trim()
             .to_string();
         self.command = Buffer::default();
-        let (cmd, tail) = command.split_once(' ').unwrap_or((command.as_str(), ""));
         self.change_mode(Mode::Normal);
+        let buf = &self.buffers[self.focused];
+        let current = buf.get_cursor().row_scroll();
+        let lines = ExLines {
+            buf,
+            search: &self.search,
+        };
+        let (range, rest) = match ex::parse_range(&command, &lines) {
+            Ok(parsed) => parsed,
+            Err(err) => return Some(command_error(err)),
+        };
+        let (cmd, tail) = ex::split_command(rest);
+        let rows = range.unwrap_or(LineRange::line(current));
+        match cmd {
+            "" if tail.starts_with('!') && range.is_some() => {
+                return Some(command_error("No range allowed"))
+            }
+            "" if tail.starts_with('!') => return self.shell_command(&tail[1..]),
+            "" => return self.goto_row(rows.end),
+            "substitute" | "s" => return self.substitute(rows, tail),
+            "delete" | "d" => return self.delete_rows(rows, tail),
+            "yank" | "y" => return self.yank_rows(rows, tail),
+            "move" | "m" => return self.move_rows(rows, tail, false),
+            "copy" | "co" | "t" => return self.move_rows(rows, tail, true),
+            "normal" | "norm" => return self.normal_command(rows, tail),
+            "retab" | "ret" => {
+                let all = LineRange {
+                    start: 0,
+                    end: self.buffers[self.focused].len_lines(),
+                };
+                return self.retab(range.unwrap_or(all), tail);
+            }
+            "write" | "w" if range.is_some() => return self.write_rows(rows, tail),
+            _ if self.callbacks.commands.contains_key(cmd) => {
+                return self.user_command(cmd, range, tail)
+            }
+            _ if range.is_some() => return Some(command_error("No range allowed")),
+            _ => {}
+        }
         match cmd {
-            "write" | "w" => Some(Message::Save({
-                if tail.is_empty() {
+            "write" | "w" => {
+                let (force, tail) = match tail.strip_prefix('!') {
+                    Some(tail) => (true, tail.trim()),
+                    None => (false, tail),
+                };
+                let filename = if tail.is_empty() {
                     None
                 } else {
                     Some(tail.to_string())
-                }
-            })),
-            "quit" | "exit" | "q" => Some(Message::Quit),
+                };
+                Some(Message::Save(filename, force))
+            }
+            "quit" | "exit" | "q" => Some(Message::CloseWindow),
+            "qall" | "qa" => Some(Message::Quit),
+            "split" | "sp" => self.split_window(Stack::Vertically, tail),
+            "vsplit" | "vs" => self.split_window(Stack::Horizontally, tail),
+            "close" | "clo" if self.windows.len() == 1 => {
+                Some(command_error("Cannot close last window"))
+            }
+            "close" | "clo" => Some(Message::CloseWindow),
             "ls" => Some(Message::BufferList),
+            "registers" | "reg" | "display" | "di" => Some(Message::RegisterList),
             "edit" | "e" => Some(Message::EditFile(tail.to_string())),
             "buffer" | "b" => Some(Message::SwapBuffer(tail.to_string())),
+            "undo" | "u" => Some(Message::Undo),
+            "redo" | "red" => Some(Message::Redo),
+            "nohlsearch" | "noh" => Some(Message::NoHighlight),
+            "set" | "se" => self.set_command(tail, false),
+            "setlocal" | "setl" => self.set_command(tail, true),
+            _ if map_keys::map_command(cmd).is_some() => {
+                let (modes, kind) = map_keys::map_command(cmd)?;
+                self.map_command(modes, kind, tail)
+            }
             _ => Some(
                 UserMessageBuilder::default()
                     .message(command)
@@ -212,6 +1106,303 @@ impl State {
         }
     }
 
+    /// `:42`, jumps to the first non blank of `row`.
+    pub fn goto_row(&mut self, row: usize) -> Option<Message> {
+        let size = self.text_area_size();
+        let buf = &mut self.buffers[self.focused];
+        let idx = buf.first_non_blank(buf.line_to_char(row));
+        buf.set_cursor_idx(idx, size);
+        None
+    }
+
+    fn rows_span(&self, rows: LineRange) -> Span {
+        let buf = &self.buffers[self.focused];
+        Span {
+            start: buf.line_to_char(rows.start),
+            end: buf.line_to_char(rows.end + 1),
+            linewise: true,
+        }
+    }
+
+    /// Reads the `x` of `:d x` and `:y x`.
+    fn register_arg(&mut self, arg: &str) -> Result<(), Message> {
+        let mut chars = arg.chars();
+        match (chars.next(), chars.next()) {
+            (None, _) => Ok(()),
+            (Some(name), None) if Registers::is_valid(name) && !name.is_ascii_digit() => {
+                self.register = Some(name);
+                Ok(())
+            }
+            _ => Err(command_error(format!("Trailing characters: {arg}"))),
+        }
+    }
+
+    /// `:[range]d [x]`
+    pub fn delete_rows(&mut self, rows: LineRange, arg: &str) -> Option<Message> {
+        if let Err(err) = self.register_arg(arg) {
+            return Some(err);
+        }
+        let span = self.rows_span(rows);
+        self.apply_operator(Operator::Delete, &[span], false)
+    }
+
+    /// `:[range]y [x]`
+    pub fn yank_rows(&mut self, rows: LineRange, arg: &str) -> Option<Message> {
+        if let Err(err) = self.register_arg(arg) {
+            return Some(err);
+        }
+        let span = self.rows_span(rows);
+        self.apply_operator(Operator::Yank, &[span], false)
+    }
+
+    /// `:[range]m {address}` and `:[range]t {address}`, the cursor ends on the last line moved to.
+    pub fn move_rows(&mut self, rows: LineRange, arg: &str, copy: bool) -> Option<Message> {
+        let lines = ExLines {
+            buf: &self.buffers[self.focused],
+            search: &self.search,
+        };
+        let after = match ex::parse_address(arg.trim(), &lines) {
+            Ok(Some((after, ""))) if after <= lines.last() + 1 => after,
+            Ok(_) => return Some(command_error(format!("Invalid address: {arg}"))),
+            Err(err) => return Some(command_error(err)),
+        };
+        let count = rows.end - rows.start + 1;
+        let buf = &mut self.buffers[self.focused];
+        let last = if copy {
+            buf.copy_lines(rows, after);
+            after + count - 1
+        } else if (rows.start + 1..=rows.end).contains(&after) {
+            return Some(command_error("Cannot move a range of lines into itself"));
+        } else {
+            buf.move_lines(rows, after);
+            if after > rows.end {
+                after - 1
+            } else {
+                after + count - 1
+            }
+        };
+        self.goto_row(last)
+    }
+
+    /// `:[range]normal {keys}`, runs `keys` in normal mode on each line as one undo step.
+    pub fn normal_command(&mut self, rows: LineRange, keys: &str) -> Option<Message> {
+        let keys = string_to_keys(keys);
+        self.buffers[self.focused].begin_undo_group();
+        for row in rows.start..=rows.end {
+            if row > self.buffers[self.focused].len_lines() {
+                break;
+            }
+            let size = self.text_area_size();
+            let buf = &mut self.buffers[self.focused];
+            let idx = buf.line_to_char(row);
+            buf.set_cursor_idx(idx, size);
+            for key in keys.iter() {
+                let mut next = Some(Message::KeyPress(*key));
+                while let Some(message) = next {
+                    next = self.handle(message);
+                }
+            }
+            self.key_parse.clear();
+            match self.mode {
+                Mode::Normal => {}
+                Mode::Search => _ = self.cancel_search(),
+                _ => _ = self.change_mode(Mode::Normal),
+            }
+        }
+        self.buffers[self.focused].end_undo_group();
+        None
+    }
+
+    /// `:[range]w {file}`, only a whole buffer can be written without a file name.
+    pub fn write_rows(&mut self, rows: LineRange, filename: &str) -> Option<Message> {
+        let buf = &self.buffers[self.focused];
+        let (force, filename) = match filename.strip_prefix('!') {
+            Some(filename) => (true, filename.trim()),
+            None => (false, filename),
+        };
+        if filename.is_empty() {
+            if rows.start == 0 && rows.end >= buf.len_lines() {
+                return self.save(None, force);
+            }
+            return Some(command_error(
+                "Give a file name to write part of the buffer",
+            ));
+        }
+        if let Err(err) = buf.save_rows(rows, filename, force) {
+            return Some(command_error(err.to_string()));
+        }
+        None
+    }
+
+    pub fn set_mark(&mut self, name: char) -> Option<Message> {
+        let buf = &mut self.buffers[self.focused];
+        let pos = buf.get_cursor().pos();
+        buf.set_mark(name, pos);
+        None
+    }
+
+    /// `'a` jumps to the first non blank of the mark's line, `` `a `` to the mark itself.
+    pub fn jump_to_mark(&mut self, name: char, linewise: bool) -> Option<Message> {
+        let size = self.text_area_size();
+        let buf = &mut self.buffers[self.focused];
+        let Some(pos) = buf.mark(name) else {
+            return Some(command_error(format!("Mark not set: {name}")));
+        };
+        let row = (pos.y as usize).min(buf.len_lines());
+        let start = buf.line_to_char(row);
+        let idx = if linewise {
+            buf.first_non_blank(start)
+        } else {
+            start + (pos.x as usize).min(buf.line_len(row))
+        };
+        buf.set_cursor_idx(idx, size);
+        None
+    }
+
+    /// `:[range]s/pattern/replacement/[gcin]`, an empty pattern reuses the last search.
+    pub fn substitute(&mut self, rows: LineRange, args: &str) -> Option<Message> {
+        let sub = match Substitute::parse(args) {
+            Ok(sub) => sub,
+            Err(err) => return Some(command_error(err)),
+        };
+        // Without a replacement the last substitute is done again, a bare `:s`
+        // takes its pattern too.
+        let replacement = match (sub.replacement, &self.last_substitute) {
+            (Some(replacement), _) => replacement,
+            (None, Some((pattern, replacement))) => {
+                if sub.pattern.is_empty() {
+                    self.search.pattern = pattern.clone();
+                }
+                replacement.clone()
+            }
+            (None, None) => return Some(command_error("No previous substitute")),
+        };
+        if !sub.pattern.is_empty() {
+            self.search.pattern = sub.pattern;
+        }
+        if self.search.pattern.is_empty() {
+            return Some(command_error("No previous regular expression"));
+        }
+        self.last_substitute = Some((self.search.pattern.clone(), replacement.clone()));
+        let regex = self.search.compile(&self.search.pattern).and_then(|regex| {
+            if sub.flags.ignore_case {
+                regex::Regex::new(&format!("(?i){}", regex.as_str()))
+            } else {
+                Ok(regex)
+            }
+        });
+        let regex = match regex {
+            Ok(regex) => regex,
+            Err(err) => return Some(command_error(err.to_string())),
+        };
+        let buf = &self.buffers[self.focused];
+        let found = buf.substitutions(&regex, rows, &replacement, sub.flags.global);
+        if found.is_empty() {
+            return Some(command_error(format!(
+                "Pattern not found: {}",
+                self.search.pattern
+            )));
+        }
+        if sub.flags.count_only {
+            let mut lines = found
+                .iter()
+                .map(|r| buf.char_to_line(r.range.start))
+                .collect::<Vec<_>>();
+            lines.dedup();
+            return Some(
+                UserMessageBuilder::default()
+                    .message(format!("{} matches on {} lines", found.len(), lines.len()))
+                    .footer("substitute")
+                    .build(),
+            );
+        }
+        if sub.flags.confirm {
+            self.confirm = Some(Confirm {
+                pending: found,
+                ..Confirm::default()
+            });
+            return self.confirm_next();
+        }
+        self.replace(&found)
+    }
+
+    /// `:retab[!] [tabstop]` redoes whitespace holding tabs in `rows` for `tabstop`,
+    /// `!` takes in runs of spaces too, then sets `tabstop` when one is given.
+    pub fn retab(&mut self, rows: LineRange, args: &str) -> Option<Message> {
+        let (spaces, args) = match args.strip_prefix('!') {
+            Some(args) => (true, args.trim()),
+            None => (false, args.trim()),
+        };
+        let tabstop = if args.is_empty() {
+            self.buffers[self.focused].tabstop()
+        } else {
+            match args.parse::<usize>() {
+                Ok(tabstop) if tabstop > 0 => tabstop,
+                _ => return Some(command_error(format!("Invalid argument: {args}"))),
+            }
+        };
+        let expand = self.option("expandtab").as_bool();
+        let buf = &mut self.buffers[self.focused];
+        let found = buf.retabs(rows, tabstop, expand, spaces && !expand);
+        let Pos { x, y } = buf.get_cursor().pos();
+        buf.replace_ranges(&found);
+        self.move_cursor_to(y as usize, x as usize);
+        if args.is_empty() {
+            return None;
+        }
+        self.set_command(&format!("tabstop={tabstop}"), false)
+    }
+
+    /// Makes `replacements` as one undo step and leaves the cursor on the last line changed.
+    fn replace(&mut self, replacements: &[Replace]) -> Option<Message> {
+        let size = self.text_area_size();
+        let last = replacements.last()?;
+        let buf = &mut self.buffers[self.focused];
+        let added_lines = replacements
+            .iter()
+            .take(replacements.len() - 1)
+            .map(|r| r.text.matches('\n').count())
+            .sum::<usize>();
+        let row = buf.char_to_line(last.range.start) + added_lines;
+        buf.replace_ranges(replacements);
+        let idx = buf.first_non_blank(buf.line_to_char(row));
+        buf.set_cursor_idx(idx, size);
+        None
+    }
+
+    /// Shows the match waiting for an answer, or makes the accepted replacements once
+    /// every match has one.
+    fn confirm_next(&mut self) -> Option<Message> {
+        let size = self.text_area_size();
+        let confirm = self.confirm.as_ref()?;
+        if let Some(current) = confirm.current() {
+            let start = current.range.start;
+            self.buffers[self.focused].set_cursor_idx(start, size);
+            return None;
+        }
+        let confirm = self.confirm.take()?;
+        self.replace(&confirm.accepted)
+    }
+
+    /// `y` replaces, `n` skips, `a` replaces the rest, `l` replaces and stops, `q` stops.
+    fn confirm_key(&mut self, keys: Keys) -> Option<Message> {
+        let confirm = self.confirm.as_mut()?;
+        match keys.as_char() {
+            Some('y') => confirm.accept(),
+            Some('n') => confirm.skip(),
+            Some('a') => confirm.accept_all(),
+            Some('l') => {
+                confirm.accept();
+                confirm.stop();
+            }
+            _ if keys == Keys::Key(Key::Esc) || keys.as_char() == Some('q') => {
+                confirm.stop();
+            }
+            _ => {}
+        }
+        self.confirm_next()
+    }
+
     pub fn buffer_list_command(&mut self) -> Option<Message> {
         let paths = self
             .buffers
@@ -229,9 +1420,45 @@ impl State {
         )
     }
 
-    pub fn edit_file_command(&mut self, filename: &str) -> Option<Message> {
-        let buf = Buffer::from_path(filename);
-        self.set_new_buffer_as_focused(buf);
+    /// `:e[!] [++enc={encoding}] [file]`, without a file or with the focused
+    /// buffer's own the file is read again into it. Changes made since it was
+    /// written are only thrown away with `!`.
+    pub fn edit_file_command(&mut self, args: &str) -> Option<Message> {
+        let (force, args) = match args.strip_prefix('!') {
+            Some(args) => (true, args),
+            None => (false, args),
+        };
+        let mut encoding = None;
+        let mut filename = args.trim();
+        if let Some(rest) = filename.strip_prefix("++") {
+            let (arg, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+            let name = match arg.split_once('=') {
+                Some(("enc" | "encoding", name)) => name,
+                _ => return Some(command_error(format!("Invalid argument: ++{arg}"))),
+            };
+            encoding = match Encoding::from_name(name) {
+                Some(encoding) => Some(encoding),
+                None => return Some(command_error(format!("Invalid encoding: {name}"))),
+            };
+            filename = rest.trim();
+        }
+        let buf = &mut self.buffers[self.focused];
+        if !filename.is_empty() && filename != buf.name {
+            let buf = Buffer::from_path(filename, encoding);
+            self.set_new_buffer_as_focused(buf);
+            return None;
+        }
+        if buf.name.is_empty() {
+            return Some(command_error("No file name"));
+        }
+        if buf.is_modified() && !force {
+            return Some(command_error(
+                "No write since last change (add ! to override)",
+            ));
+        }
+        buf.reload(encoding);
+        self.fit_cursor();
+        self.buffer_loaded(self.focused);
         None
     }
 
@@ -239,34 +1466,160 @@ impl State {
         if arg.is_empty() {
             unimplemented!("Message to user");
         }
-        if let Ok(idx) = arg.parse::<usize>() {
-            if self.buffers.get(idx).is_none() {
-                unimplemented!("Message to user");
-                // Message
-                //     "buffer id does not exsist".into(),
-                //     command.into())
-                //     .call(ctx.clone());
-                // return;
-            };
-            self.focused = idx;
+        if let Ok(idx) = arg.parse::<usize>() {
+            if self.buffers.get(idx).is_none() {
+                unimplemented!("Message to user");
+                // Message
+                //     "buffer id does not exsist".into(),
+                //     command.into())
+                //     .call(ctx.clone());
+                // return;
+            };
+            self.show_buffer(idx);
+            return None;
+        }
+        let Some(idx) = self
+            .buffers
+            .iter()
+            .enumerate()
+            .find(|(_, b)| b.name == arg)
+            .map(|(i, _)| i)
+        else {
+            unimplemented!("Message to user");
+            // Message(
+            //     "name buffer open with that name or path".into(),
+            //     command.into())
+            //     .call(ctx.clone());
+            // return;
+        };
+        self.show_buffer(idx);
+        None
+    }
+
+    /// Copies the cursor of the window losing focus out of its buffer and puts the
+    /// one of `window` in.
+    fn focus_window(&mut self, window: usize) {
+        self.windows[self.window].cursor = *self.buffers[self.focused].get_cursor();
+        self.load_window(window);
+    }
+
+    fn load_window(&mut self, window: usize) {
+        self.window = window;
+        let Window { buffer, cursor, .. } = self.windows[window];
+        self.focused = buffer;
+        self.buffers[buffer].set_cursor(cursor);
+        self.fit_cursor();
+    }
+
+    /// Keeps the cursor inside the focused window after it changed size.
+    fn fit_cursor(&mut self) {
+        let size = self.text_area_size();
+        self.buffers[self.focused].clamp_cursor(size);
+    }
+
+    /// `:split` and `:vsplit`, the new window shows `filename` or the same buffer and
+    /// takes the focus.
+    pub fn split_window(&mut self, stack: Stack, filename: &str) -> Option<Message> {
+        let rect = self.window_rect(self.window);
+        let room = match stack {
+            Stack::Vertically => rect.height >= 4,
+            Stack::Horizontally => rect.width >= 2,
+        };
+        if !room {
+            return Some(command_error("Not enough room"));
+        }
+        let new = self.windows.len();
+        let cursor = *self.buffers[self.focused].get_cursor();
+        let options = self.windows[self.window].options.clone();
+        self.windows
+            .push(Window::new(self.focused, cursor, options));
+        self.layout.split(self.window, new, stack);
+        self.focus_window(new);
+        if !filename.is_empty() {
+            return self.edit_file_command(filename);
+        }
+        None
+    }
+
+    /// `<C-w>q` and `:q`, the window after it gets the focus. Closing the last window quits.
+    pub fn close_window(&mut self) -> Option<Message> {
+        if self.windows.len() == 1 {
+            return Some(Message::Quit);
+        }
+        let order = self.layout.windows();
+        let at = order.iter().position(|id| *id == self.window)?;
+        let next = if at + 1 < order.len() {
+            order[at + 1]
+        } else {
+            order[at - 1]
+        };
+        let closed = self.window;
+        self.layout.remove(closed);
+        self.windows.remove(closed);
+        self.load_window(next - (next > closed) as usize);
+        None
+    }
+
+    /// `<C-w>h`, `<C-w>j`, `<C-w>k` and `<C-w>l`.
+    pub fn focus_direction(&mut self, direction: Direction) -> Option<Message> {
+        let cursor = self.cursor_pos()?;
+        let rects = self.window_rects();
+        if let Some(window) = window::neighbour(&rects, self.window, cursor, direction) {
+            self.focus_window(window);
+        }
+        None
+    }
+
+    /// `<C-w>w`, goes through the windows left to right and top to bottom.
+    pub fn next_window(&mut self) -> Option<Message> {
+        let order = self.layout.windows();
+        let at = order.iter().position(|id| *id == self.window)?;
+        self.focus_window(order[(at + 1) % order.len()]);
+        None
+    }
+
+    pub fn equalize_windows(&mut self) -> Option<Message> {
+        self.layout.equalize();
+        self.fit_cursor();
+        None
+    }
+
+    pub fn resize_window(&mut self, stack: Stack, delta: i32) -> Option<Message> {
+        let area = Size::new(self.size.width, self.size.height.saturating_sub(1));
+        self.layout
+            .resize(self.window, stack, delta, Rect::new(area));
+        self.fit_cursor();
+        None
+    }
+
+    /// `:set` and `:setlocal`, without arguments every option is listed.
+    pub fn set_command(&mut self, args: &str, local: bool) -> Option<Message> {
+        let window = &mut self.windows[self.window];
+        let buffer = &mut self.buffers[window.buffer].options;
+        let mut shown = Vec::new();
+        for arg in args.split_whitespace() {
+            match self.options.set(arg, local, buffer, &mut window.options) {
+                Ok(Some(text)) => shown.push(text),
+                Ok(None) => {}
+                Err(err) => return Some(command_error(err)),
+            }
+        }
+        if args.trim().is_empty() {
+            shown = options::OPTIONS
+                .iter()
+                .map(|def| options::display(def.name, self.option(def.name)))
+                .collect();
+        }
+        self.fit_cursor();
+        if shown.is_empty() {
             return None;
         }
-        let Some(idx) = self
-            .buffers
-            .iter()
-            .enumerate()
-            .find(|(_, b)| b.name == arg)
-            .map(|(i, _)| i)
-        else {
-            unimplemented!("Message to user");
-            // Message(
-            //     "name buffer open with that name or path".into(),
-            //     command.into())
-            //     .call(ctx.clone());
-            // return;
-        };
-        self.focused = idx;
-        None
+        Some(
+            UserMessageBuilder::default()
+                .message(shown.join("\n"))
+                .footer("set")
+                .build(),
+        )
     }
 
     pub fn close_message(&mut self) -> Option<Message> {
@@ -276,6 +1629,9 @@ impl State {
 
     pub fn next_available_command(&mut self) -> Option<Message> {
         let current_cmd = self.get_focused_buffer().get_all_text();
+        if let Some((head, args)) = current_cmd.split_once(' ') {
+            return self.next_available_argument(head, args);
+        }
         let list = if current_cmd.is_empty() {
             self.command_list.get_all_words()
         } else {
@@ -295,8 +1651,70 @@ impl State {
         None
     }
 
-    pub fn save(&mut self, filename: Option<String>) -> Option<Message> {
-        if let Err(err) = self.get_focused_buffer().save(filename) {
+    /// `<tab>` after a user command's name, completes its last argument the way
+    /// its `complete` option says.
+    fn next_available_argument(&mut self, head: &str, args: &str) -> Option<Message> {
+        let name = head.trim_start_matches(|c: char| !c.is_ascii_alphabetic());
+        let (name, _) = ex::split_command(name);
+        let complete = self.callbacks.commands.get(name)?.complete.clone();
+        let last = args.rsplit(' ').next().unwrap_or_default();
+        // Another `<tab>` on the line the last one made goes on to the next match.
+        let lead = match self.completion.take() {
+            Some((line, lead)) if line == format!("{head} {args}") => lead,
+            _ => {
+                self.tab_index = 0;
+                last.to_string()
+            }
+        };
+        let list = self
+            .completions(&complete, &lead)
+            .into_iter()
+            .filter(|word| word.starts_with(&lead) && *word != lead)
+            .collect::<Vec<_>>();
+        let word = list.get(self.tab_index % list.len().max(1))?;
+        self.tab_index = (self.tab_index + 1) % list.len();
+        let line = format!("{head} {}{word}", &args[..args.len() - last.len()]);
+        self.completion = Some((line.clone(), lead));
+        let buf = self.get_focused_buffer_mut();
+        buf.clear();
+        buf.insert(line);
+        None
+    }
+
+    fn completions(&self, complete: &Complete, lead: &str) -> Vec<String> {
+        match complete {
+            Complete::Nothing => Vec::new(),
+            Complete::File => file_completions(lead),
+            Complete::Buffer => self.buffers.iter().map(|buf| buf.name.clone()).collect(),
+            Complete::Option => options::OPTIONS
+                .iter()
+                .map(|def| def.name.to_string())
+                .collect(),
+            Complete::Command => self.command_list.get_all_words(),
+            Complete::List(words) => words.clone(),
+        }
+    }
+
+    /// Runs `BufWritePre` before the buffer is written by [`Message::WriteBuffer`].
+    /// With script calls queued the write goes through the runtime, which makes
+    /// them first, even when a mapping typed the `:w`.
+    pub fn save(&mut self, filename: Option<String>, force: bool) -> Option<Message> {
+        let file = filename
+            .clone()
+            .unwrap_or_else(|| self.buffers[self.focused].name.clone());
+        self.fire(autocmd::Event::BufWritePre, &file);
+        let write = Message::WriteBuffer(filename, force);
+        if self.callbacks.pending.is_empty() {
+            return Some(write);
+        }
+        self.tasks.push(Task::from(Some(write)));
+        None
+    }
+
+    pub fn write_buffer(&mut self, filename: Option<String>, force: bool) -> Option<Message> {
+        let buf = &mut self.buffers[self.focused];
+        let file = filename.clone().unwrap_or_else(|| buf.name.clone());
+        if let Err(err) = buf.save(filename, force) {
             return Some(
                 UserMessageBuilder::default()
                     .message(err.to_string())
@@ -305,18 +1723,164 @@ impl State {
                     .build(),
             );
         }
+        self.fire(autocmd::Event::BufWritePost, &file);
+        None
+    }
+
+    /// `:!cmd` runs in the background and shows what it printed once it's done.
+    fn shell_command(&mut self, command: &str) -> Option<Message> {
+        let command = command.trim().to_string();
+        if command.is_empty() {
+            return Some(command_error("Argument required"));
+        }
+        self.spawn(move || {
+            let (shell, flag) = if cfg!(windows) {
+                ("cmd", "/C")
+            } else {
+                ("sh", "-c")
+            };
+            let output = std::process::Command::new(shell)
+                .args([flag, &command])
+                .output();
+            let builder = UserMessageBuilder::default().footer(format!("!{command}"));
+            match output {
+                Ok(output) => {
+                    let text = format!(
+                        "{}{}",
+                        String::from_utf8_lossy(&output.stdout),
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                    let builder = builder.message(text.trim_end());
+                    if output.status.success() {
+                        builder.build()
+                    } else {
+                        builder.fg(Color::Red).build()
+                    }
+                }
+                Err(err) => builder.message(err.to_string()).fg(Color::Red).build(),
+            }
+        });
         None
     }
 
+    /// Queues the autocommands for `event` on `file`.
+    fn fire(&mut self, event: autocmd::Event, file: &str) {
+        let mut data = rhai::Map::new();
+        data.insert("file".into(), file.into());
+        self.callbacks.fire(event, file, data);
+    }
+
+    /// `BufReadPost` for a buffer read from a file, `BufNewFile` when there was none.
+    pub fn buffer_loaded(&mut self, id: usize) {
+        let name = self.buffers[id].name.clone();
+        let event = if std::path::Path::new(&name).exists() {
+            autocmd::Event::BufReadPost
+        } else {
+            autocmd::Event::BufNewFile
+        };
+        self.fire(event, &name);
+        if let Some(err) = self.buffers[id].read_error.take() {
+            self.messages.push(
+                UserMessageBuilder::default()
+                    .message(err)
+                    .footer("error")
+                    .fg(Color::Red),
+            );
+        }
+    }
+
+    /// `InsertEnter`, `InsertLeave` and `ModeChanged`, whose pattern matches `old:new`.
+    fn mode_changed(&mut self, old: Mode) {
+        let new = self.mode;
+        if old == new {
+            return;
+        }
+        let file = self.buffers[self.focused].name.clone();
+        match (old, new) {
+            (_, Mode::Insert) => self.fire(autocmd::Event::InsertEnter, &file),
+            (Mode::Insert, _) => self.fire(autocmd::Event::InsertLeave, &file),
+            _ => {}
+        }
+        let (old, new) = (
+            old.to_string().to_lowercase(),
+            new.to_string().to_lowercase(),
+        );
+        let mut data = rhai::Map::new();
+        data.insert("file".into(), file.into());
+        data.insert("old_mode".into(), old.clone().into());
+        data.insert("new_mode".into(), new.clone().into());
+        self.callbacks
+            .fire(autocmd::Event::ModeChanged, &format!("{old}:{new}"), data);
+    }
+
+    /// Where the cursor is, `CursorMoved` fires when an update changes it.
+    fn cursor_place(&self) -> (usize, usize, Pos) {
+        let pos = self.buffers[self.focused].get_cursor().pos();
+        (self.window, self.focused, pos)
+    }
+
     pub fn command_mode_insert(&mut self, c: impl Into<String>) -> Option<Message> {
         self.command.insert(c);
+        if self.mode == Mode::Search {
+            return self.incremental_search();
+        }
         None
     }
 
     pub fn check_for_mapping(&mut self) -> Option<Message> {
+        if !self.noremap {
+            match self
+                .map_keys
+                .user_lookup(&self.mode, self.key_parse.get_keys())
+            {
+                UserLookup::Pending => return None,
+                UserLookup::Found(message, len) => return self.user_mapping(message, len),
+                UserLookup::NoMatch => {}
+            }
+        }
+        self.builtin_mapping(true)
+    }
+
+    /// `timeoutlen` went by with keys pending, the shorter of the mappings they
+    /// could start runs and keys no mapping takes are typed in insert mode.
+    fn mapping_timeout(&mut self) -> Option<Message> {
+        let keys = self.key_parse.get_keys();
+        if keys.is_empty() {
+            return None;
+        }
+        if !self.noremap {
+            if let Some((message, len)) = self.map_keys.user_prefix(&self.mode, keys) {
+                return self.user_mapping(message, len);
+            }
+        }
+        self.builtin_mapping(false)
+    }
+
+    /// Looks the pending keys up in the built in mappings, with `wait` keys that
+    /// are a mapping and the start of a longer one wait for `MappingTimeout`.
+    fn builtin_mapping(&mut self, wait: bool) -> Option<Message> {
+        if let Mode::Normal = self.mode {
+            match self.key_parse.parse_operator() {
+                OperatorParse::Pending => return None,
+                OperatorParse::Done(msg) => {
+                    self.register = self.key_parse.register;
+                    self.key_parse.clear();
+                    return Some(msg);
+                }
+                OperatorParse::NoMatch => {}
+            }
+        }
         let msg = self
             .map_keys
             .get_mapping(&self.mode, self.key_parse.get_keys());
+        if wait
+            && msg.is_some()
+            && self
+                .map_keys
+                .is_ambiguous(&self.mode, self.key_parse.get_keys())
+        {
+            return None;
+        }
         let is_possible_mapping = self
             .map_keys
             .is_possible_mapping(&self.mode, self.key_parse.get_keys());
@@ -337,33 +1901,99 @@ impl State {
                 .join("");
             self.key_parse.clear();
             let message = match self.mode {
-                Mode::Command => Message::ModeCommandInsertStr(input),
+                Mode::Command | Mode::Search => Message::ModeCommandInsertStr(input),
                 Mode::Insert => Message::ModeInsertInsertStr(input),
                 _ => return None,
             };
             return Some(message);
         }
-        if msg.is_some() {
-            self.key_parse.clear();
+        let msg = msg?;
+        let count = self.key_parse.count;
+        self.register = self.key_parse.register;
+        self.key_parse.clear();
+        match count {
+            Some(count) => Some(msg.with_count(count)),
+            None => Some(msg),
         }
-        msg
     }
 
-    fn text_area_view(&self, buf: &Buffer, width: u16, height: u16) -> Container {
-        let text_size = Size {
-            width,
-            height: height - 2,
-        };
-        let rect_text = Rect::new(text_size);
+    /// A window's text, only the focused one shows the selection and `:s///c` match.
+    fn text_area_view(
+        &self,
+        buf: &Buffer,
+        focused: bool,
+        lines: &[ScreenLine],
+        wrap: Option<&Wrap>,
+        size: Size,
+    ) -> Container {
+        let Size { width, height } = size;
+        let rect_text = Rect::new(size);
         // let mut p = tree_sitter::Parser::new();
         // p.set_language(tree_sitter_md::language())
         //     .expect("Error loading Rust grammar");
 
         // let mut last_context_parser: Option<tree_sitter::Tree> = None;
-        buf.on_screen(&text_size)
+        let showbreak = wrap.map_or("", |wrap| wrap.showbreak.as_str());
+        let pattern = match self.mode {
+            Mode::Search => self.command.get_all_text(),
+            _ if self.search.highlight => self.search.pattern.clone(),
+            _ => String::new(),
+        };
+        let regex = Some(pattern)
+            .filter(|p| !p.is_empty())
+            .and_then(|p| self.search.compile(&p).ok());
+        lines
             .iter()
-            .map(|line| {
-                Text::new(line.as_str()).max_width(width)
+            .map(|screen| {
+                let row = screen.row;
+                let line = buf.line(row);
+                let prefix = if screen.first { "" } else { showbreak };
+                let offset = prefix.chars().count();
+                let (shown, mut starts) =
+                    grapheme::expand(&line, screen.cols.clone(), buf.tabstop());
+                let mut text = format!("{prefix}{shown}");
+                // The line break takes a cell after the end of the line, a selected
+                // empty line still shows.
+                let mut cols = screen.cols.clone();
+                if cols.end == line.chars().count() {
+                    text.push(' ');
+                    starts.push(starts[starts.len() - 1] + 1);
+                    cols.end += 1;
+                }
+                let shift = |range: std::ops::Range<usize>| {
+                    let start = range.start.clamp(cols.start, cols.end);
+                    let end = range.end.clamp(cols.start, cols.end);
+                    starts[start - cols.start] + offset..starts[end - cols.start] + offset
+                };
+                let confirm = self
+                    .confirm
+                    .as_ref()
+                    .filter(|_| focused)
+                    .and_then(Confirm::current)
+                    .filter(|replace| buf.char_to_line(replace.range.start) == row)
+                    .map(|replace| {
+                        let start = buf.line_to_char(row);
+                        let range = replace.range.start - start..replace.range.end - start;
+                        (shift(range), Color::DarkRed)
+                    });
+                let mut highlights = confirm
+                    .into_iter()
+                    .chain(
+                        regex
+                            .as_ref()
+                            .map(|regex| search::matches_in_line(regex, &buf.line(row)))
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|range| (shift(range), Color::DarkYellow)),
+                    )
+                    .chain(
+                        buf.selected_cols(row, self.mode)
+                            .filter(|_| focused)
+                            .map(|(start, end)| (shift(start..end), Color::DarkGrey)),
+                    )
+                    .collect::<Vec<_>>();
+                highlights.sort_by_key(|(range, _)| range.start);
+                highlighted_line(&text, width, &highlights)
                 // last_context_parser = p.parse(line, last_context_parser.as_ref());
                 // let mut text_line =
                 //     Container::new(Rect::new(Size::new(width, 1)), Stack::Horizontally);
@@ -380,7 +2010,7 @@ impl State {
                 // }
                 // text_line
             })
-            .chain(std::iter::repeat(Text::new(" ").max_width(width)))
+            .chain(std::iter::repeat(Text::new(" ").max_width(width).into()))
             // .chain(std::iter::repeat(
             //     Container::new(Rect::new(Size::new(width, 1)), Stack::Horizontally)
             //         .push(Text::new(" ").max_width(width)),
@@ -400,8 +2030,15 @@ impl State {
             .iter()
             .map(ToString::to_string)
             .collect::<String>();
+        if let Some(replace) = self.confirm.as_ref().and_then(Confirm::current) {
+            let prompt = format!("replace with {} (y/n/a/q/l)?", replace.text);
+            return Container::new(rect_cmd, Stack::Horizontally)
+                .push(Text::new(&prompt).max_width(width));
+        }
         let visable_colon = match self.mode {
             Mode::Command => ":",
+            Mode::Search if self.search.forward => "/",
+            Mode::Search => "?",
             _ => " ",
         };
         Container::new(rect_cmd, Stack::Horizontally)
@@ -409,33 +2046,51 @@ impl State {
             .push(Text::new(&src_cmd).max_width(width.saturating_sub(1)))
     }
 
-    fn status_bar_view(&self, buf: &Buffer, width: u16) -> Container {
-        let mode_status = Text::new(&format!("{:?}", self.mode))
+    /// A window's status bar, windows without the focus are greyed out and show no mode.
+    fn status_bar_view(
+        &self,
+        buf: &Buffer,
+        cursor: &Cursor,
+        focused: bool,
+        width: u16,
+    ) -> Container {
+        let (mode, bg) = if focused {
+            (self.mode.to_string(), Color::White)
+        } else {
+            (String::new(), Color::DarkGrey)
+        };
+        let mode_status = Text::new(&mode)
             .max_width(8)
             .with_fg(Color::Black)
-            .with_bg(Color::White)
+            .with_bg(bg)
             .with_atter(vec![Attribute::Bold, Attribute::Italic].as_slice());
 
         let filename_status = Text::new(&buf.name)
-            .max_width(buf.name.len() as u16)
+            .max_width(grid::width(&buf.name) as u16)
             .with_fg(Color::Black)
-            .with_bg(Color::White)
+            .with_bg(bg)
             .with_atter(vec![Attribute::Bold, Attribute::Italic].as_slice());
 
-        let cursor = buf.get_cursor();
         let cursor_pos_status_width =
-            width - (mode_status.char_len() + filename_status.char_len()) as u16;
+            width.saturating_sub((mode_status.char_len() + filename_status.char_len()) as u16);
         let pos = cursor.pos();
         let col = pos.x;
         let row = pos.y;
         let scroll = cursor.scroll;
         let scol = scroll.x;
         let srow = scroll.y;
-        let cursor_pos_status = Text::new(&format!("{scol}/{srow} {col}/{row}"))
+        let mut ruler = format!("{scol}/{srow} {col}/{row}");
+        if focused && self.option("showcmd").as_bool() {
+            let showcmd = self.key_parse.showcmd();
+            if !showcmd.is_empty() {
+                ruler = format!("{showcmd:<11}{ruler}");
+            }
+        }
+        let cursor_pos_status = Text::new(&ruler)
             .max_width(cursor_pos_status_width)
             .with_alignment(Alignment::Right)
             .with_fg(Color::Black)
-            .with_bg(Color::White)
+            .with_bg(bg)
             .with_atter(vec![Attribute::Bold, Attribute::Italic].as_slice());
 
         let rect_status = Rect::new(Size { width, height: 1 });
@@ -444,6 +2099,305 @@ impl State {
             .push(filename_status)
             .push(cursor_pos_status)
     }
+
+    /// A window's text with its status bar under it.
+    fn window_view(&self, id: usize, size: Size) -> Container {
+        let window = &self.windows[id];
+        let focused = id == self.window;
+        let buf = &self.buffers[window.buffer];
+        let cursor = if focused {
+            buf.get_cursor()
+        } else {
+            &window.cursor
+        };
+        let height = size.height.saturating_sub(1);
+        let gutter = self.gutter_width(id);
+        let text_size = Size::new(size.width.saturating_sub(gutter), height);
+        let wrap = self.wrap(id);
+        let lines = buf.screen_lines(cursor, text_size, wrap.as_ref());
+        let text = Container::new(
+            Rect::new(Size::new(size.width, height)),
+            Stack::Horizontally,
+        )
+        .push(gutter_view(
+            cursor,
+            self.line_numbers(id),
+            &lines,
+            Size::new(gutter, height),
+        ))
+        .push(self.text_area_view(buf, focused, &lines, wrap.as_ref(), text_size));
+        Container::new(Rect::new(size), Stack::Vertically)
+            .push(text)
+            .push(self.status_bar_view(buf, cursor, focused, size.width))
+    }
+
+    fn layout_view(&self, layout: &Layout, size: Size) -> BoxWidget {
+        match layout {
+            Layout::Window(id) => self.window_view(*id, size).into(),
+            Layout::Split {
+                stack, children, ..
+            } => children
+                .iter()
+                .zip(layout.child_sizes(size))
+                .fold(
+                    Container::new(Rect::new(size), *stack),
+                    |acc, (child, size)| acc.push(self.layout_view(child, size)),
+                )
+                .into(),
+        }
+    }
+
+    /// Runs `work` on a worker thread, the message it makes comes back through `update`.
+    pub fn spawn(&mut self, work: impl FnOnce() -> Message + Send + 'static) {
+        self.tasks.push(Task::perform(work));
+    }
+
+    /// Everything for the runtime to do after a message, `next` and the spawned work.
+    pub fn task(&mut self, next: Option<Message>) -> Task<Message> {
+        if self.tasks.is_empty() {
+            return Task::from(next);
+        }
+        let mut tasks = std::mem::take(&mut self.tasks);
+        tasks.push(Task::from(next));
+        Task::batch(tasks)
+    }
+
+    /// Runs one message, the one it leads to is returned to run next.
+    pub fn handle(&mut self, message: Message) -> Option<Message> {
+        let before = self.cursor_place();
+        self.updating += 1;
+        let next = match message {
+            Message::CursorUp => self.cursor_up(),
+            Message::CursorDown => self.cursor_down(),
+            Message::CursorScreenDown => self.cursor_screen_row(true),
+            Message::CursorScreenUp => self.cursor_screen_row(false),
+            Message::CursorLeft => self.cursor_left(),
+            Message::CursorRight => self.cursor_right(),
+            Message::CursorHome => self.cursor_home(),
+            Message::CursorEnd => self.cursor_end(),
+            Message::ScrollUp => self.scroll_up(),
+            Message::ScrollDown => self.scroll_down(),
+            Message::ScrollLeft => self.scroll_left(),
+            Message::ScrollRight => self.scroll_right(),
+            Message::InsertAtEnd => self.insert_at_end(),
+            Message::InsertTab => self.insert_tab(),
+            Message::BackSpace => self.backspace(),
+            Message::Delete => self.delete_char(),
+            Message::Motion(motion, count) => self.motion(motion, count),
+            Message::Repeat(count, message) => self.repeat(count, *message),
+            Message::DeleteRange(motion, count) => self.operator(Operator::Delete, motion, count),
+            Message::ChangeRange(motion, count) => self.operator(Operator::Change, motion, count),
+            Message::YankRange(motion, count) => self.operator(Operator::Yank, motion, count),
+            Message::ShiftRightRange(motion, count) => {
+                self.operator(Operator::ShiftRight, motion, count)
+            }
+            Message::ShiftLeftRange(motion, count) => {
+                self.operator(Operator::ShiftLeft, motion, count)
+            }
+            Message::PutAfter(count) => self.put(true, count),
+            Message::PutBefore(count) => self.put(false, count),
+            Message::VisualOperator(op) => self.visual_operator(op),
+            Message::SwapAnchor => self.swap_anchor(),
+            Message::RegisterList => self.register_list_command(),
+            Message::SetMark(name) => self.set_mark(name),
+            Message::JumpToMark(name, linewise) => self.jump_to_mark(name, linewise),
+            Message::SearchPrompt(forward) => self.search_prompt(forward),
+            Message::ExecuteSearch => self.execute_search(),
+            Message::CancelSearch => self.cancel_search(),
+            Message::SearchNext(reverse) => self.search_next(reverse),
+            Message::NoHighlight => self.no_highlight(),
+            Message::SplitWindow(stack) => self.split_window(stack, ""),
+            Message::FocusWindow(direction) => self.focus_direction(direction),
+            Message::NextWindow => self.next_window(),
+            Message::CloseWindow => self.close_window(),
+            Message::EqualizeWindows => self.equalize_windows(),
+            Message::ResizeWindow(stack, delta) => self.resize_window(stack, delta),
+            Message::Undo => self.undo(),
+            Message::Redo => self.redo(),
+            Message::UserMessage(builder) => self.user_message(builder),
+            Message::KeyPress(keys) => self.key_press(keys),
+            Message::FeedKeys(ref keys, remap) => self.feed_keys(keys, remap),
+            Message::ScriptCall(id) => self.script_call(id),
+            Message::CheckForMapping => self.check_for_mapping(),
+            Message::MappingTimeout => self.mapping_timeout(),
+            Message::ModeCommandInsertStr(s) => self.command_mode_insert(s),
+            Message::ModeInsertInsertStr(s) => self.insert_mode_insert(s),
+            Message::ChangeMode(mode) => self.change_mode(mode),
+            Message::ExecuteCommand => self.execute_command(),
+            Message::BufferList => self.buffer_list_command(),
+            Message::EditFile(ref filename) => self.edit_file_command(filename),
+            Message::SwapBuffer(ref arg) => self.swap_buffer_command(arg),
+            Message::CloseCurrentPaneOnKeyPress => self.close_message(),
+            Message::NextAvailableCommand => self.next_available_command(),
+            Message::Resize(size) => {
+                self.size = size;
+                self.fit_cursor();
+                self.fire(autocmd::Event::VimResized, "");
+                None
+            }
+            Message::Save(filename, force) => self.save(filename, force),
+            Message::WriteBuffer(filename, force) => self.write_buffer(filename, force),
+            Message::Quit => {
+                self.fire(autocmd::Event::VimLeavePre, "");
+                self.is_running = false;
+                None
+            }
+        };
+        self.updating -= 1;
+        self.keep_scrolloff();
+        self.keep_wrapped();
+        let moved = self.cursor_place() != before;
+        if moved && self.updating == 0 && !self.mode.is_command_line() && self.mode != Mode::Insert
+        {
+            let file = self.buffers[self.focused].name.clone();
+            self.fire(autocmd::Event::CursorMoved, &file);
+        }
+        next
+    }
+}
+
+/// Line numbers down the left of a window, nothing past the end of the buffer
+/// or next to the rows a wrapped line goes on to.
+fn gutter_view(
+    cursor: &Cursor,
+    numbers: LineNumbers,
+    lines: &[ScreenLine],
+    size: Size,
+) -> Container {
+    let cursor_row = cursor.pos().y as usize;
+    lines
+        .iter()
+        .map(|screen| {
+            if screen.first {
+                numbers.label(screen.row, cursor_row, size.width)
+            } else {
+                String::new()
+            }
+        })
+        .chain(std::iter::repeat(String::new()))
+        .take(size.height as usize)
+        .fold(
+            Container::new(Rect::new(size), Stack::Vertically),
+            |acc, label| {
+                acc.push(
+                    Text::new(&label)
+                        .max_width(size.width)
+                        .with_fg(Color::DarkGrey),
+                )
+            },
+        )
+}
+
+/// One line of the text area with the char ranges in `highlights` drawn on a background color.
+fn highlighted_line(
+    line: &str,
+    width: u16,
+    highlights: &[(std::ops::Range<usize>, Color)],
+) -> BoxWidget {
+    if highlights.iter().all(|(range, _)| range.is_empty()) {
+        return Text::new(line).max_width(width).into();
+    }
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut container = Container::new(Rect::new(Size::new(width, 1)), Stack::Horizontally);
+    let mut col = 0;
+    let mut push = |container: Container, end: usize, bg: Option<Color>| {
+        let end = end.min(chars.len());
+        if end <= col {
+            return container;
+        }
+        let text = chars[col..end].iter().collect::<String>();
+        let len = grid::width(&text) as u16;
+        col = end;
+        let text = Text::new(&text).max_width(len).max_height(1);
+        match bg {
+            Some(bg) => container.push(text.with_bg(bg)),
+            None => container.push(text),
+        }
+    };
+    for (range, bg) in highlights {
+        container = push(container, range.start, None);
+        container = push(container, range.end, Some(*bg));
+    }
+    let container = push(container, chars.len(), None);
+    container.into()
+}
+
+/// The focused buffer as seen by ex addresses.
+struct ExLines<'a> {
+    buf: &'a Buffer,
+    search: &'a Search,
+}
+
+impl ex::Lines for ExLines<'_> {
+    fn current(&self) -> usize {
+        self.buf.get_cursor().row_scroll()
+    }
+
+    fn last(&self) -> usize {
+        self.buf.len_lines()
+    }
+
+    fn mark(&self, name: char) -> Option<usize> {
+        self.buf.mark(name).map(|pos| pos.y as usize)
+    }
+
+    fn find(&self, pattern: &str, from: usize, forward: bool) -> Result<Option<usize>, String> {
+        let pattern = if pattern.is_empty() {
+            &self.search.pattern
+        } else {
+            pattern
+        };
+        let regex = self
+            .search
+            .compile(pattern)
+            .map_err(|err| err.to_string())?;
+        let len = self.last() + 1;
+        let found = (1..=len)
+            .map(|i| {
+                if forward {
+                    (from + i) % len
+                } else {
+                    (from + len * 2 - i) % len
+                }
+            })
+            .find(|row| regex.is_match(&self.buf.line(*row)));
+        Ok(found)
+    }
+}
+
+/// Files and directories starting with `lead`, directories end in a `/`.
+fn file_completions(lead: &str) -> Vec<String> {
+    let (dir, _) = lead.rsplit_once('/').unwrap_or(("", lead));
+    let path = match dir {
+        "" if lead.starts_with('/') => "/",
+        "" => ".",
+        dir => dir,
+    };
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return Vec::new();
+    };
+    let prefix = match lead.rfind('/') {
+        Some(end) => &lead[..=end],
+        None => "",
+    };
+    let mut names = entries
+        .filter_map(Result::ok)
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            format!("{prefix}{name}{slash}")
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn command_error(msg: impl Into<String>) -> Message {
+    UserMessageBuilder::default()
+        .message(msg)
+        .footer("error")
+        .fg(Color::Red)
+        .build()
 }
 
 impl App for State {
@@ -451,25 +2405,105 @@ impl App for State {
     type Message = Message;
 
     fn new(settings: Self::Settings) -> Self {
-        let buffers = if settings.buffers.is_empty() {
+        let mut buffers = if settings.buffers.is_empty() {
             vec![Buffer::default()]
         } else {
             settings.buffers
         };
+        let options = Options::default();
+        for buf in buffers.iter_mut() {
+            // What reading the file found, like its `fileencoding`, is kept.
+            buf.set_option_defaults(options.locals(Scope::Buffer));
+        }
+        let window = Window::new(0, Cursor::default(), options.locals(Scope::Window));
         Self {
             focused: 0,
+            windows: vec![window],
+            window: 0,
+            layout: Layout::Window(0),
+            options,
             buffers,
             messages: Vec::new(),
             command: Buffer::default(),
             command_list: trie::Trie::from(&vec![
-                "b", "buffer", "ls", "e", "edit", "q", "quit", "w", "write",
+                "b",
+                "buffer",
+                "ls",
+                "e",
+                "edit",
+                "q",
+                "quit",
+                "w",
+                "write",
+                "u",
+                "undo",
+                "red",
+                "redo",
+                "reg",
+                "registers",
+                "di",
+                "display",
+                "noh",
+                "nohlsearch",
+                "s",
+                "substitute",
+                "d",
+                "delete",
+                "y",
+                "yank",
+                "m",
+                "move",
+                "t",
+                "co",
+                "copy",
+                "norm",
+                "normal",
+                "se",
+                "set",
+                "setl",
+                "setlocal",
+                "qa",
+                "qall",
+                "sp",
+                "split",
+                "vs",
+                "vsplit",
+                "clo",
+                "close",
+                "map",
+                "nmap",
+                "vmap",
+                "imap",
+                "cmap",
+                "noremap",
+                "nnoremap",
+                "vnoremap",
+                "inoremap",
+                "cnoremap",
+                "unmap",
+                "nunmap",
+                "vunmap",
+                "iunmap",
+                "cunmap",
             ]),
             tab_index: 0,
+            completion: None,
+            registers: Registers::default(),
+            register: None,
+            search: Search::default(),
+            confirm: None,
             map_keys: Mapper::default(),
             key_parse: KeyParser::default(),
             mode: Mode::Normal,
             size: size(),
             is_running: true,
+            callbacks: Callbacks::default(),
+            map_depth: 0,
+            noremap: false,
+            block_change: None,
+            last_substitute: None,
+            updating: 0,
+            tasks: Vec::new(),
         }
     }
 
@@ -482,9 +2516,10 @@ impl App for State {
             let stack = Stack::Vertically;
 
             let message = builder.build_container(width);
-            let status = self.status_bar_view(&self.buffers[self.focused], width);
+            let buf = &self.buffers[self.focused];
+            let status = self.status_bar_view(buf, buf.get_cursor(), true, width);
             let h = message.height() + status.height();
-            let y = height - h - 10;
+            let y = height.saturating_sub(h + 10);
             let rect = Rect::with_position(Pos::new(0, y), Size { width, height: h });
             return Container::new(rect, stack)
                 .push(status)
@@ -492,94 +2527,64 @@ impl App for State {
                 .into();
         }
 
-        let buf = &self.buffers[self.focused];
-        // ------ TEXT AREA --------
-        let text_area = self.text_area_view(buf, width, height);
+        // ------ WINDOWS AREA --------
+        let area = Size::new(width, height.saturating_sub(1));
+        let windows = self.layout_view(&self.layout, area);
 
         // ------ CMD AREA --------
         let cmd = self.command_bar_view(width);
 
-        // ------ Status Bar AREA --------
-        let status = self.status_bar_view(buf, width);
-
-        // ------ Status Bar && CMD combinding AREA --------
-        let rect_info = Rect::new(Size { width, height: 2 });
-        let info = Container::new(rect_info, Stack::Vertically)
-            .push(status)
-            .push(cmd);
-
         // ------ All widgets AREA --------
         Container::new(rect, Stack::Vertically)
-            .push(text_area)
-            .push(info)
+            .push(windows)
+            .push(cmd)
             .into()
     }
 
-    fn update(&mut self, message: Self::Message) -> Option<Self::Message> {
-        match message {
-            Message::CursorUp => self.cursor_up(),
-            Message::CursorDown => self.cursor_down(),
-            Message::CursorLeft => self.cursor_left(),
-            Message::CursorRight => self.cursor_right(),
-            Message::CursorHome => self.cursor_home(),
-            Message::CursorEnd => self.cursor_end(),
-            Message::ScrollUp => self.scroll_up(),
-            Message::ScrollDown => self.scroll_down(),
-            Message::ScrollLeft => self.scroll_left(),
-            Message::ScrollRight => self.scroll_right(),
-            Message::InsertAtEnd => self.insert_at_end(),
-            Message::BackSpace => self.backspace(),
-            Message::Delete => self.delete_char(),
-            Message::UserMessage(builder) => self.user_message(builder),
-            Message::KeyPress(keys) => self.key_press(keys),
-            Message::CheckForMapping => self.check_for_mapping(),
-            Message::ModeCommandInsertStr(s) => self.command_mode_insert(s),
-            Message::ModeInsertInsertStr(s) => self.insert_mode_insert(s),
-            Message::ChangeMode(mode) => self.change_mode(mode),
-            Message::ExecuteCommand => self.execute_command(),
-            Message::BufferList => self.buffer_list_command(),
-            Message::EditFile(ref filename) => self.edit_file_command(filename),
-            Message::SwapBuffer(ref arg) => self.swap_buffer_command(arg),
-            Message::CloseCurrentPaneOnKeyPress => self.close_message(),
-            Message::NextAvailableCommand => self.next_available_command(),
-            Message::Resize(size) => {
-                self.size = size;
-                None
-            }
-            Message::Save(filename) => self.save(filename),
-            Message::Quit => {
-                self.is_running = false;
-                None
-            }
-        }
+    fn update(&mut self, message: Self::Message) -> Task<Self::Message> {
+        let next = self.handle(message);
+        self.task(next)
     }
 
     fn cursor_pos(&self) -> Option<Pos> {
         match self.mode {
-            Mode::Command => {
-                let cursor = self.command.get_cursor();
-                let x = cursor.pos.x + 1;
-                let y = cursor.pos.y + self.size.height;
+            Mode::Command | Mode::Search => {
+                let cursor = self.command.screen_cursor();
+                let x = cursor.x + 1;
+                let y = cursor.y + self.size.height;
                 Some(Pos { x, y })
             }
             _ => {
+                let rect = self.window_rect(self.window);
+                let gutter = self.gutter_width(self.window);
                 let buf = self.get_focused_buffer();
-                let cursor = buf.get_cursor();
-                Some(cursor.pos)
+                let Pos { x, y } = match self.wrap(self.window) {
+                    Some(wrap) => buf.wrapped_cursor(&wrap),
+                    None => buf.screen_cursor(),
+                };
+                Some(Pos::new(x + rect.x + gutter, y + rect.y))
             }
         }
     }
     fn cursor_shape(&self) -> Option<SetCursorStyle> {
         match self.mode {
-            Mode::Normal => Some(SetCursorStyle::BlinkingBlock),
-            Mode::Command => Some(SetCursorStyle::BlinkingBar),
+            Mode::Normal | Mode::Visual | Mode::VisualLine | Mode::VisualBlock => {
+                Some(SetCursorStyle::BlinkingBlock)
+            }
+            Mode::Command | Mode::Search => Some(SetCursorStyle::BlinkingBar),
             Mode::Insert => Some(SetCursorStyle::BlinkingBar),
         }
     }
 
     fn subscription(&self) -> Subscription<Self::Message> {
         let close_message = !self.messages.is_empty();
-        Subscription::none().push(move |event| match event {
+        let subscription = if self.key_parse.get_keys().is_empty() {
+            Subscription::none()
+        } else {
+            let timeout = self.option("timeoutlen").as_int() as u64;
+            Subscription::none().after(Duration::from_millis(timeout), Message::MappingTimeout)
+        };
+        subscription.push(move |event| match event {
             Event::Key(k) => {
                 if close_message {
                     return Some(Message::CloseCurrentPaneOnKeyPress);
@@ -595,3 +2600,339 @@ impl App for State {
         self.is_running
     }
 }
+
+#[cfg(test)]
+impl State {
+    /// A state showing `text` on a `size` screen, the cursor at the start.
+    pub fn with_text(text: &str, size: Size) -> Self {
+        let mut buf = Buffer::default();
+        buf.insert(text);
+        Self::with_buffer(buf, size)
+    }
+
+    pub fn with_buffer(buf: Buffer, size: Size) -> Self {
+        let mut state = Self::new(Settings {
+            buffers: vec![buf],
+            config: None,
+        });
+        state.size = size;
+        state.move_cursor_to(0, 0);
+        state
+    }
+
+    /// Handles `message` and everything it leads to.
+    pub fn run(&mut self, message: Message) {
+        let mut next = Some(message);
+        while let Some(message) = next {
+            next = self.handle(message);
+        }
+    }
+
+    /// Types `keys`, written the way mappings write them.
+    pub fn feed(&mut self, keys: &str) {
+        self.press_keys(&string_to_keys(keys));
+    }
+}
+
+#[test]
+fn huge_counts() {
+    let mut state = State::with_text("one two\nthree\n", Size::new(80, 24));
+    let text = |state: &State| state.buffers[0].get_all_text();
+    state.feed("99999999999x");
+    assert_eq!(text(&state), "\nthree\n");
+    state.feed("99999999999j");
+    assert_eq!(state.buffers[0].get_cursor().pos().y, 1);
+    state.feed("99999999999w");
+    state.feed("yy99999999999p");
+    assert_eq!(text(&state), "\nthree\n");
+    assert_eq!(
+        state.messages.last().unwrap().message,
+        "Resulting text too long"
+    );
+}
+
+#[test]
+fn block_change_every_row() {
+    let mut state = State::with_text("abcd\nefgh\ni\nklmn\n", Size::new(80, 24));
+    let text = |state: &State| state.buffers[0].get_all_text();
+    state.feed("l<c-v>jjjlcXY<esc>");
+    // `i` doesn't reach the block and is left alone.
+    assert_eq!(text(&state), "aXYd\neXYh\ni\nkXYn\n");
+    state.feed("u");
+    assert_eq!(text(&state), "abcd\nefgh\ni\nklmn\n");
+}
+
+#[test]
+fn repeat_substitute() {
+    let mut state = State::with_text("a a\nb a\na\n", Size::new(80, 24));
+    let text = |state: &State| state.buffers[0].get_all_text();
+    state.feed(":s<enter>");
+    assert_eq!(
+        state.messages.last().unwrap().message,
+        "No previous substitute"
+    );
+    state.feed(":s/a<enter>");
+    assert_eq!(text(&state), "a a\nb a\na\n");
+    state.feed(":s/a/x/<enter>");
+    assert_eq!(text(&state), "x a\nb a\na\n");
+    // A bare `:s` takes the pattern and the replacement, not the flags.
+    state.feed(":2,3s<enter>");
+    assert_eq!(text(&state), "x a\nb x\nx\n");
+    state.feed(":1s g<enter>");
+    assert_eq!(text(&state), "x x\nb x\nx\n");
+    state.feed("u2G:s/b<enter>");
+    assert_eq!(text(&state), "x a\nx x\nx\n");
+}
+
+#[test]
+fn ex_line_commands() {
+    let mut state = State::with_text("a\nb\nc\nd\n", Size::new(80, 24));
+    let text = |state: &State| state.buffers[0].get_all_text();
+    let row = |state: &State| state.buffers[0].get_cursor().pos().y;
+    state.feed(":1m$<enter>");
+    assert_eq!(text(&state), "b\nc\nd\na\n");
+    state.feed(":2,3t0<enter>");
+    assert_eq!(text(&state), "c\nd\nb\nc\nd\na\n");
+    state.feed(":%normal Ax<enter>");
+    assert_eq!(text(&state), "cx\ndx\nbx\ncx\ndx\nax\n");
+    state.feed(":99<enter>");
+    assert_eq!(row(&state), 5);
+    assert!(state.messages.is_empty());
+    let dir = std::env::temp_dir().join(format!("revi-ranges-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("part.txt").to_string_lossy().into_owned();
+    state.feed(&format!(":2,3w {file}<enter>"));
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "dx\nbx\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn marks_follow_their_lines() {
+    let mut state = State::with_text("a\nb\nc\nd\n", Size::new(80, 24));
+    let text = |state: &State| state.buffers[0].get_all_text();
+    let row = |state: &State| state.buffers[0].get_cursor().pos().y;
+    state.feed("jjmajmbgg");
+    state.feed(":'a,'bd<enter>");
+    assert_eq!(text(&state), "a\nb\n");
+    state.feed("u");
+    // Lines put in above a mark push it down.
+    state.feed("ggmaGmbggyyP");
+    assert_eq!(text(&state), "a\na\nb\nc\nd\n");
+    state.feed("'a");
+    assert_eq!(row(&state), 1);
+    state.feed("ggdd'b");
+    assert_eq!(row(&state), 3);
+    // A mark on a deleted line is gone.
+    state.feed("'add'a");
+    assert_eq!(state.messages.last().unwrap().message, "Mark not set: a");
+}
+
+#[test]
+fn mapping_timeout() {
+    let mut state = State::with_text("one\ntwo\nthree\n", Size::new(80, 24));
+    state.feed(":imap jk <lt>esc><enter>:nmap x dd<enter>:nmap xj dj<enter>");
+    let text = |state: &State| state.buffers[0].get_all_text();
+    state.feed("x");
+    assert_eq!(text(&state), "one\ntwo\nthree\n");
+    let screen = state.view().to_grid().lines();
+    assert!(screen
+        .iter()
+        .any(|line| line.ends_with("x          0/0 0/0")));
+    assert_eq!(
+        state.subscription().next_timer(),
+        Some(Duration::from_millis(1000))
+    );
+    state.run(Message::MappingTimeout);
+    assert_eq!(text(&state), "two\nthree\n");
+    assert!(state.subscription().next_timer().is_none());
+    state.feed("xj");
+    assert_eq!(text(&state), "");
+    // A `j` on its own is typed once the wait for `k` is over.
+    state.feed("ij");
+    state.run(Message::MappingTimeout);
+    assert_eq!(text(&state), "j");
+    assert_eq!(state.mode, Mode::Insert);
+}
+
+#[test]
+fn soft_wrap() {
+    let text = "short\nthe quick brown fox jumps over the lazy dog\nthree\n";
+    let mut state = State::with_text(text, Size::new(20, 6));
+    state.feed(":set wrap lbr sbr=>><enter>");
+    state.feed("jgj");
+    assert_eq!(
+        state.view().to_grid().lines()[..4],
+        [
+            "short               ",
+            "the quick brown fox ",
+            ">>jumps over the    ",
+            ">>lazy dog          ",
+        ]
+    );
+    assert_eq!(state.buffers[0].get_cursor().pos().x, 20);
+    assert_eq!(state.cursor_pos(), Some(Pos::new(2, 2)));
+    state.feed("gjgk$");
+    assert_eq!(state.buffers[0].get_cursor().pos().x, 42);
+    assert_eq!(state.cursor_pos(), Some(Pos::new(9, 3)));
+}
+
+#[test]
+fn line_taller_than_window() {
+    let line = (0..5)
+        .map(|row| row.to_string().repeat(10))
+        .collect::<String>();
+    let mut state = State::with_text(&format!("{line}\nnext\n"), Size::new(10, 5));
+    state.feed(":set wrap<enter>45l");
+    let rows = |state: &State| state.view().to_grid().lines()[..3].to_vec();
+    // The rows of the line above the cursor's scroll off, the cursor stays on screen.
+    assert_eq!(rows(&state), ["2222222222", "3333333333", "4444444444"]);
+    assert_eq!(state.cursor_pos(), Some(Pos::new(5, 2)));
+    state.feed("0");
+    assert_eq!(rows(&state), ["0000000000", "1111111111", "2222222222"]);
+    assert_eq!(state.cursor_pos(), Some(Pos::new(0, 0)));
+    state.feed("$j");
+    assert_eq!(rows(&state)[0], "next      ");
+    assert_eq!(state.cursor_pos(), Some(Pos::new(3, 0)));
+}
+
+#[test]
+fn grapheme_cursor() {
+    // `e` with a combining acute is one grapheme of two chars, `中` takes two columns.
+    let mut state = State::with_text("e\u{301}中x\nabcdef\n", Size::new(20, 6));
+    let col = |state: &State| state.buffers[0].get_cursor().pos().x;
+    let line = state.view().to_grid().line(0);
+    assert_eq!(line, format!("e\u{301}中x{}", " ".repeat(16)));
+    state.feed("l");
+    assert_eq!(col(&state), 2);
+    assert_eq!(state.cursor_pos(), Some(Pos::new(1, 0)));
+    state.feed("l");
+    assert_eq!(col(&state), 3);
+    assert_eq!(state.cursor_pos(), Some(Pos::new(3, 0)));
+    // Up and down keep to the screen column, not the char column.
+    state.feed("j");
+    assert_eq!(col(&state), 3);
+    state.feed("hk");
+    assert_eq!(col(&state), 2);
+    state.feed("x");
+    assert_eq!(state.buffers[0].get_all_text(), "e\u{301}x\nabcdef\n");
+    state.feed("iü中<esc>");
+    assert_eq!(state.buffers[0].get_all_text(), "e\u{301}ü中x\nabcdef\n");
+    state.feed("0ll");
+    assert_eq!(col(&state), 3);
+    assert_eq!(state.cursor_pos(), Some(Pos::new(2, 0)));
+}
+
+#[test]
+fn tabs() {
+    let mut state = State::with_text("\tx\n  y\n", Size::new(20, 6));
+    let text = |state: &State| state.buffers[0].get_all_text();
+    assert_eq!(
+        state.view().to_grid().line(0),
+        format!("{}x{}", " ".repeat(8), " ".repeat(11))
+    );
+    state.feed("l");
+    assert_eq!(state.cursor_pos(), Some(Pos::new(8, 0)));
+    state.feed("j>>");
+    assert_eq!(text(&state), "\tx\n      y\n");
+    state.feed("<lt><lt>");
+    assert_eq!(text(&state), "\tx\n  y\n");
+    // `softtabstop` stops every 4 columns, backspace takes a whole one back.
+    state.feed(":set sts=4<enter>A<tab><tab>");
+    assert_eq!(text(&state), "\tx\n  y     \n");
+    state.feed("<backspace>");
+    assert_eq!(text(&state), "\tx\n  y \n");
+    state.feed("<backspace><esc>");
+    assert_eq!(text(&state), "\tx\n  y\n");
+    // Without `expandtab` a tab is used where it reaches the stop.
+    state.feed(":set noet<enter>i<tab><tab>z<esc>");
+    assert_eq!(text(&state), "\tx\n    \tzy\n");
+    state.feed(":retab! 4<enter>");
+    assert_eq!(text(&state), "\t\tx\n\t\tzy\n");
+    let line = state.view().to_grid().line(1);
+    assert_eq!(line, format!("{}zy{}", " ".repeat(8), " ".repeat(10)));
+}
+
+#[test]
+fn file_encodings() {
+    let dir = std::env::temp_dir().join(format!("revi-encodings-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let latin1 = dir.join("latin1.txt").to_string_lossy().into_owned();
+    let bom = dir.join("bom.txt").to_string_lossy().into_owned();
+    std::fs::write(&latin1, b"caf\xE9\n").unwrap();
+    std::fs::write(&bom, b"\xEF\xBB\xBFhi\n").unwrap();
+    let buf = Buffer::from_path(&latin1, None);
+    let mut state = State::with_buffer(buf, Size::new(200, 6));
+    let flag = |state: &State, name: &str| {
+        let options = &state.buffers[state.focused].options;
+        options.get_or_default(name).as_bool()
+    };
+    // Not UTF-8, so it opens readonly and `:w` leaves the file alone.
+    assert!(flag(&state, "readonly"));
+    state.feed(":w<enter>");
+    assert_eq!(std::fs::read(&latin1).unwrap(), b"caf\xE9\n");
+    state.feed(&format!(":1w {latin1}<enter>"));
+    assert_eq!(std::fs::read(&latin1).unwrap(), b"caf\xE9\n");
+    // The same file by another path is still the buffer's own.
+    let name = dir.file_name().unwrap().to_string_lossy();
+    let other = dir.join("..").join(&*name).join(".").join("latin1.txt");
+    let other = other.to_string_lossy();
+    state.feed(&format!(":w {other}<enter>:1w {other}<enter>"));
+    assert_eq!(std::fs::read(&latin1).unwrap(), b"caf\xE9\n");
+    // Read again into the same buffer, it's writable now.
+    state.feed(":e ++enc=latin1<enter>");
+    assert_eq!(state.buffers.len(), 1);
+    assert_eq!(state.buffers[0].get_all_text(), "café\n");
+    assert!(!flag(&state, "readonly"));
+    state.feed("A!<esc>:w<enter>");
+    assert_eq!(std::fs::read(&latin1).unwrap(), b"caf\xE9!\n");
+    // A char Latin-1 has no byte for stops the write before the file is touched.
+    state.feed("A中<esc>:w<enter>");
+    assert_eq!(std::fs::read(&latin1).unwrap(), b"caf\xE9!\n");
+    state.feed(":e<enter>");
+    assert_eq!(state.buffers[0].get_all_text(), "café!中\n");
+    state.feed(":e!<enter>");
+    assert_eq!(state.buffers[0].get_all_text(), "café!\n");
+    assert_eq!(state.buffers.len(), 1);
+    state.feed(&format!(":e {bom}<enter>"));
+    assert_eq!(state.buffers[state.focused].get_all_text(), "hi\n");
+    assert!(flag(&state, "bomb"));
+    state.feed("x:w<enter>");
+    assert_eq!(std::fs::read(&bom).unwrap(), b"\xEF\xBB\xBFi\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn file_formats() {
+    let dir = std::env::temp_dir().join(format!("revi-formats-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let dos = dir.join("dos.txt").to_string_lossy().into_owned();
+    std::fs::write(&dos, "abc\r\nde\r\n").unwrap();
+    let mut state = State::with_buffer(Buffer::from_path(&dos, None), Size::new(200, 6));
+    // The `\r` isn't part of the line, `$` stops on the last char.
+    assert_eq!(state.buffers[0].get_all_text(), "abc\nde\n");
+    let format = state.buffers[0].options.get_or_default("fileformat");
+    assert_eq!(format.as_str(), "dos");
+    state.feed("$");
+    assert_eq!(state.buffers[0].get_cursor().pos().x, 2);
+    state.feed("x:w<enter>");
+    assert_eq!(std::fs::read_to_string(&dos).unwrap(), "ab\r\nde\r\n");
+    // Another `fileformat` is a change to write, `:e` won't throw it away.
+    state.feed(":set ff=unix<enter>");
+    assert!(state.buffers[0].is_modified());
+    state.feed(":e<enter>");
+    assert_eq!(
+        state.messages.last().unwrap().message,
+        "No write since last change (add ! to override)"
+    );
+    state.feed(":w<enter>");
+    assert_eq!(std::fs::read_to_string(&dos).unwrap(), "ab\nde\n");
+    assert!(!state.buffers[0].is_modified());
+    // Mostly `\r\n` is `dos`, the odd `\n` line is written back with `\r\n` too.
+    std::fs::write(&dos, "ab\r\nc\r\nd\n").unwrap();
+    state.feed(":e<enter>$");
+    assert_eq!(state.buffers[0].get_all_text(), "ab\nc\nd\n");
+    assert_eq!(state.buffers[0].get_cursor().pos().x, 1);
+    state.feed(":w<enter>");
+    assert_eq!(std::fs::read_to_string(&dos).unwrap(), "ab\r\nc\r\nd\r\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,240 @@
+use std::ops::Range;
+
+use regex::{Captures, Regex};
+use ropey::Rope;
+
+use crate::ex::LineRange;
+
+/// `:s/pattern/replacement/flags`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Substitute {
+    pub pattern: String,
+    /// `None` when there's no delimiter after the pattern, the last
+    /// substitute's replacement is used.
+    pub replacement: Option<String>,
+    pub flags: Flags,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// `g`, every match in a line instead of only the first.
+    pub global: bool,
+    /// `c`, asks before each replacement.
+    pub confirm: bool,
+    /// `i`, ignores case.
+    pub ignore_case: bool,
+    /// `n`, only counts the matches.
+    pub count_only: bool,
+}
+
+/// Splits `src` on the first `delim` not escaped with a `\`, an escaped `delim` loses its `\`.
+fn split_delimited(src: &str, delim: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = src.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, next)) if next == delim => part.push(next),
+                Some((_, next)) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => part.push('\\'),
+            },
+            c if c == delim => return (part, Some(&src[i + c.len_utf8()..])),
+            c => part.push(c),
+        }
+    }
+    (part, None)
+}
+
+impl Substitute {
+    /// Parses everything after the `s`, the first char is the delimiter. Without
+    /// one it's only flags, and the last substitute is done again.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let src = src.trim_start();
+        let mut chars = src.chars();
+        let Some(delim) = chars.next() else {
+            return Ok(Self::default());
+        };
+        if delim.is_alphabetic() {
+            return Ok(Self {
+                flags: Flags::parse(src)?,
+                ..Self::default()
+            });
+        }
+        if delim.is_numeric() || delim == '\\' || delim == '"' {
+            return Err(format!("Invalid delimiter: {delim}"));
+        }
+        let (pattern, rest) = split_delimited(chars.as_str(), delim);
+        let (replacement, rest) = match rest {
+            Some(rest) => {
+                let (replacement, rest) = split_delimited(rest, delim);
+                (Some(replacement), rest)
+            }
+            None => (None, None),
+        };
+        Ok(Self {
+            pattern,
+            replacement,
+            flags: Flags::parse(rest.unwrap_or_default())?,
+        })
+    }
+}
+
+impl Flags {
+    fn parse(src: &str) -> Result<Self, String> {
+        let mut flags = Self::default();
+        for flag in src.trim().chars() {
+            match flag {
+                'g' => flags.global = true,
+                'c' => flags.confirm = true,
+                'i' => flags.ignore_case = true,
+                'n' => flags.count_only = true,
+                _ => return Err(format!("Trailing characters: {flag}")),
+            }
+        }
+        Ok(flags)
+    }
+}
+
+/// Builds the text a match is replaced with, `&` and `\0` are the whole match and
+/// `\1` to `\9` its groups.
+pub fn expand(replacement: &str, caps: &Captures) -> String {
+    let group = |i: usize| caps.get(i).map_or("", |m| m.as_str());
+    let mut text = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => text.push_str(group(0)),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => text.push_str(group(d as usize - '0' as usize)),
+                Some('n' | 'r') => text.push('\n'),
+                Some('t') => text.push('\t'),
+                Some(other) => text.push(other),
+                None => text.push('\\'),
+            },
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// A replacement waiting for the `c` flag's answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replace {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// Every replacement `:s` makes in `rows`, in buffer order.
+pub fn replacements(
+    rope: &Rope,
+    regex: &Regex,
+    rows: LineRange,
+    replacement: &str,
+    global: bool,
+) -> Vec<Replace> {
+    let mut found = Vec::new();
+    for row in rows.start..=rows.end.min(rope.len_lines().saturating_sub(1)) {
+        let line = rope.line(row).to_string();
+        let line = line.trim_end_matches(['\n', '\r']);
+        let start = rope.line_to_char(row);
+        let col = |byte: usize| start + line[..byte].chars().count();
+        for caps in regex
+            .captures_iter(line)
+            .take(if global { usize::MAX } else { 1 })
+        {
+            let Some(m) = caps.get(0) else {
+                continue;
+            };
+            found.push(Replace {
+                range: col(m.start())..col(m.end()),
+                text: expand(replacement, &caps),
+            });
+        }
+    }
+    found
+}
+
+/// Matches of a `:s///c` still to be answered, nothing is changed until it's done.
+#[derive(Debug, Clone, Default)]
+pub struct Confirm {
+    pub pending: Vec<Replace>,
+    pub accepted: Vec<Replace>,
+    pub current: usize,
+}
+
+impl Confirm {
+    pub fn current(&self) -> Option<&Replace> {
+        self.pending.get(self.current)
+    }
+
+    pub fn accept(&mut self) {
+        if let Some(replace) = self.pending.get(self.current) {
+            self.accepted.push(replace.clone());
+        }
+        self.current += 1;
+    }
+
+    pub fn skip(&mut self) {
+        self.current += 1;
+    }
+
+    pub fn accept_all(&mut self) {
+        while self.current().is_some() {
+            self.accept();
+        }
+    }
+
+    /// Leaves every match not answered yet alone.
+    pub fn stop(&mut self) {
+        self.current = self.pending.len();
+    }
+}
+
+#[test]
+fn parse_substitute() {
+    let sub = Substitute::parse("/foo\\/bar/baz/gi").unwrap();
+    assert_eq!(sub.pattern, "foo/bar");
+    assert_eq!(sub.replacement.as_deref(), Some("baz"));
+    assert!(sub.flags.global && sub.flags.ignore_case);
+    assert!(!sub.flags.confirm && !sub.flags.count_only);
+    let sub = Substitute::parse("#a#b").unwrap();
+    assert_eq!(
+        (sub.pattern.as_str(), sub.replacement.as_deref()),
+        ("a", Some("b"))
+    );
+    assert_eq!(
+        Substitute::parse("/a/").unwrap().replacement.as_deref(),
+        Some("")
+    );
+    assert_eq!(Substitute::parse("/a").unwrap().replacement, None);
+    assert!(Substitute::parse("/a/b/x").is_err());
+    let sub = Substitute::parse("g").unwrap();
+    assert_eq!((sub.pattern.as_str(), sub.replacement), ("", None));
+    assert!(sub.flags.global);
+    assert!(Substitute::parse("x").is_err());
+}
+
+#[test]
+fn replace_in_rows() {
+    let rope = Rope::from_str("a a\na a\na a\n");
+    let regex = Regex::new("a").unwrap();
+    let rows = LineRange { start: 1, end: 2 };
+    let found = replacements(&rope, &regex, rows, "b", false);
+    assert_eq!(
+        found.iter().map(|r| r.range.start).collect::<Vec<_>>(),
+        [4, 8]
+    );
+    let found = replacements(&rope, &regex, rows, "b", true);
+    assert_eq!(found.len(), 4);
+}
+
+#[test]
+fn expand_captures() {
+    let regex = regex::Regex::new(r"(\w+)=(\w+)").unwrap();
+    let caps = regex.captures("key=value").unwrap();
+    assert_eq!(expand(r"\2=\1", &caps), "value=key");
+    assert_eq!(expand(r"[&] \&", &caps), "[key=value] &");
+}
@@ -0,0 +1,245 @@
+use crate::buffer::Cursor;
+use ropey::Rope;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    Insert { at: usize, text: String },
+    Remove { at: usize, text: String },
+}
+
+impl Edit {
+    pub fn apply(&self, rope: &mut Rope) {
+        match self {
+            Self::Insert { at, text } => rope.insert(*at, text),
+            Self::Remove { at, text } => rope.remove(*at..*at + text.chars().count()),
+        }
+    }
+
+    #[must_use]
+    pub fn invert(&self) -> Self {
+        match self {
+            Self::Insert { at, text } => Self::Remove {
+                at: *at,
+                text: text.clone(),
+            },
+            Self::Remove { at, text } => Self::Insert {
+                at: *at,
+                text: text.clone(),
+            },
+        }
+    }
+
+    /// Folds `other` into `self` when it continues typing right where `self` left off.
+    fn merge(&mut self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Insert { at, text },
+                Self::Insert {
+                    at: next,
+                    text: more,
+                },
+            ) if *at + text.chars().count() == *next => {
+                text.push_str(more);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// One undoable step, everything between two commits.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub edits: Vec<Edit>,
+    pub before: Cursor,
+    pub after: Cursor,
+}
+
+impl Change {
+    fn new(before: Cursor) -> Self {
+        Self {
+            edits: Vec::new(),
+            before,
+            after: before,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    parent: usize,
+    change: Change,
+    // Child that redo will walk back into, the most recently made or undone one.
+    next: Option<usize>,
+}
+
+/// Undo tree for a single buffer.
+///
+/// Node `0` is the root and holds no change.  Undoing walks up towards the root
+/// and redoing walks back down the branch that was last visited, so making a new
+/// edit after an undo starts a new branch instead of throwing the old one away.
+#[derive(Debug, Clone)]
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    pending: Option<Change>,
+    // Open groups, they nest so a group can hold a trip through insert mode.
+    grouping: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: 0,
+                change: Change::new(Cursor::default()),
+                next: None,
+            }],
+            current: 0,
+            pending: None,
+            grouping: 0,
+        }
+    }
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit, before: Cursor) {
+        let change = self.pending.get_or_insert_with(|| Change::new(before));
+        if let Some(last) = change.edits.last_mut() {
+            if last.merge(&edit) {
+                return;
+            }
+        }
+        change.edits.push(edit);
+    }
+
+    /// Keeps every edit recorded until [`History::end_group`] in one step.
+    pub fn begin_group(&mut self) {
+        self.grouping += 1;
+    }
+
+    pub fn end_group(&mut self, after: Cursor) {
+        self.grouping = self.grouping.saturating_sub(1);
+        self.commit(after);
+    }
+
+    /// Closes the pending step unless a group is open.
+    pub fn commit(&mut self, after: Cursor) {
+        if self.grouping > 0 {
+            return;
+        }
+        let Some(mut change) = self.pending.take() else {
+            return;
+        };
+        if change.edits.is_empty() {
+            return;
+        }
+        change.after = after;
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            parent: self.current,
+            change,
+            next: None,
+        });
+        self.nodes[self.current].next = Some(id);
+        self.current = id;
+    }
+
+    /// The step the buffer is at, the same one again means the text is too.
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    /// Whether there are edits not yet closed into a step.
+    pub fn has_pending(&self) -> bool {
+        self.pending
+            .as_ref()
+            .is_some_and(|change| !change.edits.is_empty())
+    }
+
+    pub fn undo(&mut self) -> Option<&Change> {
+        if self.current == 0 {
+            return None;
+        }
+        let id = self.current;
+        let parent = self.nodes[id].parent;
+        self.nodes[parent].next = Some(id);
+        self.current = parent;
+        Some(&self.nodes[id].change)
+    }
+
+    pub fn redo(&mut self) -> Option<&Change> {
+        let id = self.nodes[self.current].next?;
+        self.current = id;
+        Some(&self.nodes[id].change)
+    }
+}
+
+#[test]
+fn undo_redo_round_trip() {
+    let mut rope = Rope::from_str("hello\n");
+    let mut history = History::default();
+    let edit = Edit::Insert {
+        at: 5,
+        text: " world".into(),
+    };
+    edit.apply(&mut rope);
+    history.record(edit, Cursor::default());
+    history.commit(Cursor::default());
+
+    let change = history.undo().unwrap();
+    change
+        .edits
+        .iter()
+        .rev()
+        .for_each(|e| e.invert().apply(&mut rope));
+    assert_eq!(rope.to_string(), "hello\n");
+    assert!(history.undo().is_none());
+
+    let change = history.redo().unwrap();
+    change.edits.iter().for_each(|e| e.apply(&mut rope));
+    assert_eq!(rope.to_string(), "hello world\n");
+    assert!(history.redo().is_none());
+}
+
+#[test]
+fn grouped_edits_are_one_step() {
+    let mut history = History::default();
+    history.begin_group();
+    for (at, c) in "abc".chars().enumerate() {
+        history.record(
+            Edit::Insert {
+                at,
+                text: c.to_string(),
+            },
+            Cursor::default(),
+        );
+        history.commit(Cursor::default());
+    }
+    history.end_group(Cursor::default());
+    let change = history.undo().unwrap();
+    assert_eq!(
+        change.edits,
+        vec![Edit::Insert {
+            at: 0,
+            text: "abc".into()
+        }]
+    );
+    assert!(history.undo().is_none());
+}
+
+#[test]
+fn new_edit_after_undo_branches() {
+    let mut history = History::default();
+    let insert = |at: usize, text: &str| Edit::Insert {
+        at,
+        text: text.into(),
+    };
+    history.record(insert(0, "a"), Cursor::default());
+    history.commit(Cursor::default());
+    history.undo();
+    history.record(insert(0, "b"), Cursor::default());
+    history.commit(Cursor::default());
+    assert_eq!(history.undo().unwrap().edits, vec![insert(0, "b")]);
+    assert_eq!(history.redo().unwrap().edits, vec![insert(0, "b")]);
+}
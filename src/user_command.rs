@@ -0,0 +1,190 @@
+use std::str::FromStr;
+
+use rhai::{Dynamic, FnPtr, Map};
+
+/// How many arguments a user command takes, Vim's `-nargs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NArgs {
+    /// `0`
+    None,
+    /// `1`, spaces and all.
+    One,
+    /// `?`, none or one.
+    Optional,
+    /// `*`, any number split on white space.
+    Any,
+    /// `+`, at least one.
+    Some,
+}
+
+impl FromStr for NArgs {
+    type Err = String;
+
+    fn from_str(nargs: &str) -> Result<Self, Self::Err> {
+        match nargs {
+            "0" => Ok(Self::None),
+            "1" => Ok(Self::One),
+            "?" => Ok(Self::Optional),
+            "*" => Ok(Self::Any),
+            "+" => Ok(Self::Some),
+            _ => Err(format!("Invalid nargs: {nargs}")),
+        }
+    }
+}
+
+/// What `<tab>` offers for a user command's arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Complete {
+    Nothing,
+    File,
+    Buffer,
+    Option,
+    Command,
+    /// The words of an array.
+    List(Vec<String>),
+}
+
+impl Complete {
+    fn from_dynamic(value: Dynamic) -> Result<Self, String> {
+        if value.is_array() {
+            let words = value
+                .into_array()?
+                .into_iter()
+                .map(|word| word.into_string())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|ty| format!("complete list holds a {ty}, not a string"))?;
+            return Ok(Self::List(words));
+        }
+        match value.into_string()?.as_str() {
+            "file" => Ok(Self::File),
+            "buffer" => Ok(Self::Buffer),
+            "option" => Ok(Self::Option),
+            "command" => Ok(Self::Command),
+            kind => Err(format!("Invalid complete: {kind}")),
+        }
+    }
+}
+
+/// Lines a user command works on when no range is typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeDefault {
+    /// `false`, a range is an error.
+    NotAllowed,
+    /// `true`, the cursor's line.
+    Line,
+    /// `"%"`, the whole buffer.
+    All,
+}
+
+/// An ex command a script made with `revi.command`.
+#[derive(Debug, Clone)]
+pub struct UserCommand {
+    pub func: FnPtr,
+    pub nargs: NArgs,
+    pub complete: Complete,
+    /// Whether `:Name!` is allowed.
+    pub bang: bool,
+    pub range: RangeDefault,
+    /// Commands from `revi.export_command` get the rest of the line as it is,
+    /// or nothing, instead of the map the others get.
+    pub raw: bool,
+}
+
+impl UserCommand {
+    pub fn raw(func: FnPtr) -> Self {
+        Self {
+            func,
+            nargs: NArgs::Any,
+            complete: Complete::Nothing,
+            bang: false,
+            range: RangeDefault::NotAllowed,
+            raw: true,
+        }
+    }
+
+    /// Reads `#{nargs, complete, bang, range}`, every key is optional.
+    pub fn new(func: FnPtr, opts: Map) -> Result<Self, String> {
+        let mut command = Self {
+            raw: false,
+            nargs: NArgs::None,
+            ..Self::raw(func)
+        };
+        for (key, value) in opts {
+            match key.as_str() {
+                "nargs" => {
+                    let nargs = match value.as_int() {
+                        Ok(int) => int.to_string(),
+                        Err(_) => value.into_string()?,
+                    };
+                    command.nargs = nargs.parse()?;
+                }
+                "complete" => command.complete = Complete::from_dynamic(value)?,
+                "bang" => command.bang = value.as_bool()?,
+                "range" => {
+                    command.range = match (value.as_bool(), value.into_string().as_deref()) {
+                        (Ok(false), _) => RangeDefault::NotAllowed,
+                        (Ok(true), _) => RangeDefault::Line,
+                        (_, Ok("%")) => RangeDefault::All,
+                        _ => return Err("range is true, false or \"%\"".into()),
+                    }
+                }
+                key => return Err(format!("Unknown command option: {key}")),
+            }
+        }
+        Ok(command)
+    }
+
+    /// Splits what was typed after the command into its arguments.
+    pub fn args(&self, tail: &str) -> Result<Vec<String>, String> {
+        let tail = tail.trim();
+        let words = tail
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        match self.nargs {
+            NArgs::None if !tail.is_empty() => Err(format!("Trailing characters: {tail}")),
+            NArgs::None => Ok(Vec::new()),
+            NArgs::One | NArgs::Some if tail.is_empty() => Err("Argument required".into()),
+            NArgs::One | NArgs::Optional if tail.is_empty() => Ok(Vec::new()),
+            NArgs::One | NArgs::Optional => Ok(vec![tail.to_string()]),
+            NArgs::Any | NArgs::Some => Ok(words),
+        }
+    }
+}
+
+/// User command names start with a capital so they can't shadow the built in ones.
+pub fn valid_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_uppercase() && chars.all(|c| c.is_ascii_alphabetic()) => {
+            Ok(())
+        }
+        _ => Err(format!(
+            "User command names are letters starting with a capital: {name}"
+        )),
+    }
+}
+
+#[test]
+fn command_args() {
+    let func = FnPtr::new("f").unwrap();
+    let mut opts = Map::new();
+    opts.insert("nargs".into(), "+".into());
+    opts.insert("complete".into(), "file".into());
+    opts.insert("bang".into(), true.into());
+    let command = UserCommand::new(func.clone(), opts).unwrap();
+    assert_eq!(command.complete, Complete::File);
+    assert!(command.bang);
+    assert_eq!(command.args(" a  b "), Ok(vec!["a".into(), "b".into()]));
+    assert!(command.args("").is_err());
+
+    let mut opts = Map::new();
+    opts.insert("nargs".into(), Dynamic::from_int(1));
+    let command = UserCommand::new(func.clone(), opts).unwrap();
+    assert_eq!(command.args("a b"), Ok(vec!["a b".into()]));
+
+    let command = UserCommand::new(func, Map::new()).unwrap();
+    assert!(command.args("x").is_err());
+    assert!(valid_name("Format").is_ok());
+    assert!(valid_name("format").is_err());
+}
@@ -0,0 +1,403 @@
+use revi_ui::layout::{Pos, Rect, Size, Stack};
+
+use crate::buffer::Cursor;
+use crate::options::LocalOptions;
+
+/// A view into a buffer, windows showing the same buffer keep their own cursors.
+///
+/// The focused window's cursor lives in its buffer while it has focus and is
+/// copied back here when focus moves on.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub buffer: usize,
+    pub cursor: Cursor,
+    /// Window local options, `number` and `wrap`.
+    pub options: LocalOptions,
+}
+
+impl Window {
+    pub fn new(buffer: usize, cursor: Cursor, options: LocalOptions) -> Self {
+        Self {
+            buffer,
+            cursor,
+            options,
+        }
+    }
+}
+
+/// Numbers in the gutter left of a window's text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineNumbers {
+    Off,
+    /// `:set number`
+    Absolute,
+    /// `:set relativenumber`, how far each line is from the cursor's.
+    Relative,
+    /// Both set, the cursor's line shows its own number.
+    Hybrid,
+}
+
+impl LineNumbers {
+    /// From the `number` and `relativenumber` options.
+    pub fn from_flags(number: bool, relative: bool) -> Self {
+        match (number, relative) {
+            (false, false) => Self::Off,
+            (true, false) => Self::Absolute,
+            (false, true) => Self::Relative,
+            (true, true) => Self::Hybrid,
+        }
+    }
+
+    /// Columns the gutter takes for a buffer of `lines` lines, the last one is a space.
+    pub fn width(self, lines: usize) -> u16 {
+        if self == Self::Off {
+            return 0;
+        }
+        let digits = lines.max(1).ilog10() as u16 + 1;
+        digits.max(3) + 1
+    }
+
+    /// What the gutter shows next to `row` when the cursor is on `cursor_row`.
+    pub fn label(self, row: usize, cursor_row: usize, width: u16) -> String {
+        let digits = width.saturating_sub(1) as usize;
+        match self {
+            Self::Off => String::new(),
+            Self::Absolute => format!("{:>digits$} ", row + 1),
+            Self::Hybrid if row == cursor_row => format!("{:<digits$} ", row + 1),
+            Self::Relative | Self::Hybrid => format!("{:>digits$} ", row.abs_diff(cursor_row)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+/// How windows split the screen, leaves are indexes into `State::windows`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    Window(usize),
+    /// `Stack::Vertically` is `:split`, windows on top of each other.
+    Split {
+        stack: Stack,
+        children: Vec<Layout>,
+        /// Share of the split each child gets.
+        weights: Vec<f32>,
+    },
+}
+
+/// Shares `len` out by `weights`, the last one takes what rounding leaves over.
+fn lengths(weights: &[f32], len: u16) -> Vec<u16> {
+    let total = weights.iter().sum::<f32>().max(f32::EPSILON);
+    let mut left = len;
+    weights
+        .iter()
+        .enumerate()
+        .map(|(i, weight)| {
+            let share = if i + 1 == weights.len() {
+                left
+            } else {
+                ((len as f32 * weight / total) as u16).min(left)
+            };
+            left -= share;
+            share
+        })
+        .collect()
+}
+
+fn child_rects(rect: Rect, stack: Stack, weights: &[f32]) -> Vec<Rect> {
+    let len = match stack {
+        Stack::Vertically => rect.height,
+        Stack::Horizontally => rect.width,
+    };
+    let mut offset = 0;
+    lengths(weights, len)
+        .into_iter()
+        .map(|len| {
+            let child = match stack {
+                Stack::Vertically => Rect::with_position(
+                    Pos::new(rect.x, rect.y + offset),
+                    Size::new(rect.width, len),
+                ),
+                Stack::Horizontally => Rect::with_position(
+                    Pos::new(rect.x + offset, rect.y),
+                    Size::new(len, rect.height),
+                ),
+            };
+            offset += len;
+            child
+        })
+        .collect()
+}
+
+impl Layout {
+    /// Sizes of this node's children when it's given `size`.
+    pub fn child_sizes(&self, size: Size) -> Vec<Size> {
+        match self {
+            Self::Window(_) => Vec::new(),
+            Self::Split { stack, weights, .. } => child_rects(Rect::new(size), *stack, weights)
+                .into_iter()
+                .map(|rect| Size::new(rect.width, rect.height))
+                .collect(),
+        }
+    }
+
+    /// Where every window goes inside `rect`, in layout order.
+    pub fn rects(&self, rect: Rect) -> Vec<(usize, Rect)> {
+        match self {
+            Self::Window(id) => vec![(*id, rect)],
+            Self::Split {
+                stack,
+                children,
+                weights,
+            } => children
+                .iter()
+                .zip(child_rects(rect, *stack, weights))
+                .flat_map(|(child, rect)| child.rects(rect))
+                .collect(),
+        }
+    }
+
+    pub fn windows(&self) -> Vec<usize> {
+        self.rects(Rect::default())
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    fn contains(&self, id: usize) -> bool {
+        match self {
+            Self::Window(window) => *window == id,
+            Self::Split { children, .. } => children.iter().any(|child| child.contains(id)),
+        }
+    }
+
+    /// Splits window `target` in half, `new` goes above or to the left of it.
+    pub fn split(&mut self, target: usize, new: usize, stack: Stack) {
+        match self {
+            Self::Window(id) if *id == target => {
+                *self = Self::Split {
+                    stack,
+                    children: vec![Self::Window(new), Self::Window(target)],
+                    weights: vec![1.0, 1.0],
+                };
+            }
+            Self::Window(_) => {}
+            Self::Split {
+                stack: split_stack,
+                children,
+                weights,
+            } => {
+                let Some(i) = children.iter().position(|child| child.contains(target)) else {
+                    return;
+                };
+                if *split_stack == stack && matches!(children[i], Self::Window(_)) {
+                    let half = weights[i] / 2.0;
+                    weights[i] = half;
+                    weights.insert(i, half);
+                    children.insert(i, Self::Window(new));
+                    return;
+                }
+                children[i].split(target, new, stack);
+            }
+        }
+    }
+
+    /// Takes window `target` out, windows after it move down an index.
+    pub fn remove(&mut self, target: usize) {
+        self.take_out(target);
+        self.renumber(target);
+    }
+
+    fn take_out(&mut self, target: usize) {
+        if let Self::Split {
+            children, weights, ..
+        } = self
+        {
+            if let Some(i) = children
+                .iter()
+                .position(|child| matches!(child, Self::Window(id) if *id == target))
+            {
+                children.remove(i);
+                weights.remove(i);
+            } else {
+                children.iter_mut().for_each(|child| child.take_out(target));
+            }
+            if children.len() == 1 {
+                *self = children.remove(0);
+            }
+        }
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Self::Window(id) if *id > removed => *id -= 1,
+            Self::Window(_) => {}
+            Self::Split { children, .. } => {
+                children
+                    .iter_mut()
+                    .for_each(|child| child.renumber(removed));
+            }
+        }
+    }
+
+    /// `<C-w>=`, every window in a split gets the same share.
+    pub fn equalize(&mut self) {
+        if let Self::Split {
+            children, weights, ..
+        } = self
+        {
+            weights.iter_mut().for_each(|weight| *weight = 1.0);
+            children.iter_mut().for_each(Self::equalize);
+        }
+    }
+
+    /// Grows window `target` by `delta` rows, or columns for `Stack::Horizontally`,
+    /// taking them from its neighbour in the closest split going that way.
+    pub fn resize(&mut self, target: usize, stack: Stack, delta: i32, rect: Rect) -> bool {
+        let Self::Split {
+            stack: split_stack,
+            children,
+            weights,
+        } = self
+        else {
+            return false;
+        };
+        let rects = child_rects(rect, *split_stack, weights);
+        let Some(i) = children.iter().position(|child| child.contains(target)) else {
+            return false;
+        };
+        if children[i].resize(target, stack, delta, rects[i]) {
+            return true;
+        }
+        if *split_stack != stack || children.len() < 2 {
+            return false;
+        }
+        let mut lens = rects
+            .iter()
+            .map(|rect| match stack {
+                Stack::Vertically => rect.height as i32,
+                Stack::Horizontally => rect.width as i32,
+            })
+            .collect::<Vec<_>>();
+        let other = if i + 1 < lens.len() { i + 1 } else { i - 1 };
+        let delta = delta.clamp(1 - lens[i], lens[other] - 1);
+        lens[i] += delta;
+        lens[other] -= delta;
+        *weights = lens.into_iter().map(|len| len as f32).collect();
+        true
+    }
+}
+
+/// Window next to `from` going `direction`, the one closest to the cursor's row or column.
+pub fn neighbour(
+    rects: &[(usize, Rect)],
+    from: usize,
+    cursor: Pos,
+    direction: Direction,
+) -> Option<usize> {
+    let (_, current) = rects.iter().find(|(id, _)| *id == from)?;
+    let overlaps = |a: u16, a_len: u16, b: u16, b_len: u16| a < b + b_len && b < a + a_len;
+    rects
+        .iter()
+        .filter(|(id, rect)| {
+            *id != from
+                && match direction {
+                    Direction::Left => {
+                        rect.x + rect.width <= current.x
+                            && overlaps(rect.y, rect.height, current.y, current.height)
+                    }
+                    Direction::Right => {
+                        rect.x >= current.x + current.width
+                            && overlaps(rect.y, rect.height, current.y, current.height)
+                    }
+                    Direction::Up => {
+                        rect.y + rect.height <= current.y
+                            && overlaps(rect.x, rect.width, current.x, current.width)
+                    }
+                    Direction::Down => {
+                        rect.y >= current.y + current.height
+                            && overlaps(rect.x, rect.width, current.x, current.width)
+                    }
+                }
+        })
+        .min_by_key(|(_, rect)| {
+            let gap = match direction {
+                Direction::Left => current.x - (rect.x + rect.width),
+                Direction::Right => rect.x - (current.x + current.width),
+                Direction::Up => current.y - (rect.y + rect.height),
+                Direction::Down => rect.y - (current.y + current.height),
+            };
+            let along = match direction {
+                Direction::Left | Direction::Right => (rect.y as i32 - cursor.y as i32)
+                    .max(cursor.y as i32 - (rect.y + rect.height) as i32 + 1),
+                Direction::Up | Direction::Down => (rect.x as i32 - cursor.x as i32)
+                    .max(cursor.x as i32 - (rect.x + rect.width) as i32 + 1),
+            };
+            (gap, along.max(0))
+        })
+        .map(|(id, _)| *id)
+}
+
+#[test]
+fn split_and_remove() {
+    let mut layout = Layout::Window(0);
+    layout.split(0, 1, Stack::Vertically);
+    layout.split(1, 2, Stack::Vertically);
+    layout.split(0, 3, Stack::Horizontally);
+    assert_eq!(layout.windows(), vec![2, 1, 3, 0]);
+    let rects = layout.rects(Rect::new(Size::new(80, 24)));
+    let heights = rects.iter().map(|(_, r)| r.height).collect::<Vec<_>>();
+    assert_eq!(heights, vec![6, 6, 12, 12]);
+    assert_eq!(rects[2].1.width, 40);
+
+    let cursor = Pos::new(50, 20);
+    assert_eq!(neighbour(&rects, 0, cursor, Direction::Left), Some(3));
+    assert_eq!(neighbour(&rects, 3, cursor, Direction::Up), Some(1));
+    assert_eq!(neighbour(&rects, 2, cursor, Direction::Down), Some(1));
+    assert_eq!(neighbour(&rects, 2, cursor, Direction::Up), None);
+
+    layout.remove(0);
+    assert_eq!(layout.windows(), vec![1, 0, 2]);
+    layout.remove(1);
+    assert_eq!(layout.windows(), vec![0, 1]);
+    layout.remove(0);
+    assert!(matches!(layout, Layout::Window(0)));
+}
+
+#[test]
+fn resize_takes_from_neighbour() {
+    let mut layout = Layout::Window(0);
+    layout.split(0, 1, Stack::Vertically);
+    let rect = Rect::new(Size::new(80, 20));
+    assert!(layout.resize(1, Stack::Vertically, 3, rect));
+    let heights = |layout: &Layout| {
+        layout
+            .rects(rect)
+            .iter()
+            .map(|(_, r)| r.height)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(heights(&layout), vec![13, 7]);
+    assert!(!layout.resize(1, Stack::Horizontally, 3, rect));
+    layout.equalize();
+    assert_eq!(heights(&layout), vec![10, 10]);
+}
+
+#[test]
+fn line_number_labels() {
+    let numbers = LineNumbers::from_flags(true, false);
+    assert_eq!(numbers.width(42), 4);
+    assert_eq!(numbers.width(12345), 6);
+    assert_eq!(numbers.label(9, 3, 4), " 10 ");
+    let numbers = LineNumbers::from_flags(true, true);
+    assert_eq!(numbers.label(9, 3, 4), "  6 ");
+    assert_eq!(numbers.label(3, 3, 4), "4   ");
+    let numbers = LineNumbers::from_flags(false, true);
+    assert_eq!(numbers.label(3, 3, 4), "  0 ");
+    assert_eq!(LineNumbers::Off.width(42), 0);
+}
@@ -0,0 +1,148 @@
+use std::ops::Range;
+
+use revi_ui::grid::width;
+
+use crate::grapheme;
+
+/// Where `linebreak` may break a line, the chars of Vim's default `breakat`.
+const BREAKAT: &str = " \t!@*-+;:,./?";
+
+/// How a window with `wrap` set lays its lines out, from its `linebreak` and
+/// `showbreak` options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wrap {
+    /// Columns of the text area.
+    pub width: usize,
+    pub linebreak: bool,
+    /// Shown at the start of every row a line continues on.
+    pub showbreak: String,
+    pub tabstop: usize,
+}
+
+/// One row of a window, the columns of buffer line `row` it shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenLine {
+    pub row: usize,
+    pub cols: Range<usize>,
+    /// Whether the line starts on this row, the rest get `showbreak`.
+    pub first: bool,
+}
+
+impl Wrap {
+    /// Columns a row starting with `showbreak` has left for text.
+    fn room(&self, first: bool) -> usize {
+        if first {
+            return self.width.max(1);
+        }
+        self.width.saturating_sub(width(&self.showbreak)).max(1)
+    }
+
+    /// The columns of `line` each row shows, an empty line still takes one row.
+    pub fn segments(&self, line: &str) -> Vec<Range<usize>> {
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut segments = Vec::new();
+        let mut start = 0;
+        loop {
+            let room = self.room(segments.is_empty());
+            let mut end = grapheme::fit(line, start, room, self.tabstop);
+            if end >= chars.len() {
+                segments.push(start..chars.len());
+                return segments;
+            }
+            // A grapheme wider than the row still gets one to itself.
+            if end == start {
+                end = grapheme::next(line, start);
+            }
+            if self.linebreak {
+                // Break after the last `breakat` char that fits, words stay whole.
+                if let Some(after) = (start + 1..=end)
+                    .rev()
+                    .find(|idx| BREAKAT.contains(chars[idx - 1]))
+                {
+                    end = after;
+                }
+            }
+            segments.push(start..end);
+            start = end;
+        }
+    }
+
+    /// Rows `line` takes on screen.
+    pub fn rows(&self, line: &str) -> usize {
+        self.segments(line).len()
+    }
+
+    /// Screen columns `showbreak` takes on the `idx`th row of a line.
+    fn prefix(&self, idx: usize) -> usize {
+        if idx == 0 {
+            0
+        } else {
+            width(&self.showbreak)
+        }
+    }
+
+    /// The row of `line` column `col` is on and how far along the screen it is,
+    /// `showbreak` included.
+    pub fn locate(&self, line: &str, col: usize) -> (usize, usize) {
+        let segments = self.segments(line);
+        let idx = segments
+            .iter()
+            .position(|segment| col < segment.end)
+            .unwrap_or(segments.len() - 1);
+        let x = grapheme::display_col(line, col, self.tabstop).saturating_sub(
+            grapheme::display_col(line, segments[idx].start, self.tabstop),
+        ) + self.prefix(idx);
+        (idx, x.min(self.width.saturating_sub(1)))
+    }
+
+    /// Column of `line` shown `x` columns along its `idx`th row, `line_len` is
+    /// the last column the cursor may take.
+    pub fn col_at(&self, line: &str, idx: usize, x: usize, line_len: usize) -> usize {
+        let segments = self.segments(line);
+        let idx = idx.min(segments.len() - 1);
+        let segment = &segments[idx];
+        let x = grapheme::display_col(line, segment.start, self.tabstop)
+            + x.saturating_sub(self.prefix(idx));
+        let last = grapheme::prev(line, segment.end).max(segment.start);
+        grapheme::col_at(line, x, self.tabstop)
+            .min(last)
+            .min(line_len)
+    }
+}
+
+#[test]
+fn wrapping() {
+    let mut wrap = Wrap {
+        width: 10,
+        linebreak: false,
+        showbreak: String::new(),
+        tabstop: 8,
+    };
+    assert_eq!(wrap.segments(""), vec![0..0]);
+    assert_eq!(wrap.segments("0123456789"), vec![0..10]);
+    assert_eq!(wrap.segments("the quick brown fox"), vec![0..10, 10..19]);
+    wrap.linebreak = true;
+    assert_eq!(wrap.segments("the quick brown fox"), vec![0..10, 10..19]);
+    assert_eq!(
+        wrap.segments("the quicker brown"),
+        vec![0..4, 4..12, 12..17]
+    );
+    assert_eq!(wrap.segments("abcdefghijklm"), vec![0..10, 10..13]);
+
+    wrap.showbreak = "> ".into();
+    assert_eq!(
+        wrap.segments("abcdefghijklmnopqrs"),
+        vec![0..10, 10..18, 18..19]
+    );
+    assert_eq!(wrap.locate("abcdefghijklmnopqrs", 12), (1, 4));
+    assert_eq!(wrap.locate("abcdefghijklmnopqrs", 3), (0, 3));
+    assert_eq!(wrap.col_at("abcdefghijklmnopqrs", 1, 4, 18), 12);
+    assert_eq!(wrap.col_at("abcdefghijklmnopqrs", 2, 9, 18), 18);
+
+    // Wide graphemes don't get split across rows.
+    wrap.showbreak.clear();
+    assert_eq!(wrap.segments("一二三四五六"), vec![0..5, 5..6]);
+    assert_eq!(wrap.segments("abc一二三四五六"), vec![0..6, 6..9]);
+    assert_eq!(wrap.locate("abc一二三四五六", 4), (0, 5));
+    assert_eq!(wrap.col_at("abc一二三四五六", 1, 3, 8), 7);
+}